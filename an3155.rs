@@ -0,0 +1,254 @@
+//! Target side of the ST serial bootloader protocol (AN3155), so tools like stm32flash can
+//! update a custom bootloader over a UART.
+//!
+//! After the host's `0x7f` sync byte every command is the command byte and its complement,
+//! answered with `ACK` or `NACK`. Addresses come as 4 big endian bytes and their XOR. Supported
+//! are GET, GET VERSION, GET ID, READ MEMORY, GO, WRITE MEMORY, ERASE and EXTENDED ERASE. The
+//! read and write protection commands are refused with `NACK`.
+//!
+//! Writes, erases and GO are limited to the application region, which keeps the bootloader
+//! itself out of reach. A global erase erases the application region only. Reads cover all of
+//! main flash. Writes go straight to flash, so the pages have to be erased first, as the tools
+//! do anyway.
+
+use crate::{
+    flash_end, ByteTransport, Error, FlashPage, Read, Region, UnlockedFlash, WriteErase,
+    FLASH_START,
+};
+
+const SYNC: u8 = 0x7f;
+pub(crate) const ACK: u8 = 0x79;
+pub(crate) const NACK: u8 = 0x1f;
+/// Protocol version reported by GET, the one of the F0 ROM bootloader
+pub(crate) const VERSION: u8 = 0x31;
+
+pub(crate) const GET: u8 = 0x00;
+pub(crate) const GET_VERSION: u8 = 0x01;
+pub(crate) const GET_ID: u8 = 0x02;
+pub(crate) const READ_MEMORY: u8 = 0x11;
+pub(crate) const GO: u8 = 0x21;
+pub(crate) const WRITE_MEMORY: u8 = 0x31;
+const ERASE: u8 = 0x43;
+pub(crate) const EXTENDED_ERASE: u8 = 0x44;
+/// Commands listed by GET. ERASE is understood as well, but only one erase may be listed.
+pub(crate) const COMMANDS: [u8; 7] = [
+    GET,
+    GET_VERSION,
+    GET_ID,
+    READ_MEMORY,
+    GO,
+    WRITE_MEMORY,
+    EXTENDED_ERASE,
+];
+
+#[cfg(not(any(feature = "stm32f1", feature = "stm32f3")))]
+const DBGMCU_IDCODE: usize = 0x4001_5800;
+#[cfg(any(feature = "stm32f1", feature = "stm32f3"))]
+const DBGMCU_IDCODE: usize = 0xe004_2000;
+
+/// AN3155 command handler for an application region
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SerialBootloader {
+    region: Region,
+}
+
+impl SerialBootloader {
+    /// Handler that lets the host write, erase and start the application in `region`
+    pub fn new(region: Region) -> Self {
+        SerialBootloader { region }
+    }
+
+    /// Wait for the sync byte, then handle commands until GO and return its address, ready for
+    /// `boot_into`. A command that fails, times out or is malformed is answered with `NACK`.
+    pub fn serve(&self, flash: &mut UnlockedFlash, transport: &mut impl ByteTransport) -> usize {
+        while transport.read_byte() != Some(SYNC) {}
+        transport.write(&[ACK]);
+        loop {
+            let command = match transport.read_byte() {
+                Some(command) => command,
+                None => continue,
+            };
+            if transport.read_byte() != Some(!command) {
+                transport.write(&[NACK]);
+                continue;
+            }
+            match self.command(flash, transport, command) {
+                Ok(Some(address)) => return address,
+                Ok(None) => {}
+                Err(_) => transport.write(&[NACK]),
+            }
+        }
+    }
+
+    /// Handle `command` after its complement checked out, returning the address of a GO
+    fn command(
+        &self,
+        flash: &mut UnlockedFlash,
+        transport: &mut impl ByteTransport,
+        command: u8,
+    ) -> core::result::Result<Option<usize>, Error> {
+        match command {
+            GET => {
+                transport.write(&[ACK, COMMANDS.len() as u8, VERSION]);
+                transport.write(&COMMANDS);
+                transport.write(&[ACK]);
+            }
+            GET_VERSION => transport.write(&[ACK, VERSION, 0, 0, ACK]),
+            GET_ID => {
+                let id = device_id();
+                transport.write(&[ACK, 1, (id >> 8) as u8, id as u8, ACK]);
+            }
+            READ_MEMORY => {
+                transport.write(&[ACK]);
+                let address = read_address(transport)?;
+                if address < FLASH_START || address >= flash_end() {
+                    return Err(Error::OutOfBounds);
+                }
+                transport.write(&[ACK]);
+                let len = read_byte(transport)?;
+                if read_byte(transport)? != !len {
+                    return Err(Error::InvalidRecord);
+                }
+                let len = len as usize + 1;
+                if address + len > flash_end() {
+                    return Err(Error::OutOfBounds);
+                }
+                transport.write(&[ACK]);
+                let mut buf = [0u8; 256];
+                flash.read(address, &mut buf[..len]);
+                transport.write(&buf[..len]);
+            }
+            GO => {
+                transport.write(&[ACK]);
+                let address = read_address(transport)?;
+                if !self.region.contains(address) {
+                    return Err(Error::OutOfBounds);
+                }
+                transport.write(&[ACK]);
+                return Ok(Some(address));
+            }
+            WRITE_MEMORY => {
+                transport.write(&[ACK]);
+                let address = read_address(transport)?;
+                if !self.region.contains(address) {
+                    return Err(Error::OutOfBounds);
+                }
+                transport.write(&[ACK]);
+                let len = read_byte(transport)?;
+                let mut buf = [0u8; 256];
+                let data = &mut buf[..len as usize + 1];
+                let mut checksum = len;
+                for byte in data.iter_mut() {
+                    *byte = read_byte(transport)?;
+                    checksum ^= *byte;
+                }
+                if read_byte(transport)? != checksum {
+                    return Err(Error::InvalidRecord);
+                }
+                if address + data.len() > self.region.end_address() {
+                    return Err(Error::OutOfBounds);
+                }
+                flash.write(address, data)?;
+                transport.write(&[ACK]);
+            }
+            ERASE => {
+                transport.write(&[ACK]);
+                let count = read_byte(transport)?;
+                if count == 0xff {
+                    if read_byte(transport)? != 0x00 {
+                        return Err(Error::InvalidRecord);
+                    }
+                    flash.erase_region(&self.region)?;
+                } else {
+                    let mut pages = [0u16; 256];
+                    let pages = &mut pages[..count as usize + 1];
+                    let mut checksum = count;
+                    for page in pages.iter_mut() {
+                        let byte = read_byte(transport)?;
+                        checksum ^= byte;
+                        *page = byte as u16;
+                    }
+                    if read_byte(transport)? != checksum {
+                        return Err(Error::InvalidRecord);
+                    }
+                    erase_pages(flash, &self.region, pages)?;
+                }
+                transport.write(&[ACK]);
+            }
+            EXTENDED_ERASE => {
+                transport.write(&[ACK]);
+                let count = read_u16(transport)?;
+                let mut checksum = (count >> 8) as u8 ^ count as u8;
+                if count >= 0xfff0 {
+                    // Mass erase, 0xfffe and 0xfffd for the banks of bigger parts aren't taken
+                    if count != 0xffff || read_byte(transport)? != checksum {
+                        return Err(Error::InvalidRecord);
+                    }
+                    flash.erase_region(&self.region)?;
+                } else {
+                    let mut pages = [0u16; 256];
+                    if count as usize >= pages.len() {
+                        return Err(Error::InvalidLength);
+                    }
+                    let pages = &mut pages[..count as usize + 1];
+                    for page in pages.iter_mut() {
+                        *page = read_u16(transport)?;
+                        checksum ^= (*page >> 8) as u8 ^ *page as u8;
+                    }
+                    if read_byte(transport)? != checksum {
+                        return Err(Error::InvalidRecord);
+                    }
+                    erase_pages(flash, &self.region, pages)?;
+                }
+                transport.write(&[ACK]);
+            }
+            _ => return Err(Error::InvalidRecord),
+        }
+        Ok(None)
+    }
+}
+
+/// DEV_ID from DBGMCU_IDCODE, the product ID reported by GET ID
+pub(crate) fn device_id() -> u16 {
+    (unsafe { (DBGMCU_IDCODE as *const u32).read_volatile() } & 0xfff) as u16
+}
+
+/// Erase `pages` after checking that all of them lie in `region`
+pub(crate) fn erase_pages(
+    flash: &mut UnlockedFlash,
+    region: &Region,
+    pages: &[u16],
+) -> crate::Result {
+    let first = region.start.0;
+    if pages
+        .iter()
+        .any(|page| (*page as usize) < first || *page as usize >= first + region.pages)
+    {
+        return Err(Error::OutOfBounds);
+    }
+    for page in pages {
+        flash.erase_page(FlashPage(*page as usize))?;
+    }
+    Ok(())
+}
+
+fn read_byte(transport: &mut impl ByteTransport) -> core::result::Result<u8, Error> {
+    transport.read_byte().ok_or(Error::Cancelled)
+}
+
+fn read_u16(transport: &mut impl ByteTransport) -> core::result::Result<u16, Error> {
+    Ok(u16::from_be_bytes([read_byte(transport)?, read_byte(transport)?]))
+}
+
+/// Address and its XOR checksum
+fn read_address(transport: &mut impl ByteTransport) -> core::result::Result<usize, Error> {
+    let mut bytes = [0u8; 4];
+    for byte in bytes.iter_mut() {
+        *byte = read_byte(transport)?;
+    }
+    if read_byte(transport)? != bytes.iter().fold(0, |checksum, byte| checksum ^ byte) {
+        return Err(Error::InvalidRecord);
+    }
+    Ok(u32::from_be_bytes(bytes) as usize)
+}
@@ -0,0 +1,34 @@
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::mutex::{Mutex, MutexGuard};
+
+use crate::UnlockedFlash;
+
+/// Flash shared between embassy tasks, which await their turn for exclusive access.
+///
+/// `M` selects the raw mutex, e.g. `CriticalSectionRawMutex` when interrupt executors are
+/// involved or `ThreadModeRawMutex` when all users run in thread mode.
+pub struct AsyncSharedFlash<M: RawMutex> {
+    flash: Mutex<M, UnlockedFlash>,
+}
+
+impl<M: RawMutex> AsyncSharedFlash<M> {
+    pub const fn new(flash: UnlockedFlash) -> Self {
+        AsyncSharedFlash {
+            flash: Mutex::new(flash),
+        }
+    }
+
+    /// Wait until no other task holds the flash and take it
+    pub async fn lock(&self) -> MutexGuard<'_, M, UnlockedFlash> {
+        self.flash.lock().await
+    }
+
+    /// Take the flash if nobody else holds it right now
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, M, UnlockedFlash>> {
+        self.flash.try_lock().ok()
+    }
+
+    pub fn into_inner(self) -> UnlockedFlash {
+        self.flash.into_inner()
+    }
+}
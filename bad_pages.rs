@@ -0,0 +1,193 @@
+//! Persistent table of pages that keep failing to erase or program.
+//!
+//! The table is a region of page numbers, one halfword each, appended one after another and
+//! never erased, so a page stays bad for the life of the part. `erase_page` and `erase_region`
+//! erase and blank check the good pages, retrying a few times before a page is marked bad,
+//! `good_pages` lists the pages of a region a storage layer should use and `allocate` picks a
+//! run of good pages for a store that needs contiguous pages.
+//!
+//! Mounting the table hands its pages to the `UnlockedFlash`, and so does `mark_bad`.
+//! `UnlockedFlash::erase_region` leaves them alone, `KvStore` and `RingLog` skip them like
+//! missing pages and `SlotStore` doesn't use the slots on them. `WearLeveler` skips them as
+//! well and, when mounted with `mount_with_bad_pages`, adds the pages that fail to the table.
+
+use crate::protect::page_bit;
+use crate::{Error, FlashPage, Read, Region, Result, UnlockedFlash, WriteErase, ERASED_BYTE};
+
+const END: u16 = 0xffff;
+
+/// Whether `error` points at a worn out page rather than at protection or a wrong argument
+pub(crate) fn is_page_failure(error: Error) -> bool {
+    matches!(error, Error::ProgrammingError | Error::Eop | Error::Failure)
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BadPages {
+    table: Region,
+    count: usize,
+    attempts: u8,
+}
+
+impl BadPages {
+    /// Open the table in `table` and have `flash` skip the pages in it. Pages are erased up to
+    /// 3 times before they are marked bad.
+    pub fn mount(flash: &mut UnlockedFlash, table: Region) -> Self {
+        let count = (0..table.len() / 2)
+            .find(|index| flash.read_halfword(table.start_address() + 2 * index) == END)
+            .unwrap_or(table.len() / 2);
+        let bad_pages = BadPages {
+            table,
+            count,
+            attempts: 3,
+        };
+        for page in 0..count {
+            let page = flash.read_halfword(table.start_address() + 2 * page);
+            flash.bad_pages |= page_bit(FlashPage(page as usize));
+        }
+        bad_pages
+    }
+
+    /// Number of erases of a page, including the first one, before it is marked bad
+    pub fn set_attempts(&mut self, attempts: u8) {
+        self.attempts = attempts.max(1);
+    }
+
+    /// Number of pages marked bad
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_bad(&self, flash: &UnlockedFlash, page: FlashPage) -> bool {
+        self.bad_pages(flash).any(|bad| bad.0 == page.0)
+    }
+
+    /// Add `page` to the table, doing nothing if it is in there already
+    pub fn mark_bad(&mut self, flash: &mut UnlockedFlash, page: FlashPage) -> Result {
+        if self.is_bad(flash, page) {
+            return Ok(());
+        }
+        if 2 * (self.count + 1) > self.table.len() {
+            return Err(Error::RegionFull);
+        }
+        let address = self.table.start_address() + 2 * self.count;
+        self.count += 1;
+        flash.bad_pages |= page_bit(page);
+        flash.write_native(address, &[page.0 as u16])
+    }
+
+    /// Pages marked bad, in the order they were marked
+    pub fn bad_pages<'a>(
+        &'a self,
+        flash: &'a UnlockedFlash,
+    ) -> impl Iterator<Item = FlashPage> + 'a {
+        (0..self.count)
+            .map(move |index| flash.read_halfword(self.table.start_address() + 2 * index))
+            .map(|page| FlashPage(page as usize))
+    }
+
+    /// Pages of `region` that aren't marked bad
+    pub fn good_pages<'a>(
+        &'a self,
+        flash: &'a UnlockedFlash,
+        region: &'a Region,
+    ) -> impl Iterator<Item = FlashPage> + 'a {
+        (0..region.pages)
+            .map(move |index| region.page(index))
+            .filter(move |page| !self.is_bad(flash, *page))
+    }
+
+    /// First run of `pages` good pages in `within`, as a region for a store that needs
+    /// contiguous pages
+    pub fn allocate(
+        &self,
+        flash: &UnlockedFlash,
+        within: &Region,
+        pages: usize,
+    ) -> Option<Region> {
+        if pages == 0 {
+            return None;
+        }
+        let mut run = 0;
+        for index in 0..within.pages {
+            if self.is_bad(flash, within.page(index)) {
+                run = 0;
+                continue;
+            }
+            run += 1;
+            if run == pages {
+                return Some(Region::new(within.page(index + 1 - pages), pages));
+            }
+        }
+        None
+    }
+
+    /// Erase and blank check `page`, marking it bad once every attempt has failed.
+    ///
+    /// Fails with `Error::Failure` for a page that is or has just been marked bad.
+    pub fn erase_page(&mut self, flash: &mut UnlockedFlash, page: FlashPage) -> Result {
+        if self.is_bad(flash, page) {
+            return Err(Error::Failure);
+        }
+        for _ in 0..self.attempts {
+            match flash.erase_page(page) {
+                Ok(()) if is_blank(flash, page) => return Ok(()),
+                Ok(()) => {}
+                Err(error) if is_page_failure(error) => {}
+                Err(error) => return Err(error),
+            }
+        }
+        self.mark_bad(flash, page)?;
+        Err(Error::Failure)
+    }
+
+    /// Erase the good pages of `region`, marking the ones that fail as bad and skipping them
+    pub fn erase_region(&mut self, flash: &mut UnlockedFlash, region: &Region) -> Result {
+        for index in 0..region.pages {
+            let page = region.page(index);
+            if self.is_bad(flash, page) {
+                continue;
+            }
+            match self.erase_page(flash, page) {
+                Ok(()) | Err(Error::Failure) => {}
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl UnlockedFlash {
+    /// Whether `page` is in the bad page table mounted on this flash
+    pub fn is_bad_page(&self, page: FlashPage) -> bool {
+        self.bad_pages & page_bit(page) != 0
+    }
+
+    /// Whether any page of `address..address + len` is marked bad
+    pub(crate) fn touches_bad_page(&self, address: usize, len: usize) -> bool {
+        if self.bad_pages == 0 || len == 0 {
+            return false;
+        }
+        let first = FlashPage::from_address(address).0;
+        let last = FlashPage::from_address(address + len - 1).0;
+        (first..=last).any(|page| self.is_bad_page(FlashPage(page)))
+    }
+
+    /// Number of pages of `region` that aren't marked bad
+    pub(crate) fn good_pages(&self, region: &Region) -> usize {
+        (0..region.pages)
+            .filter(|index| !self.is_bad_page(region.page(*index)))
+            .count()
+    }
+}
+
+fn is_blank(flash: &UnlockedFlash, page: FlashPage) -> bool {
+    let mut buf = [0u8; 64];
+    let start = page.to_address();
+    (start..start + crate::PAGE_SIZE as usize)
+        .step_by(buf.len())
+        .all(|address| {
+            flash.read(address, &mut buf);
+            buf.iter().all(|b| *b == ERASED_BYTE)
+        })
+}
@@ -0,0 +1,109 @@
+//! `embassy-boot` partitions.
+//!
+//! `UnlockedFlash` already has the `NorFlash` impls embassy-boot needs: 2 byte writes, page
+//! sized erases and `0x0000` overwrites of programmed halfwords, which the state page relies on
+//! to record swap progress without erasing. Build embassy-boot with a `WRITE_SIZE` aligned
+//! state buffer of at least 2 bytes.
+
+use core::cell::RefCell;
+
+use embassy_boot::{BootLoaderConfig, FirmwareUpdaterConfig};
+use embassy_embedded_hal::flash::partition::BlockingPartition;
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+use crate::{FlashPage, Region, UnlockedFlash, FLASH_START};
+
+/// Partition that embassy-boot drives through the shared flash
+pub type BootPartition<'a, M> = BlockingPartition<'a, M, UnlockedFlash>;
+
+/// Flash split into bootloader, state, active and DFU partitions, in this order.
+///
+/// The DFU partition is one page larger than the active one, the swap algorithm needs the
+/// spare page.
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BootLayout {
+    pub bootloader: Region,
+    pub state: Region,
+    pub active: Region,
+    pub dfu: Region,
+}
+
+impl BootLayout {
+    /// Layout with the bootloader in the first `bootloader_pages` and an `active_pages` large
+    /// application slot. The DFU partition ends at page `bootloader_pages + 2 * active_pages + 2`,
+    /// which has to fit the device.
+    pub const fn new(bootloader_pages: usize, active_pages: usize) -> Self {
+        let state = Region::new(FlashPage(bootloader_pages), 1);
+        let active = Region::new(FlashPage(bootloader_pages + 1), active_pages);
+        let dfu = Region::new(FlashPage(bootloader_pages + 1 + active_pages), active_pages + 1);
+        BootLayout {
+            bootloader: Region::new(FlashPage(0), bootloader_pages),
+            state,
+            active,
+            dfu,
+        }
+    }
+
+    /// Number of pages the layout occupies
+    pub const fn pages(&self) -> usize {
+        self.dfu.start.0 + self.dfu.pages
+    }
+
+    /// Partitions for the bootloader, which swaps active and DFU
+    pub fn bootloader_config<'a, M: RawMutex>(
+        &self,
+        flash: &'a Mutex<M, RefCell<UnlockedFlash>>,
+    ) -> BootLoaderConfig<BootPartition<'a, M>, BootPartition<'a, M>, BootPartition<'a, M>> {
+        BootLoaderConfig {
+            active: partition(flash, &self.active),
+            dfu: partition(flash, &self.dfu),
+            state: partition(flash, &self.state),
+        }
+    }
+
+    /// Partitions for the application, which writes the DFU partition and marks the update
+    pub fn updater_config<'a, M: RawMutex>(
+        &self,
+        flash: &'a Mutex<M, RefCell<UnlockedFlash>>,
+    ) -> FirmwareUpdaterConfig<BootPartition<'a, M>, BootPartition<'a, M>> {
+        FirmwareUpdaterConfig {
+            dfu: partition(flash, &self.dfu),
+            state: partition(flash, &self.state),
+        }
+    }
+}
+
+fn partition<'a, M: RawMutex>(
+    flash: &'a Mutex<M, RefCell<UnlockedFlash>>,
+    region: &Region,
+) -> BootPartition<'a, M> {
+    let offset = region.start_address() - FLASH_START;
+    BlockingPartition::new(flash, offset as u32, region.len() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partitions_follow_each_other() {
+        let layout = BootLayout::new(8, 20);
+        assert_eq!((layout.bootloader.start.0, layout.bootloader.pages), (0, 8));
+        assert_eq!((layout.state.start.0, layout.state.pages), (8, 1));
+        assert_eq!((layout.active.start.0, layout.active.pages), (9, 20));
+        assert_eq!((layout.dfu.start.0, layout.dfu.pages), (29, 21));
+        assert_eq!(layout.pages(), 8 + 2 * 20 + 2);
+    }
+
+    #[test]
+    fn partitions_are_page_aligned() {
+        let layout = BootLayout::new(8, 20);
+        for region in [layout.state, layout.active, layout.dfu] {
+            let offset = region.start_address() - FLASH_START;
+            assert_eq!(offset % crate::PAGE_SIZE as usize, 0);
+            assert_eq!(region.len() % crate::PAGE_SIZE as usize, 0);
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Boot count, reset cause and boot phase history.
+//!
+//! Every boot appends a record to a `RingLog` holding the boot number, the reset cause read
+//! from RCC_CSR and a boot phase of 0. `set_phase` appends another record for the same boot, so
+//! after a watchdog reset loop the history shows how far each boot got.
+
+use crate::{Error, LogRecord, Region, Result, RingLog, UnlockedFlash};
+
+/// Address of RCC_CSR, the reset flags
+const RCC_CSR_ADDRESS: usize = 0x4002_1024;
+const CSR_RMVF: u32 = 1 << 24;
+
+const RECORD_LEN: usize = 6;
+
+/// Cause of the last reset, the first set flag of RCC_CSR in order of the variants
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ResetCause {
+    LowPower = 1,
+    WindowWatchdog = 2,
+    IndependentWatchdog = 3,
+    Software = 4,
+    PowerOn = 5,
+    Pin = 6,
+    /// Option byte load, F0 and F3 only
+    OptionByteLoad = 7,
+    /// 1.8 V domain reset, F04x/F07x/F09x in VDDA = 1.8 V mode only
+    V18PowerOn = 8,
+    Unknown = 0,
+}
+
+impl ResetCause {
+    /// Decode the reset flags of a raw RCC_CSR value
+    pub const fn from_csr(csr: u32) -> Self {
+        if csr & (1 << 31) != 0 {
+            ResetCause::LowPower
+        } else if csr & (1 << 30) != 0 {
+            ResetCause::WindowWatchdog
+        } else if csr & (1 << 29) != 0 {
+            ResetCause::IndependentWatchdog
+        } else if csr & (1 << 28) != 0 {
+            ResetCause::Software
+        } else if csr & (1 << 27) != 0 {
+            ResetCause::PowerOn
+        } else if csr & (1 << 26) != 0 {
+            ResetCause::Pin
+        } else if csr & (1 << 25) != 0 {
+            ResetCause::OptionByteLoad
+        } else if csr & (1 << 23) != 0 {
+            ResetCause::V18PowerOn
+        } else {
+            ResetCause::Unknown
+        }
+    }
+
+    const fn from_u8(value: u8) -> Self {
+        match value {
+            1 => ResetCause::LowPower,
+            2 => ResetCause::WindowWatchdog,
+            3 => ResetCause::IndependentWatchdog,
+            4 => ResetCause::Software,
+            5 => ResetCause::PowerOn,
+            6 => ResetCause::Pin,
+            7 => ResetCause::OptionByteLoad,
+            8 => ResetCause::V18PowerOn,
+            _ => ResetCause::Unknown,
+        }
+    }
+}
+
+/// Cause of the last reset, read from RCC_CSR. The flags stay set until `clear_reset_flags`.
+pub fn reset_cause() -> ResetCause {
+    ResetCause::from_csr(unsafe { (RCC_CSR_ADDRESS as *const u32).read_volatile() })
+}
+
+/// Clear the RCC_CSR reset flags, so the next boot only sees the flags of its own reset
+pub fn clear_reset_flags() {
+    let csr = RCC_CSR_ADDRESS as *mut u32;
+    unsafe { csr.write_volatile(csr.read_volatile() | CSR_RMVF) };
+}
+
+/// One entry of the boot history
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BootRecord {
+    /// Number of the boot, counting from 0
+    pub boot: u32,
+    pub cause: ResetCause,
+    /// Boot phase the firmware had reached, see `BootHistory::set_phase`
+    pub phase: u8,
+}
+
+impl BootRecord {
+    fn to_bytes(self) -> [u8; RECORD_LEN] {
+        let boot = self.boot.to_ne_bytes();
+        [boot[0], boot[1], boot[2], boot[3], self.cause as u8, self.phase]
+    }
+
+    fn from_record(flash: &UnlockedFlash, record: &LogRecord) -> Option<Self> {
+        let mut bytes = [0u8; RECORD_LEN];
+        if record.read(flash, &mut bytes) != RECORD_LEN {
+            return None;
+        }
+        Some(BootRecord {
+            boot: u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            cause: ResetCause::from_u8(bytes[4]),
+            phase: bytes[5],
+        })
+    }
+}
+
+/// Boot history kept in a region
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BootHistory {
+    log: RingLog,
+    current: BootRecord,
+}
+
+impl BootHistory {
+    /// Record this boot with the current reset cause and clear the reset flags. Call once early
+    /// at startup.
+    pub fn start(
+        flash: &mut UnlockedFlash,
+        region: Region,
+    ) -> core::result::Result<Self, Error> {
+        let mut log = RingLog::mount(flash, region)?;
+        let boot = log
+            .records(flash)
+            .filter_map(|record| BootRecord::from_record(flash, &record))
+            .last()
+            .map_or(0, |last| last.boot.wrapping_add(1));
+        let current = BootRecord {
+            boot,
+            cause: reset_cause(),
+            phase: 0,
+        };
+        clear_reset_flags();
+        log.append(flash, &current.to_bytes())?;
+        Ok(BootHistory { log, current })
+    }
+
+    /// This boot
+    pub fn current(&self) -> BootRecord {
+        self.current
+    }
+
+    /// Number of boots recorded, including this one
+    pub fn boot_count(&self) -> u32 {
+        self.current.boot.wrapping_add(1)
+    }
+
+    /// Record that this boot reached `phase`
+    pub fn set_phase(&mut self, flash: &mut UnlockedFlash, phase: u8) -> Result {
+        self.current.phase = phase;
+        self.log.append(flash, &self.current.to_bytes())
+    }
+
+    /// Recorded entries from the oldest to the newest, the last one of a boot is how far it got
+    pub fn history<'a>(
+        &'a self,
+        flash: &'a UnlockedFlash,
+    ) -> impl Iterator<Item = BootRecord> + 'a {
+        self.log
+            .records(flash)
+            .filter_map(move |record| BootRecord::from_record(flash, &record))
+    }
+}
@@ -0,0 +1,177 @@
+//! Update protocol over CAN, for reflashing the F042 and F072 through their bxCAN.
+//!
+//! The host sends command frames with 8 data bytes at most and the target answers each one
+//! with a 3 byte status frame, on CAN IDs chosen by the application:
+//!
+//! ```text
+//! command: command (u8) | sequence (u8) | payload (0 to 6 bytes)
+//! status:  command | 0x80 (u8) | sequence (u8) | CanStatus (u8)
+//! ```
+//!
+//! ERASE starts a new image in the inactive slot of a `SlotManager`, its sequence number may be
+//! anything and every further frame carries the next one. PROGRAM appends its payload to the
+//! image. VERIFY carries the CRC-32 of the whole image, little endian, which is checked against
+//! the data read back from flash after `ImageWriter::commit` checked the image against its
+//! header, before the image is marked pending. RUN asks the application
+//! to reset into the new image, see `run_requested`.
+//!
+//! A frame with the sequence number of the previous one is a retransmission after a lost
+//! status frame and gets the same status again without being executed twice. Pages are erased
+//! while PROGRAM frames reach them, so the host has to allow for a page erase on these.
+
+use crate::checksum::flash_crc32;
+use crate::{Error, ImageWriter, SignatureVerifier, SlotManager, UnlockedFlash};
+
+const ERASE: u8 = 0x01;
+const PROGRAM: u8 = 0x02;
+const VERIFY: u8 = 0x03;
+const RUN: u8 = 0x04;
+/// Set in the first byte of status frames
+const STATUS: u8 = 0x80;
+
+/// Result of a command, the third byte of a status frame
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum CanStatus {
+    Ok = 0x00,
+    /// Sequence number is neither the expected nor the previous one
+    Sequence = 0x01,
+    /// Unknown command or wrong payload length
+    Malformed = 0x02,
+    /// PROGRAM, VERIFY or RUN before an ERASE or VERIFY they depend on
+    NotStaged = 0x03,
+    /// Image doesn't fit into the slot
+    TooLarge = 0x04,
+    /// Erasing or programming failed
+    Flash = 0x05,
+    /// CRC-32 of VERIFY doesn't match the image in flash
+    CrcMismatch = 0x06,
+    /// Image header, signature or version was refused by `SlotManager::mark_pending`
+    InvalidImage = 0x07,
+}
+
+impl From<Error> for CanStatus {
+    fn from(error: Error) -> Self {
+        match error {
+            Error::RegionFull => CanStatus::TooLarge,
+            Error::CrcMismatch => CanStatus::CrcMismatch,
+            Error::InvalidImage | Error::BadSignature | Error::Rollback => CanStatus::InvalidImage,
+            _ => CanStatus::Flash,
+        }
+    }
+}
+
+/// Target side of the CAN update protocol
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CanBootloader {
+    writer: Option<ImageWriter>,
+    /// Sequence number of the next frame, `None` before the first ERASE
+    sequence: Option<u8>,
+    last: [u8; 3],
+    verified: bool,
+    run: bool,
+}
+
+impl Default for CanBootloader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CanBootloader {
+    pub fn new() -> Self {
+        CanBootloader {
+            writer: None,
+            sequence: None,
+            last: [0u8; 3],
+            verified: false,
+            run: false,
+        }
+    }
+
+    /// Handle the data of a command frame and return the data of the status frame to send
+    pub fn handle_frame<V: SignatureVerifier>(
+        &mut self,
+        flash: &mut UnlockedFlash,
+        slots: &mut SlotManager<V>,
+        frame: &[u8],
+    ) -> [u8; 3] {
+        let (command, sequence, payload) = match frame {
+            [command, sequence, payload @ ..] if payload.len() <= 6 => {
+                (*command, *sequence, payload)
+            }
+            _ => return [0, 0, CanStatus::Malformed as u8],
+        };
+        if command != ERASE {
+            match self.sequence {
+                Some(next) if sequence == next.wrapping_sub(1) => return self.last,
+                Some(next) if sequence == next => {}
+                Some(_) => return [command | STATUS, sequence, CanStatus::Sequence as u8],
+                None => return [command | STATUS, sequence, CanStatus::NotStaged as u8],
+            }
+        }
+        let status = match self.command(flash, slots, command, payload) {
+            Ok(()) => CanStatus::Ok,
+            Err(status) => status,
+        };
+        self.sequence = Some(sequence.wrapping_add(1));
+        self.last = [command | STATUS, sequence, status as u8];
+        self.last
+    }
+
+    /// Whether a RUN command was accepted, the application should reset so the bootloader
+    /// starts the pending image
+    pub fn run_requested(&self) -> bool {
+        self.run
+    }
+
+    fn command<V: SignatureVerifier>(
+        &mut self,
+        flash: &mut UnlockedFlash,
+        slots: &mut SlotManager<V>,
+        command: u8,
+        payload: &[u8],
+    ) -> core::result::Result<(), CanStatus> {
+        match command {
+            ERASE => {
+                if !payload.is_empty() {
+                    return Err(CanStatus::Malformed);
+                }
+                self.verified = false;
+                self.writer = Some(slots.stage(flash)?);
+            }
+            PROGRAM => {
+                let writer = self.writer.as_mut().ok_or(CanStatus::NotStaged)?;
+                writer.write(flash, payload)?;
+            }
+            VERIFY => {
+                let crc = match payload {
+                    [a, b, c, d] => u32::from_le_bytes([*a, *b, *c, *d]),
+                    _ => return Err(CanStatus::Malformed),
+                };
+                let writer = self.writer.take().ok_or(CanStatus::NotStaged)?;
+                let start = writer.region().start_address();
+                let len = writer.written();
+                writer.commit(flash)?;
+                if flash_crc32(flash, start, len) != crc {
+                    return Err(CanStatus::CrcMismatch);
+                }
+                slots.mark_pending(flash)?;
+                self.verified = true;
+            }
+            RUN => {
+                if !payload.is_empty() {
+                    return Err(CanStatus::Malformed);
+                }
+                if !self.verified {
+                    return Err(CanStatus::NotStaged);
+                }
+                self.run = true;
+            }
+            _ => return Err(CanStatus::Malformed),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,148 @@
+//! Checksums for the records of the storage layers.
+//!
+//! The stores take the checksum as a type parameter, CRC-32 by default, so the records can be
+//! checked with whatever algorithm the host tools already use. Checksums narrower than 32 bits
+//! are stored zero extended.
+
+use crate::{Read, UnlockedFlash};
+
+/// Streaming checksum over the bytes of a record
+pub trait Checksum {
+    fn new() -> Self;
+
+    fn update(&mut self, data: &[u8]);
+
+    /// The checksum over everything passed to `update`
+    fn finish(self) -> u32;
+}
+
+/// CRC-32 (IEEE 802.3), as used by zlib and Ethernet
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Crc32(u32);
+
+impl Checksum for Crc32 {
+    fn new() -> Self {
+        Crc32(0xffff_ffff)
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.0 ^= *byte as u32;
+            for _ in 0..8 {
+                let mask = (self.0 & 1).wrapping_neg();
+                self.0 = (self.0 >> 1) ^ (0xedb8_8320 & mask);
+            }
+        }
+    }
+
+    fn finish(self) -> u32 {
+        !self.0
+    }
+}
+
+/// CRC-32 of `len` bytes of flash from `start`
+pub(crate) fn flash_crc32(flash: &UnlockedFlash, start: usize, len: usize) -> u32 {
+    let mut crc = Crc32::new();
+    let mut buf = [0u8; 64];
+    let mut checked = 0;
+    while checked < len {
+        let chunk = buf.len().min(len - checked);
+        flash.read(start + checked, &mut buf[..chunk]);
+        crc.update(&buf[..chunk]);
+        checked += chunk;
+    }
+    crc.finish()
+}
+
+/// CRC-16/CCITT-FALSE: polynomial 0x1021, initial value 0xffff, not reflected
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Crc16(u16);
+
+impl Checksum for Crc16 {
+    fn new() -> Self {
+        Crc16(0xffff)
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.0 ^= (*byte as u16) << 8;
+            for _ in 0..8 {
+                let mask = (self.0 >> 15).wrapping_neg();
+                self.0 = (self.0 << 1) ^ (0x1021 & mask);
+            }
+        }
+    }
+
+    fn finish(self) -> u32 {
+        self.0 as u32
+    }
+}
+
+/// Fletcher-16 over bytes
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Fletcher16 {
+    sum1: u16,
+    sum2: u16,
+}
+
+impl Checksum for Fletcher16 {
+    fn new() -> Self {
+        Fletcher16 { sum1: 0, sum2: 0 }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.sum1 = (self.sum1 + *byte as u16) % 255;
+            self.sum2 = (self.sum2 + self.sum1) % 255;
+        }
+    }
+
+    fn finish(self) -> u32 {
+        ((self.sum2 as u32) << 8) | self.sum1 as u32
+    }
+}
+
+/// Fletcher-32 over little endian halfwords, an odd last byte is padded with zero
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Fletcher32 {
+    sum1: u32,
+    sum2: u32,
+    pending: Option<u8>,
+}
+
+impl Fletcher32 {
+    fn add(&mut self, halfword: u16) {
+        self.sum1 = (self.sum1 + halfword as u32) % 65535;
+        self.sum2 = (self.sum2 + self.sum1) % 65535;
+    }
+}
+
+impl Checksum for Fletcher32 {
+    fn new() -> Self {
+        Fletcher32 {
+            sum1: 0,
+            sum2: 0,
+            pending: None,
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            match self.pending.take() {
+                Some(low) => self.add(u16::from_le_bytes([low, *byte])),
+                None => self.pending = Some(*byte),
+            }
+        }
+    }
+
+    fn finish(mut self) -> u32 {
+        if let Some(low) = self.pending.take() {
+            self.add(low as u16);
+        }
+        (self.sum2 << 16) | self.sum1
+    }
+}
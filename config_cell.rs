@@ -0,0 +1,142 @@
+//! Typed configuration with an A/B commit.
+//!
+//! The region is split into two slots. Every slot starts with a 16 byte header:
+//!
+//! ```text
+//! magic (u16) | valid (u16) | sequence (u32) | length (u16) | reserved (u16) | crc (u32)
+//! ```
+//!
+//! `save` erases the inactive slot, programs the data, the rest of the header and the magic
+//! last, and only then clears the valid halfword of the old slot. Whenever the power is cut, at
+//! least one slot holds a complete configuration, and if both do the higher sequence wins.
+
+use core::marker::PhantomData;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::{Checksum, Crc32, Error, Read, Region, Result, UnlockedFlash, WriteErase};
+
+const HEADER_LEN: usize = 16;
+const MAGIC: u16 = 0x4346;
+const VALID: u16 = 0xffff;
+const INVALID: u16 = 0x0000;
+
+/// A `T` stored with postcard in a region, serialized into at most `N` bytes and checked with `C`
+pub struct ConfigCell<T, const N: usize, C = Crc32> {
+    region: Region,
+    active: Option<usize>,
+    sequence: u32,
+    _value: PhantomData<(T, C)>,
+}
+
+impl<T: Serialize + DeserializeOwned, const N: usize, C: Checksum> ConfigCell<T, N, C> {
+    /// Find the newest complete slot in `region`, which needs an even number of pages
+    pub fn mount(flash: &UnlockedFlash, region: Region) -> core::result::Result<Self, Error> {
+        if region.is_empty() || region.pages % 2 != 0 {
+            return Err(Error::InvalidLength);
+        }
+        let mut cell = ConfigCell {
+            region,
+            active: None,
+            sequence: 0,
+            _value: PhantomData,
+        };
+        if N > cell.slot_len() - HEADER_LEN {
+            return Err(Error::InvalidLength);
+        }
+        let newest = (0..2)
+            .filter_map(|slot| Some((slot, cell.slot_sequence(flash, slot)?)))
+            .max_by_key(|(_, sequence)| *sequence);
+        if let Some((slot, sequence)) = newest {
+            cell.active = Some(slot);
+            cell.sequence = sequence;
+        }
+        Ok(cell)
+    }
+
+    /// The saved value, `None` if nothing was saved yet
+    pub fn load(&self, flash: &UnlockedFlash) -> core::result::Result<Option<T>, Error> {
+        let slot = match self.active {
+            Some(slot) => slot,
+            None => return Ok(None),
+        };
+        let address = self.slot_address(slot);
+        let len = flash.read_halfword(address + 8) as usize;
+        let mut buf = [0u8; N];
+        flash.read(address + HEADER_LEN, &mut buf[..len]);
+        postcard::from_bytes(&buf[..len])
+            .map(Some)
+            .map_err(|_| Error::Serialization)
+    }
+
+    /// Save `value` to the inactive slot and make it the active one
+    pub fn save(&mut self, flash: &mut UnlockedFlash, value: &T) -> Result {
+        let mut buf = [0u8; N];
+        let len = postcard::to_slice(value, &mut buf)
+            .map_err(|_| Error::Serialization)?
+            .len();
+        let data = &buf[..len];
+
+        let slot = self.active.map_or(0, |slot| 1 - slot);
+        let sequence = self.sequence.wrapping_add(1);
+        let mut crc = C::new();
+        crc.update(&sequence.to_ne_bytes());
+        crc.update(data);
+
+        for page in 0..self.slot_pages() {
+            flash.erase_page(self.region.page(slot * self.slot_pages() + page))?;
+        }
+        let address = self.slot_address(slot);
+        if !data.is_empty() {
+            flash.write(address + HEADER_LEN, data)?;
+        }
+        flash.write(address + 4, &sequence.to_ne_bytes())?;
+        flash.write_native(address + 8, &[len as u16])?;
+        flash.write(address + 12, &crc.finish().to_ne_bytes())?;
+        flash.write_native(address, &[MAGIC])?;
+
+        if let Some(old) = self.active {
+            flash.write_native(self.slot_address(old) + 2, &[INVALID])?;
+        }
+        self.active = Some(slot);
+        self.sequence = sequence;
+        Ok(())
+    }
+
+    /// Sequence number of `slot` if it holds a complete, valid configuration
+    fn slot_sequence(&self, flash: &UnlockedFlash, slot: usize) -> Option<u32> {
+        let address = self.slot_address(slot);
+        if flash.read_halfword(address) != MAGIC || flash.read_halfword(address + 2) != VALID {
+            return None;
+        }
+        let len = flash.read_halfword(address + 8) as usize;
+        if len > N {
+            return None;
+        }
+        let mut header = [0u8; HEADER_LEN];
+        flash.read(address, &mut header);
+        let mut buf = [0u8; N];
+        flash.read(address + HEADER_LEN, &mut buf[..len]);
+
+        let mut crc = C::new();
+        crc.update(&header[4..8]);
+        crc.update(&buf[..len]);
+        if crc.finish().to_ne_bytes() != header[12..16] {
+            return None;
+        }
+        Some(u32::from_ne_bytes([header[4], header[5], header[6], header[7]]))
+    }
+
+    fn slot_pages(&self) -> usize {
+        self.region.pages / 2
+    }
+
+    fn slot_len(&self) -> usize {
+        self.region.len() / 2
+    }
+
+    fn slot_address(&self, slot: usize) -> usize {
+        self.region.start_address() + slot * self.slot_len()
+    }
+}
@@ -0,0 +1,147 @@
+//! Monotonic counter that survives resets and spreads its wear over two pages.
+//!
+//! Each page starts with a magic and the 64 bit base value of the page. The rest of the page
+//! holds ticks: every halfword counts twice, first by programming it from erased to `TICK`,
+//! then by clearing it to `0x0000`, which the controller allows over a programmed halfword.
+//! Once a page is used up the current value becomes the base of the other page, and only then
+//! is the full page erased. A 1 KB page takes 1012 increments per erase.
+
+use crate::{Error, FlashPage, Result, UnlockedFlash, WriteErase, PAGE_SIZE};
+
+const MAGIC: u16 = 0x434e;
+const HEADER_LEN: usize = 12;
+const ERASED: u16 = 0xffff;
+const TICK: u16 = 0x5555;
+const USED: u16 = 0x0000;
+
+const TICK_HALFWORDS: usize = (PAGE_SIZE as usize - HEADER_LEN) / 2;
+/// Increments available per page
+const TICKS_PER_PAGE: usize = 2 * TICK_HALFWORDS;
+
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PersistentCounter {
+    pages: [FlashPage; 2],
+    active: usize,
+    base: u64,
+    ticks: usize,
+}
+
+impl PersistentCounter {
+    /// Pick up the counter stored in `pages`, starting at 0 if there is none
+    pub fn mount(
+        flash: &mut UnlockedFlash,
+        pages: [FlashPage; 2],
+    ) -> core::result::Result<Self, Error> {
+        let mut counter = PersistentCounter {
+            pages,
+            active: 0,
+            base: 0,
+            ticks: 0,
+        };
+        match [counter.page_base(flash, 0), counter.page_base(flash, 1)] {
+            // A reset interrupted the switch after the new page was committed
+            [Some(a), Some(b)] => {
+                counter.active = if b > a { 1 } else { 0 };
+                flash.erase_page(pages[1 - counter.active])?;
+            }
+            [Some(_), None] => counter.clean(flash, 1)?,
+            [None, Some(_)] => {
+                counter.active = 1;
+                counter.clean(flash, 0)?;
+            }
+            [None, None] => {
+                counter.clean(flash, 0)?;
+                counter.clean(flash, 1)?;
+                counter.write_header(flash, 0, 0)?;
+            }
+        }
+        counter.base = counter.page_base(flash, counter.active).unwrap_or(0);
+        counter.ticks = counter.count_ticks(flash);
+        Ok(counter)
+    }
+
+    pub fn value(&self) -> u64 {
+        self.base + self.ticks as u64
+    }
+
+    /// Add one, moving to the other page when the active one is used up
+    pub fn increment(&mut self, flash: &mut UnlockedFlash) -> Result {
+        if self.ticks == TICKS_PER_PAGE {
+            self.switch(flash)?;
+        }
+        let address = self.tick_address(self.ticks / 2);
+        let value = if self.ticks % 2 == 0 { TICK } else { USED };
+        flash.write_native(address, &[value])?;
+        self.ticks += 1;
+        Ok(())
+    }
+
+    fn switch(&mut self, flash: &mut UnlockedFlash) -> Result {
+        let old = self.active;
+        let new = 1 - old;
+        let value = self.value();
+        self.write_header(flash, new, value)?;
+        flash.erase_page(self.pages[old])?;
+        self.active = new;
+        self.base = value;
+        self.ticks = 0;
+        Ok(())
+    }
+
+    /// Program the base first and the magic last, committing the page
+    fn write_header(&self, flash: &mut UnlockedFlash, page: usize, base: u64) -> Result {
+        let address = self.pages[page].to_address();
+        let bytes = base.to_ne_bytes();
+        let mut halfwords = [0u16; 4];
+        for (halfword, chunk) in halfwords.iter_mut().zip(bytes.chunks_exact(2)) {
+            *halfword = u16::from_ne_bytes([chunk[0], chunk[1]]);
+        }
+        flash.write_native(address + 4, &halfwords)?;
+        flash.write_native(address, &[MAGIC])
+    }
+
+    fn page_base(&self, flash: &UnlockedFlash, page: usize) -> Option<u64> {
+        let address = self.pages[page].to_address();
+        if flash.read_halfword(address) != MAGIC {
+            return None;
+        }
+        let mut bytes = [0u8; 8];
+        for (i, chunk) in bytes.chunks_exact_mut(2).enumerate() {
+            chunk.copy_from_slice(&flash.read_halfword(address + 4 + 2 * i).to_ne_bytes());
+        }
+        Some(u64::from_ne_bytes(bytes))
+    }
+
+    /// Erase `page` unless it is erased already
+    fn clean(&self, flash: &mut UnlockedFlash, page: usize) -> Result {
+        let address = self.pages[page].to_address();
+        let touched = (0..PAGE_SIZE as usize)
+            .step_by(2)
+            .any(|offset| flash.read_halfword(address + offset) != ERASED);
+        if touched {
+            flash.erase_page(self.pages[page])?;
+        }
+        Ok(())
+    }
+
+    /// Ticks of the active page, found by a binary search over its halfwords
+    fn count_ticks(&self, flash: &UnlockedFlash) -> usize {
+        let (mut low, mut high) = (0, TICK_HALFWORDS);
+        // The halfwords before `low` are used up, the ones from `high` on are not
+        while low < high {
+            let mid = (low + high) / 2;
+            if flash.read_halfword(self.tick_address(mid)) == USED {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        let partial = low < TICK_HALFWORDS && flash.read_halfword(self.tick_address(low)) != ERASED;
+        2 * low + partial as usize
+    }
+
+    fn tick_address(&self, index: usize) -> usize {
+        self.pages[self.active].to_address() + HEADER_LEN + 2 * index
+    }
+}
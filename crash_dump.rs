@@ -0,0 +1,222 @@
+//! Crash dumps written from the HardFault handler and read back on the next boot.
+//!
+//! Layout of the dump region:
+//!
+//! ```text
+//! magic (u16) | commit (u16) | stack words (u16) | reserved (u16) | CrashDump words | stack
+//! ```
+//!
+//! The region is erased, then the registers, the stack snapshot and its length are programmed,
+//! then the magic and the commit halfword last. A dump that was cut short never reads back as
+//! valid, it is simply missing.
+//!
+//! ```ignore
+//! #[exception]
+//! unsafe fn HardFault(frame: &ExceptionFrame) -> ! {
+//!     FLASH.try_with_mut(|flash| capture_crash(flash, &CRASH_REGION, frame as *const _ as usize));
+//!     cortex_m::peripheral::SCB::sys_reset()
+//! }
+//! ```
+
+use crate::{Read, Region, Result, UnlockedFlash, WriteErase};
+
+const MAGIC: u16 = 0x4344;
+const COMMITTED: u16 = 0x0000;
+const HEADER_LEN: usize = 8;
+const DUMP_WORDS: usize = 14;
+const DUMP_LEN: usize = DUMP_WORDS * 4;
+
+/// Most stack words captured after the exception frame
+pub const MAX_STACK_WORDS: usize = 256;
+
+/// Registers at the time of the fault
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CrashDump {
+    pub r0: u32,
+    pub r1: u32,
+    pub r2: u32,
+    pub r3: u32,
+    pub r12: u32,
+    pub lr: u32,
+    pub pc: u32,
+    pub xpsr: u32,
+    /// Address of the exception frame, the stack pointer before the fault
+    pub sp: u32,
+    /// Configurable fault status, 0 on Cortex-M0 parts which don't have it
+    pub cfsr: u32,
+    /// HardFault status, 0 on Cortex-M0 parts
+    pub hfsr: u32,
+    /// MemManage fault address, 0 on Cortex-M0 parts
+    pub mmfar: u32,
+    /// BusFault address, 0 on Cortex-M0 parts
+    pub bfar: u32,
+    /// Number of stack words captured after the exception frame
+    pub stack_words: u32,
+}
+
+impl CrashDump {
+    fn to_words(self) -> [u32; DUMP_WORDS] {
+        [
+            self.r0,
+            self.r1,
+            self.r2,
+            self.r3,
+            self.r12,
+            self.lr,
+            self.pc,
+            self.xpsr,
+            self.sp,
+            self.cfsr,
+            self.hfsr,
+            self.mmfar,
+            self.bfar,
+            self.stack_words,
+        ]
+    }
+
+    fn from_words(words: [u32; DUMP_WORDS]) -> Self {
+        CrashDump {
+            r0: words[0],
+            r1: words[1],
+            r2: words[2],
+            r3: words[3],
+            r12: words[4],
+            lr: words[5],
+            pc: words[6],
+            xpsr: words[7],
+            sp: words[8],
+            cfsr: words[9],
+            hfsr: words[10],
+            mmfar: words[11],
+            bfar: words[12],
+            stack_words: words[13],
+        }
+    }
+}
+
+/// Capture the fault whose exception frame is at `frame` into `region`.
+///
+/// Up to `MAX_STACK_WORDS` words above the frame are captured, bounded by the top of the stack
+/// and by the size of the region.
+pub fn capture_crash(flash: &mut UnlockedFlash, region: &Region, frame: usize) -> Result {
+    extern "C" {
+        // Initial stack pointer from the cortex-m-rt linker script
+        static _stack_start: u32;
+    }
+    let stack_top = unsafe { &_stack_start as *const u32 as usize };
+    let frame_words = unsafe { core::slice::from_raw_parts(frame as *const u32, 8) };
+
+    let room = region.len().saturating_sub(HEADER_LEN + DUMP_LEN) / 4;
+    let stack_words = (stack_top.saturating_sub(frame + 32) / 4)
+        .min(MAX_STACK_WORDS)
+        .min(room);
+
+    let (cfsr, hfsr, mmfar, bfar) = fault_status();
+    let dump = CrashDump {
+        r0: frame_words[0],
+        r1: frame_words[1],
+        r2: frame_words[2],
+        r3: frame_words[3],
+        r12: frame_words[4],
+        lr: frame_words[5],
+        pc: frame_words[6],
+        xpsr: frame_words[7],
+        sp: frame as u32,
+        cfsr,
+        hfsr,
+        mmfar,
+        bfar,
+        stack_words: stack_words as u32,
+    };
+
+    // The fault may have interrupted an erase or a write
+    flash.clear_errors();
+    flash.erase_region(region)?;
+    let base = region.start_address();
+    for (i, word) in dump.to_words().iter().enumerate() {
+        write_word(flash, base + HEADER_LEN + 4 * i, *word)?;
+    }
+    let stack = (frame + 32) as *const u32;
+    for i in 0..stack_words {
+        let word = unsafe { stack.add(i).read_volatile() };
+        write_word(flash, base + HEADER_LEN + DUMP_LEN + 4 * i, word)?;
+    }
+    flash.write_native(base + 4, &[stack_words as u16])?;
+    flash.write_native(base, &[MAGIC])?;
+    flash.write_native(base + 2, &[COMMITTED])
+}
+
+/// The dump in `region`, `None` if there is no complete one
+pub fn read_crash(flash: &impl Read<NativeType = u8>, region: &Region) -> Option<CrashDump> {
+    let base = region.start_address();
+    let mut header = [0u8; HEADER_LEN];
+    flash.read(base, &mut header);
+    if u16::from_ne_bytes([header[0], header[1]]) != MAGIC
+        || u16::from_ne_bytes([header[2], header[3]]) != COMMITTED
+    {
+        return None;
+    }
+    let mut words = [0u32; DUMP_WORDS];
+    for (i, word) in words.iter_mut().enumerate() {
+        *word = read_word(flash, base + HEADER_LEN + 4 * i);
+    }
+    Some(CrashDump::from_words(words))
+}
+
+/// Copy the captured stack words of the dump in `region` into `buf`, returning how many were
+/// copied
+pub fn read_crash_stack(
+    flash: &impl Read<NativeType = u8>,
+    region: &Region,
+    buf: &mut [u32],
+) -> usize {
+    let dump = match read_crash(flash, region) {
+        Some(dump) => dump,
+        None => return 0,
+    };
+    let len = (dump.stack_words as usize).min(buf.len());
+    let stack = region.start_address() + HEADER_LEN + DUMP_LEN;
+    for (i, word) in buf[..len].iter_mut().enumerate() {
+        *word = read_word(flash, stack + 4 * i);
+    }
+    len
+}
+
+/// Erase the dump after it has been offloaded
+pub fn clear_crash(flash: &mut UnlockedFlash, region: &Region) -> Result {
+    flash.erase_region(region)
+}
+
+#[cfg(not(any(feature = "stm32f1", feature = "stm32f3")))]
+fn fault_status() -> (u32, u32, u32, u32) {
+    (0, 0, 0, 0)
+}
+
+#[cfg(any(feature = "stm32f1", feature = "stm32f3"))]
+fn fault_status() -> (u32, u32, u32, u32) {
+    let read = |address: usize| unsafe { (address as *const u32).read_volatile() };
+    (
+        read(0xe000_ed28),
+        read(0xe000_ed2c),
+        read(0xe000_ed34),
+        read(0xe000_ed38),
+    )
+}
+
+fn write_word(flash: &mut UnlockedFlash, address: usize, word: u32) -> Result {
+    let bytes = word.to_ne_bytes();
+    flash.write_native(
+        address,
+        &[
+            u16::from_ne_bytes([bytes[0], bytes[1]]),
+            u16::from_ne_bytes([bytes[2], bytes[3]]),
+        ],
+    )
+}
+
+fn read_word(flash: &impl Read<NativeType = u8>, address: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    flash.read(address, &mut bytes);
+    u32::from_ne_bytes(bytes)
+}
@@ -0,0 +1,15 @@
+//! Interrupt-free sections.
+//!
+//! With the `critical-section` feature these go through `critical_section::with`, so any
+//! critical-section implementation (including host-side ones) can be plugged in. Otherwise
+//! interrupts are masked directly with `cortex_m::interrupt::free`.
+
+#[cfg(feature = "critical-section")]
+pub(crate) fn free<R>(f: impl FnOnce() -> R) -> R {
+    critical_section::with(|_| f())
+}
+
+#[cfg(not(feature = "critical-section"))]
+pub(crate) fn free<R>(f: impl FnOnce() -> R) -> R {
+    cortex_m::interrupt::free(|_| f())
+}
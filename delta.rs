@@ -0,0 +1,150 @@
+//! Binary delta updates, rebuilding a new image from the current one and a small patch.
+//!
+//! The patch is a bsdiff style stream of little endian fields, without compression:
+//!
+//! ```text
+//! magic (u32) | new length (u32) | entries...
+//! entry: diff length (u32) | extra length (u32) | seek (i32) | diff bytes | extra bytes
+//! ```
+//!
+//! Every entry adds its diff bytes to as many bytes of the old image, continuing where the last
+//! entry left off, copies its extra bytes unchanged and then moves the position in the old
+//! image by `seek`. Patches are made between the whole contents of two slots, header included,
+//! so the result can be checked with `validate_image` before it is marked pending.
+
+use crate::{Error, ImageWriter, Read, Region, UnlockedFlash};
+
+/// Marks a patch
+pub const PATCH_MAGIC: u32 = 0x3144_5342;
+
+const CHUNK_LEN: usize = 64;
+
+/// Apply the patch stored in `patch` to the old image in `source`, writing the new image with
+/// `writer`, and return the length of the new image.
+///
+/// `writer` has to point at a slot other than `source`, which is still read while the new
+/// image is written. `Error::InvalidPatch` if the patch is malformed or reaches outside of the
+/// old image or the patch region.
+pub fn apply_patch(
+    flash: &mut UnlockedFlash,
+    patch: &Region,
+    source: &Region,
+    writer: &mut ImageWriter,
+) -> core::result::Result<usize, Error> {
+    let mut reader = PatchReader {
+        region: patch,
+        offset: 0,
+    };
+    if reader.u32(flash)? != PATCH_MAGIC {
+        return Err(Error::InvalidPatch);
+    }
+    let new_len = reader.u32(flash)? as usize;
+    let start = writer.written();
+    let mut old = 0usize;
+    let mut buf = [0u8; CHUNK_LEN];
+    let mut old_buf = [0u8; CHUNK_LEN];
+    while writer.written() - start < new_len {
+        let diff_len = reader.u32(flash)? as usize;
+        let extra_len = reader.u32(flash)? as usize;
+        let seek = reader.u32(flash)? as i32;
+        let produced = writer.written() - start;
+        if !entry_fits(diff_len, extra_len, new_len - produced, old, source.len()) {
+            return Err(Error::InvalidPatch);
+        }
+
+        let mut done = 0;
+        while done < diff_len {
+            let chunk = CHUNK_LEN.min(diff_len - done);
+            reader.read(flash, &mut buf[..chunk])?;
+            flash.read(source.start_address() + old + done, &mut old_buf[..chunk]);
+            for (byte, old_byte) in buf[..chunk].iter_mut().zip(old_buf.iter()) {
+                *byte = byte.wrapping_add(*old_byte);
+            }
+            writer.write(flash, &buf[..chunk])?;
+            done += chunk;
+        }
+        old += diff_len;
+
+        let mut done = 0;
+        while done < extra_len {
+            let chunk = CHUNK_LEN.min(extra_len - done);
+            reader.read(flash, &mut buf[..chunk])?;
+            writer.write(flash, &buf[..chunk])?;
+            done += chunk;
+        }
+
+        let moved = old as i64 + seek as i64;
+        if moved < 0 || moved as usize > source.len() {
+            return Err(Error::InvalidPatch);
+        }
+        old = moved as usize;
+    }
+    Ok(new_len)
+}
+
+/// Whether an entry produces no more than the `remaining` bytes of the new image and its diff
+/// stays inside the old image of `source_len` bytes when applied at `old`
+fn entry_fits(
+    diff_len: usize,
+    extra_len: usize,
+    remaining: usize,
+    old: usize,
+    source_len: usize,
+) -> bool {
+    match (diff_len.checked_add(extra_len), old.checked_add(diff_len)) {
+        (Some(produced), Some(old_end)) => produced <= remaining && old_end <= source_len,
+        _ => false,
+    }
+}
+
+/// Sequential reads from the patch region
+struct PatchReader<'a> {
+    region: &'a Region,
+    offset: usize,
+}
+
+impl PatchReader<'_> {
+    fn read(&mut self, flash: &UnlockedFlash, buf: &mut [u8]) -> crate::Result {
+        let end = self.offset.checked_add(buf.len()).ok_or(Error::InvalidPatch)?;
+        if end > self.region.len() {
+            return Err(Error::InvalidPatch);
+        }
+        flash.read(self.region.start_address() + self.offset, buf);
+        self.offset += buf.len();
+        Ok(())
+    }
+
+    fn u32(&mut self, flash: &UnlockedFlash) -> core::result::Result<u32, Error> {
+        let mut bytes = [0u8; 4];
+        self.read(flash, &mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_inside_both_images_fits() {
+        assert!(entry_fits(10, 6, 16, 0, 10));
+        assert!(entry_fits(0, 4, 4, 100, 100));
+    }
+
+    #[test]
+    fn entry_past_the_new_image_is_refused() {
+        assert!(!entry_fits(10, 7, 16, 0, 10));
+    }
+
+    #[test]
+    fn diff_past_the_old_image_is_refused() {
+        assert!(!entry_fits(10, 0, 16, 1, 10));
+    }
+
+    #[test]
+    fn overflowing_lengths_are_refused() {
+        assert!(!entry_fits(usize::MAX, 1, usize::MAX, 0, usize::MAX));
+        assert!(!entry_fits(1, usize::MAX, usize::MAX, 0, usize::MAX));
+        assert!(!entry_fits(2, 0, usize::MAX, usize::MAX - 1, usize::MAX));
+    }
+}
@@ -0,0 +1,46 @@
+use crate::{DetailedError, Error, FlashPage, UnlockedFlash, WriteErase, FLASH_START};
+
+impl UnlockedFlash {
+    /// Address of the halfword or page the last failed operation stopped at
+    pub fn fault_address(&self) -> Option<usize> {
+        self.fault_address
+    }
+
+    /// `erase_page` reporting the failing page
+    pub fn erase_page_detailed(
+        &mut self,
+        page: FlashPage,
+    ) -> core::result::Result<(), DetailedError> {
+        self.erase_page(page).map_err(|error| DetailedError {
+            error,
+            address: Some(page.to_address()),
+            page: Some(page),
+            offset: None,
+        })
+    }
+
+    /// `write` reporting the failing address and how far into `data` it got
+    pub fn write_detailed(
+        &mut self,
+        address: usize,
+        data: &[u8],
+    ) -> core::result::Result<(), DetailedError> {
+        self.write(address, data).map_err(|error| self.detail(error, Some(address)))
+    }
+
+    /// Attach the recorded fault address to `error`, with the offset relative to `start`
+    pub(crate) fn detail(&self, error: Error, start: Option<usize>) -> DetailedError {
+        let address = self.fault_address;
+        DetailedError {
+            error,
+            address,
+            page: address
+                .filter(|a| *a >= FLASH_START)
+                .map(FlashPage::from_address),
+            offset: match (address, start) {
+                (Some(a), Some(s)) => Some(a.saturating_sub(s)),
+                _ => None,
+            },
+        }
+    }
+}
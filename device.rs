@@ -0,0 +1,75 @@
+//! Factory information stored in system memory.
+
+use crate::{FLASH_START, PAGE_SIZE};
+
+/// Address of the 96 bit unique device ID
+#[cfg(not(feature = "stm32f1"))]
+pub const UID_ADDRESS: usize = 0x1fff_f7ac;
+/// Address of the 96 bit unique device ID
+#[cfg(feature = "stm32f1")]
+pub const UID_ADDRESS: usize = 0x1fff_f7e8;
+
+/// Address of the flash size register, holding the main flash size in KB
+#[cfg(not(feature = "stm32f1"))]
+pub const FLASHSIZE_ADDRESS: usize = 0x1fff_f7cc;
+/// Address of the flash size register, holding the main flash size in KB
+#[cfg(feature = "stm32f1")]
+pub const FLASHSIZE_ADDRESS: usize = 0x1fff_f7e0;
+
+/// Address of TS_CAL1, the temperature sensor reading at 30 °C and VDDA = 3.3 V
+#[cfg(not(feature = "stm32f1"))]
+pub const TS_CAL1_ADDRESS: usize = 0x1fff_f7b8;
+/// Address of VREFINT_CAL, the internal reference reading at 30 °C and VDDA = 3.3 V
+#[cfg(not(feature = "stm32f1"))]
+pub const VREFINT_CAL_ADDRESS: usize = 0x1fff_f7ba;
+/// Address of TS_CAL2, the temperature sensor reading at 110 °C and VDDA = 3.3 V
+#[cfg(not(feature = "stm32f1"))]
+pub const TS_CAL2_ADDRESS: usize = 0x1fff_f7c2;
+
+/// Main flash size in KB as programmed by ST
+pub fn flash_size_kb() -> u16 {
+    unsafe { (FLASHSIZE_ADDRESS as *const u16).read_volatile() }
+}
+
+/// Number of flash pages on this device
+pub fn num_pages() -> usize {
+    flash_size_kb() as usize * 1024 / PAGE_SIZE as usize
+}
+
+/// Address one past the last byte of main flash
+pub fn flash_end() -> usize {
+    FLASH_START + flash_size_kb() as usize * 1024
+}
+
+/// The 96 bit unique device ID, lowest address first
+pub fn device_uid() -> [u8; 12] {
+    let mut uid = [0u8; 12];
+    let mut address = UID_ADDRESS as *const u8;
+    for byte in &mut uid {
+        unsafe {
+            *byte = address.read_volatile();
+            address = address.add(1);
+        }
+    }
+    uid
+}
+
+/// Factory VREFINT calibration, the raw 12 bit ADC reading of VREFINT at VDDA = 3.3 V
+#[cfg(not(feature = "stm32f1"))]
+pub fn vrefint_cal() -> u16 {
+    unsafe { (VREFINT_CAL_ADDRESS as *const u16).read_volatile() }
+}
+
+/// Factory temperature sensor calibration at 30 °C, the raw 12 bit ADC reading at VDDA = 3.3 V
+#[cfg(not(feature = "stm32f1"))]
+pub fn ts_cal1() -> u16 {
+    unsafe { (TS_CAL1_ADDRESS as *const u16).read_volatile() }
+}
+
+/// Factory temperature sensor calibration at 110 °C, the raw 12 bit ADC reading at VDDA = 3.3 V.
+///
+/// Not programmed on the F030/F070 value line, where this reads back as erased.
+#[cfg(not(feature = "stm32f1"))]
+pub fn ts_cal2() -> u16 {
+    unsafe { (TS_CAL2_ADDRESS as *const u16).read_volatile() }
+}
@@ -0,0 +1,148 @@
+//! `usbd-dfu` back-end, for DfuSe downloads and uploads with dfu-util on the USB parts
+//! (F042, F048, F072, and the USB parts of the F1 and F3).
+//!
+//! The memory exposed over DFU is one slot, fixed at compile time through `DfuSlot` because
+//! `DFUMemIO` takes its address pointer and layout string as associated constants. Erases and
+//! writes outside of the slot are refused with `DFUMemError::Address`. Writes go straight to
+//! flash, so the host has to erase first, which dfu-util does for DfuSe targets.
+//!
+//! The poll timeouts reported to the host cover the worst case of the datasheets: 40 ms per
+//! page erase and 70 µs per halfword. A mass erase erases the slot page by page and reports
+//! the sum.
+
+use usbd_dfu::{DFUManifestationError, DFUMemError, DFUMemIO};
+
+use crate::{Error, FlashPage, Read, Region, UnlockedFlash, WriteErase, FLASH_START};
+
+/// Worst case page erase time
+const PAGE_ERASE_TIME_MS: u32 = 40;
+/// Bytes per download and upload block
+const TRANSFER_SIZE: usize = 128;
+/// Worst case time for programming one block, at 70 µs per halfword
+const PROGRAM_TIME_MS: u32 = (TRANSFER_SIZE as u32 / 2 * 70 + 999) / 1000;
+
+/// Slot exposed over DFU
+pub trait DfuSlot {
+    const REGION: Region;
+    /// DfuSe layout string of `REGION`, such as `"@Flash/0x08004000/48*001Ka"` for 48 writable
+    /// pages of 1 KB starting at 0x0800_4000
+    const MEM_INFO: &'static str;
+}
+
+/// `DFUMemIO` over the slot `S`
+pub struct DfuFlash<S: DfuSlot> {
+    flash: UnlockedFlash,
+    buf: [u8; TRANSFER_SIZE],
+    len: usize,
+    manifested: bool,
+    slot: core::marker::PhantomData<S>,
+}
+
+impl<S: DfuSlot> DfuFlash<S> {
+    const SLOT_IN_FLASH: () = assert!(
+        S::REGION.start_address() >= FLASH_START && !S::REGION.is_empty(),
+        "DFU slot must be a non-empty flash region"
+    );
+
+    pub fn new(flash: UnlockedFlash) -> Self {
+        let () = Self::SLOT_IN_FLASH;
+        DfuFlash {
+            flash,
+            buf: [0u8; TRANSFER_SIZE],
+            len: 0,
+            manifested: false,
+            slot: core::marker::PhantomData,
+        }
+    }
+
+    /// Whether a download has been completed since the last call, so the new image can be
+    /// checked and booted. Clears the flag.
+    pub fn take_manifested(&mut self) -> bool {
+        core::mem::replace(&mut self.manifested, false)
+    }
+
+    pub fn into_inner(self) -> UnlockedFlash {
+        self.flash
+    }
+}
+
+impl<S: DfuSlot> DFUMemIO for DfuFlash<S> {
+    const INITIAL_ADDRESS_POINTER: u32 = S::REGION.start_address() as u32;
+    const PROGRAM_TIME_MS: u32 = PROGRAM_TIME_MS;
+    const ERASE_TIME_MS: u32 = PAGE_ERASE_TIME_MS;
+    const FULL_ERASE_TIME_MS: u32 = PAGE_ERASE_TIME_MS * S::REGION.pages as u32;
+    const MEM_INFO_STRING: &'static str = S::MEM_INFO;
+    const HAS_DOWNLOAD: bool = true;
+    const HAS_UPLOAD: bool = true;
+    const MANIFESTATION_TOLERANT: bool = true;
+    const TRANSFER_SIZE: u16 = TRANSFER_SIZE as u16;
+
+    fn read(&mut self, address: u32, length: usize) -> Result<&[u8], DFUMemError> {
+        let address = address as usize;
+        if !S::REGION.contains(address) {
+            return Err(DFUMemError::Address);
+        }
+        // Uploads end with a short block at the end of the slot
+        let len = length
+            .min(self.buf.len())
+            .min(S::REGION.end_address() - address);
+        self.flash.read(address, &mut self.buf[..len]);
+        Ok(&self.buf[..len])
+    }
+
+    fn erase(&mut self, address: u32) -> Result<(), DFUMemError> {
+        let address = address as usize;
+        if !S::REGION.contains(address) {
+            return Err(DFUMemError::Address);
+        }
+        self.flash
+            .erase_page(FlashPage::from_address(address))
+            .map_err(erase_error)
+    }
+
+    fn erase_all(&mut self) -> Result<(), DFUMemError> {
+        self.flash.erase_region(&S::REGION).map_err(erase_error)
+    }
+
+    fn store_write_buffer(&mut self, src: &[u8]) -> Result<(), ()> {
+        if src.len() > self.buf.len() {
+            return Err(());
+        }
+        self.buf[..src.len()].copy_from_slice(src);
+        self.len = src.len();
+        Ok(())
+    }
+
+    fn program(&mut self, address: u32, length: usize) -> Result<(), DFUMemError> {
+        let address = address as usize;
+        if length > self.len
+            || !S::REGION.contains(address)
+            || address + length > S::REGION.end_address()
+        {
+            return Err(DFUMemError::Address);
+        }
+        self.flash
+            .write(address, &self.buf[..length])
+            .map_err(|error| match error {
+                Error::WriteProtectionError | Error::SoftProtected | Error::FirmwareRegion => {
+                    DFUMemError::Write
+                }
+                Error::ProgrammingError => DFUMemError::CheckErased,
+                _ => DFUMemError::Prog,
+            })
+    }
+
+    fn manifestation(&mut self) -> Result<(), DFUManifestationError> {
+        self.manifested = true;
+        Ok(())
+    }
+}
+
+fn erase_error(error: Error) -> DFUMemError {
+    match error {
+        Error::WriteProtectionError | Error::SoftProtected | Error::FirmwareRegion => {
+            DFUMemError::Write
+        }
+        _ => DFUMemError::Erase,
+    }
+}
@@ -0,0 +1,241 @@
+//! EEPROM emulation in two pages, following ST's AN4061.
+//!
+//! Each page starts with a status halfword and a reserved halfword, followed by 4 byte records
+//! of a value and its 16 bit virtual address. Writes append a record to the valid page. When it
+//! is full, the newest value of every variable is transferred to the other page, the full page is
+//! erased, and only then is the other page marked valid.
+//!
+//! The page status only ever moves from erased to receive to valid by programming, so a reset at
+//! any point leaves a state `Eeprom::init` can recover from.
+
+use crate::{Error, FlashPage, Read, Result, UnlockedFlash, WriteErase, ERASED_BYTE, PAGE_SIZE};
+
+const ERASED: u16 = 0xffff;
+const RECEIVE_DATA: u16 = 0xeeee;
+const VALID_PAGE: u16 = 0x0000;
+
+const HEADER_LEN: usize = 4;
+const RECORD_LEN: usize = 4;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum PageStatus {
+    Erased,
+    Receive,
+    Valid,
+    Invalid,
+}
+
+/// What `Eeprom::init` does for a pair of page states
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Recovery {
+    /// `active` is valid, the other page only has to be erased
+    Erase { active: usize },
+    /// The transfer to `active` finished, only its status update was lost
+    MarkValid { active: usize },
+    /// The transfer from `old` was interrupted, redo it
+    Transfer { old: usize },
+    Format,
+}
+
+fn recovery(status: [PageStatus; 2]) -> Recovery {
+    match status {
+        [PageStatus::Valid, PageStatus::Erased] => Recovery::Erase { active: 0 },
+        [PageStatus::Erased, PageStatus::Valid] => Recovery::Erase { active: 1 },
+        [PageStatus::Receive, PageStatus::Erased] => Recovery::MarkValid { active: 0 },
+        [PageStatus::Erased, PageStatus::Receive] => Recovery::MarkValid { active: 1 },
+        [PageStatus::Valid, PageStatus::Receive] => Recovery::Transfer { old: 0 },
+        [PageStatus::Receive, PageStatus::Valid] => Recovery::Transfer { old: 1 },
+        _ => Recovery::Format,
+    }
+}
+
+/// Variables of 16 bits addressed by a 16 bit virtual address, stored in two flash pages.
+///
+/// Virtual address `0xffff` is reserved, it can't be told apart from erased flash.
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Eeprom {
+    pages: [FlashPage; 2],
+    active: usize,
+}
+
+impl Eeprom {
+    /// Open the EEPROM in `pages`, finishing an interrupted transfer or formatting the pages if
+    /// their headers make no sense.
+    pub fn init(
+        flash: &mut UnlockedFlash,
+        pages: [FlashPage; 2],
+    ) -> core::result::Result<Self, Error> {
+        let mut eeprom = Eeprom { pages, active: 0 };
+        let status = [eeprom.status(flash, 0), eeprom.status(flash, 1)];
+
+        match recovery(status) {
+            Recovery::Erase { active } => {
+                eeprom.active = active;
+                eeprom.ensure_erased(flash, 1 - active)?;
+            }
+            Recovery::MarkValid { active } => {
+                eeprom.active = active;
+                eeprom.set_status(flash, active, VALID_PAGE)?;
+            }
+            Recovery::Transfer { old } => eeprom.finish_transfer(flash, old)?,
+            Recovery::Format => eeprom.format(flash)?,
+        }
+        Ok(eeprom)
+    }
+
+    /// Erase both pages and start over without any variables
+    pub fn format(&mut self, flash: &mut UnlockedFlash) -> Result {
+        self.ensure_erased(flash, 0)?;
+        self.ensure_erased(flash, 1)?;
+        self.set_status(flash, 0, VALID_PAGE)?;
+        self.active = 0;
+        Ok(())
+    }
+
+    /// Newest value of `address`, `None` if it was never written
+    pub fn read(&self, flash: &UnlockedFlash, address: u16) -> Option<u16> {
+        self.find(flash, self.active, address)
+    }
+
+    /// Store `value` for `address`, transferring to the other page when the valid one is full
+    pub fn write(&mut self, flash: &mut UnlockedFlash, address: u16, value: u16) -> Result {
+        if address == ERASED {
+            return Err(Error::OutOfBounds);
+        }
+        if self.read(flash, address) == Some(value) {
+            return Ok(());
+        }
+        match self.free_offset(flash, self.active) {
+            Some(offset) => self.write_record(flash, self.active, offset, address, value),
+            None => self.transfer(flash, address, value),
+        }
+    }
+
+    fn transfer(&mut self, flash: &mut UnlockedFlash, address: u16, value: u16) -> Result {
+        let old = self.active;
+        let new = 1 - old;
+        self.ensure_erased(flash, new)?;
+        self.set_status(flash, new, RECEIVE_DATA)?;
+        self.write_record(flash, new, HEADER_LEN, address, value)?;
+        self.finish_transfer(flash, old)
+    }
+
+    /// Copy every variable from the valid page `old` that the receiving page doesn't hold yet,
+    /// erase `old`, then make the receiving page the valid one
+    ///
+    /// Erasing first means a reset never leaves two valid pages, only a receiving page next to
+    /// an erased one, which `init` marks valid.
+    fn finish_transfer(&mut self, flash: &mut UnlockedFlash, old: usize) -> Result {
+        let new = 1 - old;
+        let mut offset = PAGE_SIZE as usize;
+        while offset > HEADER_LEN {
+            offset -= RECORD_LEN;
+            let (address, value) = self.record(flash, old, offset);
+            if address == ERASED || self.find(flash, new, address).is_some() {
+                continue;
+            }
+            let free = self.free_offset(flash, new).ok_or(Error::RegionFull)?;
+            self.write_record(flash, new, free, address, value)?;
+        }
+        flash.erase_page(self.pages[old])?;
+        self.set_status(flash, new, VALID_PAGE)?;
+        self.active = new;
+        Ok(())
+    }
+
+    fn find(&self, flash: &UnlockedFlash, page: usize, address: u16) -> Option<u16> {
+        let end = self.free_offset(flash, page).unwrap_or(PAGE_SIZE as usize);
+        let mut offset = end;
+        while offset > HEADER_LEN {
+            offset -= RECORD_LEN;
+            let (record_address, value) = self.record(flash, page, offset);
+            if record_address == address {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    /// Offset of the first free record in `page`, `None` if the page is full
+    fn free_offset(&self, flash: &UnlockedFlash, page: usize) -> Option<usize> {
+        (HEADER_LEN..PAGE_SIZE as usize)
+            .step_by(RECORD_LEN)
+            .find(|offset| self.record(flash, page, *offset) == (ERASED, ERASED))
+    }
+
+    fn record(&self, flash: &UnlockedFlash, page: usize, offset: usize) -> (u16, u16) {
+        let base = self.pages[page].to_address() + offset;
+        (flash.read_halfword(base + 2), flash.read_halfword(base))
+    }
+
+    fn write_record(
+        &self,
+        flash: &mut UnlockedFlash,
+        page: usize,
+        offset: usize,
+        address: u16,
+        value: u16,
+    ) -> Result {
+        let base = self.pages[page].to_address() + offset;
+        // The address goes last, a record without one is skipped
+        flash.write_native(base, &[value])?;
+        flash.write_native(base + 2, &[address])
+    }
+
+    fn status(&self, flash: &UnlockedFlash, page: usize) -> PageStatus {
+        match flash.read_halfword(self.pages[page].to_address()) {
+            ERASED => PageStatus::Erased,
+            RECEIVE_DATA => PageStatus::Receive,
+            VALID_PAGE => PageStatus::Valid,
+            _ => PageStatus::Invalid,
+        }
+    }
+
+    fn set_status(&self, flash: &mut UnlockedFlash, page: usize, status: u16) -> Result {
+        flash.write_native(self.pages[page].to_address(), &[status])
+    }
+
+    fn ensure_erased(&self, flash: &mut UnlockedFlash, page: usize) -> Result {
+        let mut buf = [0u8; 64];
+        let start = self.pages[page].to_address();
+        for address in (start..start + PAGE_SIZE as usize).step_by(buf.len()) {
+            flash.read(address, &mut buf);
+            if buf.iter().any(|b| *b != ERASED_BYTE) {
+                return flash.erase_page(self.pages[page]);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Page states after each step of a transfer from page 0 to page 1
+    const TRANSFER: [[PageStatus; 2]; 4] = [
+        [PageStatus::Valid, PageStatus::Erased],
+        [PageStatus::Valid, PageStatus::Receive],
+        [PageStatus::Erased, PageStatus::Receive],
+        [PageStatus::Erased, PageStatus::Valid],
+    ];
+
+    #[test]
+    fn reset_after_old_page_erased() {
+        assert_eq!(recovery(TRANSFER[2]), Recovery::MarkValid { active: 1 });
+    }
+
+    #[test]
+    fn reset_during_transfer_never_formats() {
+        for status in TRANSFER {
+            assert_ne!(recovery(status), Recovery::Format, "{:?}", status);
+        }
+        assert_eq!(recovery(TRANSFER[1]), Recovery::Transfer { old: 0 });
+    }
+
+    #[test]
+    fn two_valid_pages_format() {
+        assert_eq!(recovery([PageStatus::Valid, PageStatus::Valid]), Recovery::Format);
+    }
+}
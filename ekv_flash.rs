@@ -0,0 +1,94 @@
+//! ekv backend.
+//!
+//! ekv reads its geometry from build time environment variables. Build it with
+//! `EKV_PAGE_SIZE` set to `PAGE_SIZE`, an even `EKV_WRITE_SIZE` and the default erase value
+//! of `0xff`, anything else is rejected at compile time.
+
+use ekv::flash::{Flash, PageID};
+
+use crate::{Error, Read, Region, UnlockedFlash, WriteErase, ERASED_BYTE, PAGE_SIZE};
+
+const _: () = assert!(
+    ekv::config::PAGE_SIZE == PAGE_SIZE as usize,
+    "EKV_PAGE_SIZE must be the flash page size"
+);
+const _: () = assert!(
+    ekv::config::WRITE_SIZE % 2 == 0,
+    "EKV_WRITE_SIZE must be a multiple of the 2 byte halfword"
+);
+const _: () = assert!(
+    ekv::config::ERASE_VALUE == ERASED_BYTE,
+    "EKV_ERASE_VALUE must be 0xff"
+);
+
+/// ekv `Flash` over the pages of `region`, ekv page `n` is page `n` of the region
+pub struct EkvFlash {
+    flash: UnlockedFlash,
+    region: Region,
+}
+
+impl EkvFlash {
+    pub fn new(flash: UnlockedFlash, region: Region) -> Self {
+        EkvFlash { flash, region }
+    }
+
+    pub fn region(&self) -> &Region {
+        &self.region
+    }
+
+    pub fn into_inner(self) -> UnlockedFlash {
+        self.flash
+    }
+
+    /// Address of `offset` bytes into ekv page `page_id`, checking that `len` bytes fit the page
+    fn address(
+        &self,
+        page_id: PageID,
+        offset: usize,
+        len: usize,
+    ) -> core::result::Result<usize, Error> {
+        if page_id.index() >= self.region.pages {
+            return Err(Error::PageOutOfRange);
+        }
+        match offset.checked_add(len) {
+            Some(end) if end <= PAGE_SIZE as usize => {
+                Ok(self.region.page(page_id.index()).to_address() + offset)
+            }
+            _ => Err(Error::OutOfBounds),
+        }
+    }
+}
+
+impl Flash for EkvFlash {
+    type Error = Error;
+
+    fn page_count(&self) -> usize {
+        self.region.pages
+    }
+
+    async fn erase(&mut self, page_id: PageID) -> core::result::Result<(), Error> {
+        self.address(page_id, 0, 0)?;
+        self.flash.erase_page(self.region.page(page_id.index()))
+    }
+
+    async fn read(
+        &mut self,
+        page_id: PageID,
+        offset: usize,
+        data: &mut [u8],
+    ) -> core::result::Result<(), Error> {
+        let address = self.address(page_id, offset, data.len())?;
+        Read::read(&self.flash, address, data);
+        Ok(())
+    }
+
+    async fn write(
+        &mut self,
+        page_id: PageID,
+        offset: usize,
+        data: &[u8],
+    ) -> core::result::Result<(), Error> {
+        let address = self.address(page_id, offset, data.len())?;
+        self.flash.write_strict(address, data)
+    }
+}
@@ -0,0 +1,199 @@
+//! At-rest encryption of `KvStore` values and `Journal` records.
+//!
+//! Every value is stored as a 64 bit nonce followed by the value encrypted with a stream cipher
+//! under that nonce. Nonces come from a `PersistentCounter` that is incremented before the
+//! record is written, so no nonce is used twice, not even across resets.
+//!
+//! ```ignore
+//! let nonces = PersistentCounter::mount(&mut flash, NONCE_PAGES)?;
+//! let mut crypt = Encrypted::<_, 64>::new(ChaCha20Cipher::new(key), nonces);
+//! crypt.set(&mut flash, &mut store, KEY_PHONE, phone)?;
+//! ```
+//!
+//! The cipher only provides confidentiality; the stores check the integrity of the ciphertext
+//! with their CRC but an attacker with flash access could still modify it undetected.
+
+use crate::key_slot::zeroize;
+use crate::{
+    Checksum, Error, Journal, JournalRecord, KvStore, PersistentCounter, Result, UnlockedFlash,
+};
+
+const NONCE_LEN: usize = 8;
+
+/// Stream cipher encrypting and decrypting in place
+pub trait Cipher {
+    /// XOR the keystream for `nonce` into `buf`. Encrypting and decrypting are the same.
+    fn apply_keystream(&mut self, nonce: u64, buf: &mut [u8]);
+}
+
+/// ChaCha20 with a 256 bit key, the nonce in the last 8 bytes of the 96 bit IV
+#[cfg(feature = "chacha20")]
+pub struct ChaCha20Cipher {
+    key: [u8; 32],
+}
+
+#[cfg(feature = "chacha20")]
+impl ChaCha20Cipher {
+    pub fn new(key: [u8; 32]) -> Self {
+        ChaCha20Cipher { key }
+    }
+}
+
+#[cfg(feature = "chacha20")]
+impl Cipher for ChaCha20Cipher {
+    fn apply_keystream(&mut self, nonce: u64, buf: &mut [u8]) {
+        use chacha20::cipher::{KeyIvInit, StreamCipher};
+
+        let mut iv = [0u8; 12];
+        iv[4..].copy_from_slice(&nonce.to_le_bytes());
+        chacha20::ChaCha20::new(&self.key.into(), &iv.into()).apply_keystream(buf);
+    }
+}
+
+#[cfg(feature = "chacha20")]
+impl Drop for ChaCha20Cipher {
+    fn drop(&mut self) {
+        zeroize(&mut self.key);
+    }
+}
+
+/// AES-128 in counter mode, the nonce in the upper 8 bytes of the counter block
+#[cfg(feature = "aes")]
+pub struct Aes128CtrCipher {
+    key: [u8; 16],
+}
+
+#[cfg(feature = "aes")]
+impl Aes128CtrCipher {
+    pub fn new(key: [u8; 16]) -> Self {
+        Aes128CtrCipher { key }
+    }
+}
+
+#[cfg(feature = "aes")]
+impl Cipher for Aes128CtrCipher {
+    fn apply_keystream(&mut self, nonce: u64, buf: &mut [u8]) {
+        use aes::cipher::{KeyIvInit, StreamCipher};
+
+        let mut iv = [0u8; 16];
+        iv[..8].copy_from_slice(&nonce.to_be_bytes());
+        ctr::Ctr64BE::<aes::Aes128>::new(&self.key.into(), &iv.into()).apply_keystream(buf);
+    }
+}
+
+#[cfg(feature = "aes")]
+impl Drop for Aes128CtrCipher {
+    fn drop(&mut self) {
+        zeroize(&mut self.key);
+    }
+}
+
+/// Encrypting front end for the stores, for values of up to `N - 8` bytes
+pub struct Encrypted<C, const N: usize> {
+    cipher: C,
+    nonces: PersistentCounter,
+    buf: [u8; N],
+}
+
+impl<C: Cipher, const N: usize> Encrypted<C, N> {
+    pub fn new(cipher: C, nonces: PersistentCounter) -> Self {
+        Encrypted {
+            cipher,
+            nonces,
+            buf: [0u8; N],
+        }
+    }
+
+    /// Largest value that can be encrypted
+    pub fn max_value_len(&self) -> usize {
+        N.saturating_sub(NONCE_LEN)
+    }
+
+    /// Encrypt `value` and store it as `key`
+    pub fn set(
+        &mut self,
+        flash: &mut UnlockedFlash,
+        store: &mut KvStore<impl Checksum>,
+        key: u16,
+        value: &[u8],
+    ) -> Result {
+        let len = self.seal(flash, value)?;
+        let result = store.set(flash, key, &self.buf[..len]);
+        zeroize(&mut self.buf);
+        result
+    }
+
+    /// Decrypt the value of `key` into `buf` and return its length, `None` if the key isn't set
+    pub fn get(
+        &mut self,
+        flash: &UnlockedFlash,
+        store: &KvStore<impl Checksum>,
+        key: u16,
+        buf: &mut [u8],
+    ) -> core::result::Result<Option<usize>, Error> {
+        match store.get(flash, key, &mut self.buf)? {
+            Some(len) => self.open(len, buf).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Encrypt `data` and append it to `journal`
+    pub fn append(
+        &mut self,
+        flash: &mut UnlockedFlash,
+        journal: &mut Journal<impl Checksum>,
+        data: &[u8],
+    ) -> Result {
+        let len = self.seal(flash, data)?;
+        let result = journal.append(flash, &self.buf[..len]);
+        zeroize(&mut self.buf);
+        result
+    }
+
+    /// Decrypt `record` into `buf` and return its length
+    pub fn read(
+        &mut self,
+        flash: &UnlockedFlash,
+        record: &JournalRecord,
+        buf: &mut [u8],
+    ) -> core::result::Result<usize, Error> {
+        if record.len() > N {
+            return Err(Error::InvalidLength);
+        }
+        let len = record.read(flash, &mut self.buf);
+        self.open(len, buf)
+    }
+
+    /// Encrypt `data` into the buffer behind a fresh nonce, returning the sealed length
+    fn seal(
+        &mut self,
+        flash: &mut UnlockedFlash,
+        data: &[u8],
+    ) -> core::result::Result<usize, Error> {
+        if data.len() > self.max_value_len() {
+            return Err(Error::InvalidLength);
+        }
+        let nonce = self.nonces.value();
+        self.nonces.increment(flash)?;
+        self.buf[..NONCE_LEN].copy_from_slice(&nonce.to_ne_bytes());
+        let len = NONCE_LEN + data.len();
+        self.buf[NONCE_LEN..len].copy_from_slice(data);
+        self.cipher.apply_keystream(nonce, &mut self.buf[NONCE_LEN..len]);
+        Ok(len)
+    }
+
+    /// Decrypt the `len` sealed bytes in the buffer into `out`, returning the plaintext length
+    fn open(&mut self, len: usize, out: &mut [u8]) -> core::result::Result<usize, Error> {
+        if len < NONCE_LEN || out.len() < len - NONCE_LEN {
+            zeroize(&mut self.buf);
+            return Err(Error::InvalidLength);
+        }
+        let mut nonce = [0u8; NONCE_LEN];
+        nonce.copy_from_slice(&self.buf[..NONCE_LEN]);
+        let out = &mut out[..len - NONCE_LEN];
+        out.copy_from_slice(&self.buf[NONCE_LEN..len]);
+        zeroize(&mut self.buf);
+        self.cipher.apply_keystream(u64::from_ne_bytes(nonce), out);
+        Ok(out.len())
+    }
+}
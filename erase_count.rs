@@ -0,0 +1,91 @@
+//! Erase counts of the pages of a region, kept in a `KvStore` keyed by page number.
+//!
+//! ```ignore
+//! let mut counters = EraseCounters::mount(&mut flash, METADATA_REGION, DATA_REGION)?;
+//! counters.erase_page(&mut flash, FlashPage(40))?;
+//! let worst = counters.max(&flash);
+//! ```
+//!
+//! The count is stored before the page is erased, so a reset in between counts an erase that
+//! didn't happen rather than missing one. Erases of the metadata pages themselves aren't
+//! counted.
+
+use crate::{Error, FlashPage, KvStore, Region, Result, UnlockedFlash, WriteErase};
+
+/// Erase counters for the pages of a tracked region
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EraseCounters {
+    store: KvStore,
+    tracked: Region,
+}
+
+impl EraseCounters {
+    /// Open the counters kept in `metadata` for the pages of `tracked`. The regions must not
+    /// overlap.
+    pub fn mount(
+        flash: &mut UnlockedFlash,
+        metadata: Region,
+        tracked: Region,
+    ) -> core::result::Result<Self, Error> {
+        if metadata.contains(tracked.start_address())
+            || tracked.contains(metadata.start_address())
+        {
+            return Err(Error::RegionOverlap);
+        }
+        Ok(EraseCounters {
+            store: KvStore::mount(flash, metadata)?,
+            tracked,
+        })
+    }
+
+    /// Count and erase `page`, which must be one of the tracked pages
+    pub fn erase_page(&mut self, flash: &mut UnlockedFlash, page: FlashPage) -> Result {
+        if !self.tracked.contains(page.to_address()) {
+            return Err(Error::OutOfBounds);
+        }
+        let count = self.erase_count(flash, page).saturating_add(1);
+        self.store.set(flash, page.0 as u16, &count.to_ne_bytes())?;
+        flash.erase_page(page)
+    }
+
+    /// Count and erase every tracked page
+    pub fn erase_region(&mut self, flash: &mut UnlockedFlash) -> Result {
+        for index in 0..self.tracked.pages {
+            self.erase_page(flash, self.tracked.page(index))?;
+        }
+        Ok(())
+    }
+
+    /// Number of times `page` has been erased through the counters
+    pub fn erase_count(&self, flash: &UnlockedFlash, page: FlashPage) -> u32 {
+        let mut count = [0u8; 4];
+        match self.store.get(flash, page.0 as u16, &mut count) {
+            Ok(Some(4)) => u32::from_ne_bytes(count),
+            _ => 0,
+        }
+    }
+
+    /// Sum of the erase counts of all tracked pages
+    pub fn total(&self, flash: &UnlockedFlash) -> u64 {
+        (0..self.tracked.pages)
+            .map(|index| self.erase_count(flash, self.tracked.page(index)) as u64)
+            .sum()
+    }
+
+    /// The most erased tracked page and its count
+    pub fn max(&self, flash: &UnlockedFlash) -> (FlashPage, u32) {
+        (0..self.tracked.pages)
+            .map(|index| {
+                let page = self.tracked.page(index);
+                (page, self.erase_count(flash, page))
+            })
+            .fold((self.tracked.start, 0), |max, (page, count)| {
+                if count > max.1 {
+                    (page, count)
+                } else {
+                    max
+                }
+            })
+    }
+}
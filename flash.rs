@@ -1,55 +1,446 @@
 use core::mem;
-use cortex_m::interrupt;
-use stm32f0xx_hal::stm32::FLASH;
 
-pub use traits::{Error, FlashPage, Read, Result, WriteErase};
-
-mod traits:
+use regs::{
+    FlashRegisters, CR_LOCK, CR_PER, CR_PG, CR_STRT, SR_BSY, SR_EOP, SR_PGERR, SR_WRPRT,
+};
+
+pub use an3155::SerialBootloader;
+#[cfg(feature = "async")]
+pub use async_shared::AsyncSharedFlash;
+pub use bad_pages::BadPages;
+#[cfg(feature = "embassy-boot")]
+pub use boot::{BootLayout, BootPartition};
+pub use boot_history::{clear_reset_flags, reset_cause, BootHistory, BootRecord, ResetCause};
+pub use can_boot::{CanBootloader, CanStatus};
+pub use checksum::{Checksum, Crc16, Crc32, Fletcher16, Fletcher32};
+#[cfg(feature = "postcard")]
+pub use config_cell::ConfigCell;
+pub use counter::PersistentCounter;
+pub use crash_dump::{
+    capture_crash, clear_crash, read_crash, read_crash_stack, CrashDump, MAX_STACK_WORDS,
+};
+pub use delta::{apply_patch, PATCH_MAGIC};
+pub use device::{device_uid, flash_end, flash_size_kb};
+#[cfg(not(feature = "stm32f1"))]
+pub use device::{ts_cal1, ts_cal2, vrefint_cal};
+#[cfg(feature = "usbd-dfu")]
+pub use dfu::{DfuFlash, DfuSlot};
+pub use eeprom::Eeprom;
+#[cfg(feature = "ekv")]
+pub use ekv_flash::EkvFlash;
+#[cfg(feature = "aes")]
+pub use encrypted::Aes128CtrCipher;
+#[cfg(feature = "chacha20")]
+pub use encrypted::ChaCha20Cipher;
+pub use encrypted::{Cipher, Encrypted};
+pub use erase_count::EraseCounters;
+pub use frame_log::FrameLog;
+pub use golden::{boot_with_fallback, select_image, BootImage};
+pub use guard::FlashGuard;
+pub use handle::{ReadOnly, ReadOnlyRegion, ReadWrite, ReadWriteRegion, RegionHandle};
+pub use heatshrink::HeatshrinkWriter;
+#[cfg(all(feature = "hw-crc", not(feature = "stm32f1")))]
+pub use hw_crc::{CrcPeripheral, HardwareCrc};
+pub use i2c_boot::I2cBootloader;
+pub use image_header::{
+    parse_header, validate_image, write_header, ImageHeader, IMAGE_HEADER_LEN, IMAGE_MAGIC,
+    IMAGE_OFFSET,
+};
+pub use image_writer::ImageWriter;
+pub use intel_hex::IntelHex;
+pub use journal::{Journal, JournalRecord};
+pub use jump::boot_into;
+#[cfg(not(any(feature = "stm32f1", feature = "stm32f3")))]
+pub use jump::VECTOR_TABLE_LEN;
+pub use key_slot::{ct_eq, zeroize, Key, KeySlots};
+pub use kv::KvStore;
+pub use layout::{DefaultLayout, FlashLayout, Layout};
+#[cfg(feature = "littlefs")]
+pub use littlefs::LittleFsStorage;
+pub use mcuboot::{ImageTrailer, TrailerState, BOOT_MAGIC, TRAILER_ALIGN};
+#[cfg(feature = "bytemuck")]
+pub use mirrored::Mirrored;
+pub use option_bytes::{
+    read_option_bytes, IrreversibleToken, MassEraseAck, OptionByteSession, OptionBytes, RdpLevel,
+    UserOptions, UserOptionsWriter,
+};
+pub use page_cache::{FlushPolicy, PageCache};
+pub use panic_persist::{
+    clear_panic, persist_message, persist_panic, read_panic, MAX_PANIC_MESSAGE_LEN,
+};
+pub use partition_table::{Partition, PartitionTable, PARTITION_NAME_LEN, PARTITION_TABLE_MAGIC};
+pub use record_writer::RecordWriter;
+pub use regs::FLASH;
+pub use region::{AppendPolicy, Region, ERASED_BYTE};
+pub use ring_log::{LogRecord, RingLog};
+pub use rollback::RollbackCounter;
+pub use session::ProgrammingSession;
+pub use settings::{Migration, Schema, Settings};
+pub use shared::SharedFlash;
+pub use signature::{NoSignature, SignatureVerifier};
+pub use slot_manager::{Slot, SlotManager, SlotState};
+pub use slot_store::SlotStore;
+pub use spi_boot::{SpiBootloader, SPI_FILLER};
+pub use split::{FlashReader, FlashWriter};
+pub use srec::Srec;
+pub use status::DetailedStatus;
+#[cfg(feature = "telemetry")]
+pub use telemetry::{ErrorCounters, Telemetry};
+pub use text_log::TextLog;
+#[cfg(feature = "tickv")]
+pub use tickv_flash::TickvFlash;
+pub use tlv::{TlvRecord, TlvStore};
+pub use token::{RegionAllocator, RegionToken};
+pub use traits::{DetailedError, Error, FlashPage, Read, Result, WriteErase};
+pub use transaction::Transaction;
+pub use uf2::{
+    Uf2Writer, UF2_BLOCK_LEN, UF2_FAMILY_STM32F0, UF2_FAMILY_STM32F1, UF2_FAMILY_STM32F3,
+};
+pub use updater::{UpdateInfo, UpdateTransport, Updater};
+pub use wear::{BlockStorage, WearLeveler};
+pub use xmodem::{ByteTransport, ModemProtocol, ModemReceiver, CANCEL};
+
+mod an3155;
+#[cfg(feature = "async")]
+mod async_shared;
+mod bad_pages;
+#[cfg(feature = "embassy-boot")]
+mod boot;
+mod boot_history;
+mod can_boot;
+mod checksum;
+#[cfg(feature = "postcard")]
+mod config_cell;
+mod counter;
+mod crash_dump;
+mod cs;
+mod delta;
+mod detailed;
+mod device;
+#[cfg(feature = "usbd-dfu")]
+mod dfu;
+mod eeprom;
+#[cfg(feature = "ekv")]
+mod ekv_flash;
+mod encrypted;
+mod erase_count;
+mod frame_log;
+mod golden;
+mod guard;
+mod handle;
+mod heatshrink;
+#[cfg(all(feature = "hw-crc", not(feature = "stm32f1")))]
+mod hw_crc;
+mod i2c_boot;
+mod image_header;
+mod image_writer;
+mod intel_hex;
+mod journal;
+mod jump;
+mod key_slot;
+mod kv;
+mod layout;
+#[cfg(feature = "build")]
+pub mod layout_gen;
+#[cfg(feature = "littlefs")]
+mod littlefs;
+mod lockdown;
+mod mcuboot;
+#[cfg(feature = "bytemuck")]
+mod mirrored;
+#[cfg(feature = "embedded-storage")]
+mod nor_flash;
+mod option_bytes;
+mod page_cache;
+mod panic_persist;
+mod partition_table;
+mod protect;
+mod ram;
+mod record_writer;
+mod region;
+mod regs;
+mod ring_log;
+mod rollback;
+mod self_update;
+#[cfg(feature = "sequential-storage")]
+mod sequential;
+mod session;
+mod settings;
+mod shared;
+mod signature;
+mod slot_manager;
+mod slot_store;
+mod spi_boot;
+mod split;
+mod srec;
+mod status;
+#[cfg(feature = "telemetry")]
+mod telemetry;
+mod text_log;
+#[cfg(feature = "tickv")]
+mod tickv_flash;
+mod tlv;
+mod token;
+mod traits;
+mod transaction;
+#[cfg(feature = "bytemuck")]
+mod typed;
+mod uf2;
+mod update;
+mod updater;
+mod wear;
+mod xmodem;
 
 pub const FLASH_START: usize = 0x0800_0000;
 
-pub const PAGE_SIZE: u32 = 1024;
-pub const NUM_PAGES: u32 = 32; // our chip, others up to 64
-
-const FLASH_KEY1: u32 = 0x4567_0123;
-const FLASH_KEY2: u32 = 0xCDEF_89AB;
+// Flash size of the target, selected with exactly one `flash-*` feature
+const SELECTED_SIZES: u32 = cfg!(feature = "flash-16k") as u32
+    + cfg!(feature = "flash-32k") as u32
+    + cfg!(feature = "flash-64k") as u32
+    + cfg!(feature = "flash-128k") as u32
+    + cfg!(feature = "flash-256k") as u32;
+const _: () = assert!(
+    SELECTED_SIZES == 1,
+    "select exactly one of the flash-16k, flash-32k, flash-64k, flash-128k, flash-256k features"
+);
+
+const FLASH_SIZE_KB: u32 = if cfg!(feature = "flash-16k") {
+    16
+} else if cfg!(feature = "flash-32k") {
+    32
+} else if cfg!(feature = "flash-64k") {
+    64
+} else if cfg!(feature = "flash-128k") {
+    128
+} else {
+    256
+};
+
+// F3 parts always erase in 2 KB pages. F1 parts do from 256 KB up, smaller ones in 1 KB pages.
+// F0 parts do from 128 KB up, as do all F07x parts (enable `page-2k` for the 64 KB ones).
+pub const PAGE_SIZE: u32 = if cfg!(feature = "stm32f3") {
+    2048
+} else if cfg!(feature = "stm32f1") {
+    if FLASH_SIZE_KB >= 256 {
+        2048
+    } else {
+        1024
+    }
+} else if FLASH_SIZE_KB >= 128 || cfg!(feature = "page-2k") {
+    2048
+} else {
+    1024
+};
+const _: () = assert!(
+    !cfg!(feature = "page-2k") || !(cfg!(feature = "stm32f1") || cfg!(feature = "stm32f3")),
+    "the page-2k feature only applies to F07x parts"
+);
+// Bounds checks use the size detected at runtime, see `flash_size_kb`
+pub const NUM_PAGES: u32 = FLASH_SIZE_KB * 1024 / PAGE_SIZE;
+
+pub(crate) const FLASH_KEY1: u32 = 0x4567_0123;
+pub(crate) const FLASH_KEY2: u32 = 0xCDEF_89AB;
 
 impl FlashPage {
     pub const fn to_address(&self) -> usize {
         FLASH_START + self.0 * PAGE_SIZE as usize
     }
+
+    /// Page containing `address`, which must not lie below `FLASH_START`
+    pub const fn from_address(address: usize) -> Self {
+        FlashPage((address - FLASH_START) / PAGE_SIZE as usize)
+    }
 }
 
 impl FlashExt for FLASH {
+    fn is_locked(&self) -> bool {
+        FlashRegisters::is_locked(self)
+    }
+
     fn unlock(self) -> core::result::Result<UnlockedFlash, FLASH> {
-        // wait while memory interface is busy
-        while self.sr.read().bsy().bit_is_set() {}
+        self.unlock_with_status().map(|(unlocked, _)| unlocked)
+    }
 
-        // Unlock Flash
-        self.keyr.write(|w| w.fkeyr().bits(FLASH_KEY1));
-        self.keyr.write(|w| w.fkeyr().bits(FLASH_KEY2));
+    fn unlock_with_status(self) -> core::result::Result<(UnlockedFlash, UnlockStatus), FLASH> {
+        // wait while memory interface is busy
+        while self.is_busy() {}
+
+        // A key sequence written to an already unlocked controller counts as a wrong key and
+        // locks it until the next reset, so only unlock when needed
+        let status = if FlashRegisters::is_locked(&self) {
+            self.write_keyr(FLASH_KEY1);
+            self.write_keyr(FLASH_KEY2);
+            UnlockStatus::Unlocked
+        } else {
+            UnlockStatus::AlreadyUnlocked
+        };
 
         // Verify Success
-        if self.cr.read().lock().bit_is_clear() {
-            Ok(UnlockedFlash { f: self })
+        if !FlashRegisters::is_locked(&self) {
+            Ok((
+                UnlockedFlash {
+                    f: self,
+                    skip_identical: false,
+                    alignment: AlignmentPolicy::Pad,
+                    retry: RetryPolicy::NONE,
+                    soft_protected: 0,
+                    bad_pages: 0,
+                    firmware_end: 0,
+                    fault_address: None,
+                    num_pages: device::num_pages(),
+                },
+                status,
+            ))
         } else {
             Err(self)
         }
     }
+
+    fn unlock_guarded(self) -> core::result::Result<FlashGuard, FLASH> {
+        self.unlock().map(FlashGuard::new)
+    }
+
+    fn with_unlocked<T, F>(self, f: F) -> (FLASH, core::result::Result<T, Error>)
+    where
+        F: FnOnce(&mut UnlockedFlash) -> core::result::Result<T, Error>,
+    {
+        match self.unlock() {
+            Ok(mut unlocked) => {
+                let result = f(&mut unlocked);
+                (unlocked.lock(), result)
+            }
+            Err(flash) => (flash, Err(Error::Locked)),
+        }
+    }
 }
 
 pub trait FlashExt {
-    // Unlocks Flash memory for erasure and writing
+    // Whether the LOCK bit is set
+    fn is_locked(&self) -> bool;
+
+    // Unlocks Flash memory for erasure and writing, succeeds if it was already unlocked
     fn unlock(self) -> core::result::Result<UnlockedFlash, FLASH>;
+
+    // `unlock` that also reports whether the key sequence was needed
+    fn unlock_with_status(self) -> core::result::Result<(UnlockedFlash, UnlockStatus), FLASH>;
+
+    // Unlocks Flash memory and relocks it when the returned guard is dropped
+    fn unlock_guarded(self) -> core::result::Result<FlashGuard, FLASH>;
+
+    // Unlocks Flash memory, runs `f` and locks it again whatever `f` returned
+    fn with_unlocked<T, F>(self, f: F) -> (FLASH, core::result::Result<T, Error>)
+    where
+        F: FnOnce(&mut UnlockedFlash) -> core::result::Result<T, Error>;
+}
+
+/// Outcome of a successful `unlock_with_status`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UnlockStatus {
+    /// The flash was locked and the key sequence unlocked it
+    Unlocked,
+    /// The flash was already unlocked, e.g. by a bootloader, and no keys were written
+    AlreadyUnlocked,
+}
+
+/// How `write` handles data that does not start or end on a halfword boundary
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AlignmentPolicy {
+    /// Merge the unaligned head and tail bytes with 0xff into a full halfword. The other byte of
+    /// such a halfword is programmed as 0xff, so it has to be erased beforehand.
+    Pad,
+    /// Refuse with `Error::Unaligned`
+    Strict,
+}
+
+/// How often failed erases and halfword writes are retried before the error is returned
+#[derive(Copy, Clone, Debug)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one
+    pub attempts: u8,
+    /// Called with the error and the number of the failed attempt after every failure
+    pub on_error: Option<fn(Error, u8)>,
+}
+
+impl RetryPolicy {
+    /// Every operation is attempted once
+    pub const NONE: RetryPolicy = RetryPolicy {
+        attempts: 1,
+        on_error: None,
+    };
+
+    fn should_retry(&self, error: Error, attempt: u8) -> bool {
+        if let Some(on_error) = self.on_error {
+            on_error(error, attempt);
+        }
+        // Retrying can't get past protection or a busy controller
+        matches!(error, Error::ProgrammingError | Error::Eop) && attempt < self.attempts
+    }
 }
 
 pub struct UnlockedFlash {
     f: FLASH,
+    skip_identical: bool,
+    alignment: AlignmentPolicy,
+    retry: RetryPolicy,
+    soft_protected: u128,
+    bad_pages: u128,
+    firmware_end: usize,
+    fault_address: Option<usize>,
+    num_pages: usize,
 }
 
 impl UnlockedFlash {
+    /// Number of pages, derived from the flash size register when the flash was unlocked
+    pub fn num_pages(&self) -> usize {
+        self.num_pages
+    }
+
+    /// Address one past the last byte of main flash
+    pub fn end_address(&self) -> usize {
+        FLASH_START + self.num_pages * PAGE_SIZE as usize
+    }
+
+    /// When enabled, writes compare every target halfword with the new value first and skip
+    /// programming the ones that already hold it. Disabled by default.
+    pub fn set_skip_identical(&mut self, enabled: bool) {
+        self.skip_identical = enabled;
+    }
+
+    /// Clear the PGERR and WRPRT error flags
+    pub fn clear_errors(&mut self) {
+        self.f.clear_sr(SR_PGERR | SR_WRPRT);
+    }
+
+    /// Retry failed page erases and halfword writes according to `policy`
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry = policy;
+    }
+
+    /// Select how `write` treats data that does not start or end on a halfword boundary
+    pub fn set_alignment_policy(&mut self, policy: AlignmentPolicy) {
+        self.alignment = policy;
+    }
+
+    /// `write` that refuses unaligned data with `Error::Unaligned` instead of padding it.
+    ///
+    /// The offending address is recorded, see `fault_address` and `write_detailed`.
+    pub fn write_strict(&mut self, address: usize, data: &[u8]) -> Result {
+        let align = mem::size_of::<<Self as WriteErase>::NativeType>();
+        if address % align != 0 {
+            self.fault_address = Some(address);
+            return Err(Error::Unaligned);
+        }
+        if data.len() % align != 0 {
+            self.fault_address = Some(address + data.len() - 1);
+            return Err(Error::Unaligned);
+        }
+        self.write_padded(address, data)
+    }
+
     pub fn lock(self) -> FLASH {
-        self.f.cr.modify(|_, w| w.lock().set_bit());
+        self.f.set_cr(CR_LOCK);
         self.f
     }
 }
@@ -74,89 +465,121 @@ impl WriteErase for UnlockedFlash {
     type NativeType = u16;
 
     fn status(&self) -> Result {
-        let sr = self.f.sr.read();
-        if sr.bsy().bit_is_set() {
+        let sr = self.f.read_sr();
+        if sr & SR_BSY != 0 {
             return Err(Error::Busy);
         }
-        if sr.pgerr().bit_is_set() {
+        if sr & SR_PGERR != 0 {
             return Err(Error::ProgrammingError);
         }
-        if sr.wrprt().bit_is_set() {
+        if sr & SR_WRPRT != 0 {
             return Err(Error::WriteProtectionError);
         }
         Ok(())
     }
 
     fn erase_page(&mut self, page: FlashPage) -> Result {
-        if page.0 >= NUM_PAGES as usize {
+        self.fault_address = Some(page.to_address());
+        if page.0 >= self.num_pages {
             return Err(Error::PageOutOfRange);
         }
-
-        // Wait, while the memory interface is busy.
-        while self.f.sr.read().bsy().bit_is_set() {}
-        self.clear_errors();
-
-        // We absoluty can't have any access to Flash while preparing the
-        // erase, or the process will be interrupted. This includes any
-        // access to the vector table or interrupt handlers that might be
-        // caused by an interrupt.
-        interrupt::free(|_| {
-            self.f.cr.modify(|_, w| w.per().set_bit());
-            self.f
-                .ar
-                .write(|w| unsafe { w.bits(page.to_address() as u32) });
-            self.f.cr.modify(|_, w| w.strt().set_bit());
-        });
-        let result = self.wait();
-
-        if self.f.sr.read().eop().bit_is_set() {
-            self.f.sr.write(|w| w.eop().set_bit());
-        } else {
-            return Err(Error::Eop);
+        self.check_protection(page.to_address(), PAGE_SIZE as usize)?;
+
+        let mut attempt = 0;
+        loop {
+            match self.erase_page_once(page) {
+                Ok(()) => break,
+                Err(error) => {
+                    attempt += 1;
+                    if !self.retry.should_retry(error, attempt) {
+                        return Err(error);
+                    }
+                }
+            }
         }
-        self.f.cr.modify(|_, w| w.per().clear_bit());
-
-        result
+        self.fault_address = None;
+        Ok(())
     }
 
     fn write_native(&mut self, address: usize, array: &[Self::NativeType]) -> Result {
+        self.fault_address = Some(address);
+        let len = mem::size_of_val(array);
+        if address.checked_add(len).map_or(true, |end| end > self.end_address()) {
+            return Err(Error::OutOfBounds);
+        }
+        self.check_protection(address, len)?;
+
         // wait while memory interface is busy
-        while self.f.sr.read().bsy().bit_is_set() {}
+        while self.f.is_busy() {}
         self.clear_errors();
 
         // set the PG bit in flash cr register
-        self.f.cr.modify(|_, w| w.pg().set_bit());
+        self.f.set_cr(CR_PG);
 
         // Possible to program half word (16 bit)
         let mut address = address as *mut u16;
         for &word in array {
-            interrupt::free(|_| unsafe {
-                address.write_volatile(word);
-                address = address.add(1);
-            });
-
-            self.wait()?;
+            if self.skip_identical && unsafe { address.read_volatile() } == word {
+                address = unsafe { address.add(1) };
+                continue;
+            }
 
-            if self.f.sr.read().eop().bit_is_set() {
-                self.f.sr.write(|w| w.eop().set_bit());
+            let mut attempt = 0;
+            loop {
+                cs::free(|| unsafe {
+                    address.write_volatile(word);
+                });
+                let result = self.wait();
+
+                if self.f.read_sr() & SR_EOP != 0 {
+                    self.f.clear_sr(SR_EOP);
+                }
+
+                match result {
+                    Ok(()) => break,
+                    Err(error) => {
+                        attempt += 1;
+                        // Only a halfword that is still erased can be programmed again
+                        let erased = unsafe { address.read_volatile() } == 0xffff;
+                        if !erased || !self.retry.should_retry(error, attempt) {
+                            self.f.clear_cr(CR_PG);
+                            self.fault_address = Some(address as usize);
+                            return Err(error);
+                        }
+                        self.clear_errors();
+                    }
+                }
             }
+            address = unsafe { address.add(1) };
         }
-        self.f.cr.modify(|_, w| w.pg().clear_bit());
+        self.f.clear_cr(CR_PG);
+        self.fault_address = None;
         Ok(())
     }
 
     /// provide address which does not conflict with data or code address
     fn write(&mut self, address: usize, data: &[u8]) -> Result {
-        let address_offset = address % mem::align_of::<Self::NativeType>();
-        let unaligned_size = (mem::size_of::<Self::NativeType>() - address_offset)
-            % mem::size_of::<Self::NativeType>();
+        match self.alignment {
+            AlignmentPolicy::Pad => self.write_padded(address, data),
+            AlignmentPolicy::Strict => self.write_strict(address, data),
+        }
+    }
+}
+
+type Native = <UnlockedFlash as WriteErase>::NativeType;
+
+impl UnlockedFlash {
+    // Unaligned head and tail bytes are merged with 0xff into a native write
+    fn write_padded(&mut self, address: usize, data: &[u8]) -> Result {
+        let address_offset = address % mem::align_of::<Native>();
+        let unaligned_size = (mem::size_of::<Native>() - address_offset) % mem::size_of::<Native>();
 
         if unaligned_size > 0 {
             let unaligned_data = &data[..unaligned_size];
             // Handle unaligned address data, make it into a native write
             let mut data = 0xffffu16;
             for b in unaligned_data {
-                data = (data >> 8) | ((*b as Self::NativeType) << 8);
+                data = (data >> 8) | ((*b as Native) << 8);
             }
             let unaligned_address = address - address_offset;
             let native = &[data];
@@ -166,19 +589,17 @@ impl WriteErase for UnlockedFlash {
         // Handle aligned address data
         let aligned_data = &data[unaligned_size..];
         let mut aligned_address = if unaligned_size > 0 {
-            address - address_offset + mem::size_of::<Self::NativeType>()
+            address - address_offset + mem::size_of::<Native>()
         } else {
             address
         };
-        let mut chunks = aligned_data.chunks_exact(mem::size_of::<Self::NativeType>());
+        let mut chunks = aligned_data.chunks_exact(mem::size_of::<Native>());
 
         for exact_chunk in &mut chunks {
             // Write chunks
-            let native = &[Self::NativeType::from_ne_bytes(
-                exact_chunk.try_into().unwrap(),
-            )];
+            let native = &[Native::from_ne_bytes(exact_chunk.try_into().unwrap())];
             self.write_native(aligned_address, native)?;
-            aligned_address += mem::size_of::<Self::NativeType>();
+            aligned_address += mem::size_of::<Native>();
         }
         let rem = chunks.remainder();
 
@@ -186,7 +607,7 @@ impl WriteErase for UnlockedFlash {
             let mut data = 0xffffu16;
             // Write remainder
             for b in rem.iter().rev() {
-                data = (data << 8) | *b as Self::NativeType;
+                data = (data << 8) | *b as Native;
             }
 
             let native = &[data];
@@ -194,17 +615,36 @@ impl WriteErase for UnlockedFlash {
         }
         Ok(())
     }
-}
 
-impl UnlockedFlash {
-    fn clear_errors(&mut self) {
-        self.f
-            .sr
-            .modify(|_, w| w.pgerr().set_bit().wrprt().set_bit());
+    fn erase_page_once(&mut self, page: FlashPage) -> Result {
+        // Wait, while the memory interface is busy.
+        while self.f.is_busy() {}
+        self.clear_errors();
+
+        // We absoluty can't have any access to Flash while preparing the
+        // erase, or the process will be interrupted. This includes any
+        // access to the vector table or interrupt handlers that might be
+        // caused by an interrupt.
+        cs::free(|| {
+            self.f.set_cr(CR_PER);
+            self.f.write_ar(page.to_address() as u32);
+            self.f.set_cr(CR_STRT);
+        });
+        let result = self.wait();
+
+        if self.f.read_sr() & SR_EOP != 0 {
+            self.f.clear_sr(SR_EOP);
+        } else {
+            self.f.clear_cr(CR_PER);
+            return Err(Error::Eop);
+        }
+        self.f.clear_cr(CR_PER);
+
+        result
     }
 
     fn wait(&self) -> Result {
-        while self.f.sr.read().bsy().bit_is_set() {}
+        while self.f.is_busy() {}
         self.status()
     }
 }
\ No newline at end of file
@@ -0,0 +1,87 @@
+//! Log frames kept in a flash ring, so the last logs before a reset can be read out later.
+//!
+//! Frames are collected in RAM with `write` and appended to a `RingLog` by `commit`, which fits
+//! the acquire/write/release cycle of a `defmt` global logger. The frames are stored as they
+//! are, `dump` streams them back in order, e.g. to a UART or RTT channel. With defmt's rzcobs
+//! encoding the frames are self-delimiting, so the dump can be fed to the decoder directly.
+//!
+//! ```ignore
+//! // in the global logger
+//! fn release() {
+//!     LOG.lock(|log| FLASH.try_with_mut(|flash| log.commit(flash)));
+//! }
+//! ```
+
+use crate::{Error, Region, Result, RingLog, UnlockedFlash};
+
+/// Flash backed log of frames of up to `N` bytes
+#[derive(Debug)]
+pub struct FrameLog<const N: usize> {
+    log: RingLog,
+    buf: [u8; N],
+    len: usize,
+    truncated: u32,
+}
+
+impl<const N: usize> FrameLog<N> {
+    pub fn mount(flash: &mut UnlockedFlash, region: Region) -> core::result::Result<Self, Error> {
+        let log = RingLog::mount(flash, region)?;
+        if N > log.max_record_len() {
+            return Err(Error::InvalidLength);
+        }
+        Ok(FrameLog {
+            log,
+            buf: [0u8; N],
+            len: 0,
+            truncated: 0,
+        })
+    }
+
+    /// Add `bytes` to the current frame, dropping what doesn't fit `N`
+    pub fn write(&mut self, bytes: &[u8]) {
+        let len = bytes.len().min(N - self.len);
+        self.buf[self.len..self.len + len].copy_from_slice(&bytes[..len]);
+        self.len += len;
+        if len < bytes.len() {
+            self.truncated = self.truncated.saturating_add(1);
+        }
+    }
+
+    /// Append the current frame to flash and start a new one
+    pub fn commit(&mut self, flash: &mut UnlockedFlash) -> Result {
+        let len = core::mem::replace(&mut self.len, 0);
+        if len == 0 {
+            return Ok(());
+        }
+        self.log.append(flash, &self.buf[..len])
+    }
+
+    /// Append a complete frame to flash, bypassing the current one
+    pub fn push(&mut self, flash: &mut UnlockedFlash, frame: &[u8]) -> Result {
+        self.log.append(flash, frame)
+    }
+
+    /// Number of writes that didn't fit their frame since mounting
+    pub fn truncated(&self) -> u32 {
+        self.truncated
+    }
+
+    /// Pass every stored frame, oldest first, to `out` in chunks of up to 32 bytes
+    pub fn dump(&self, flash: &UnlockedFlash, mut out: impl FnMut(&[u8])) {
+        let mut chunk = [0u8; 32];
+        for record in self.log.records(flash) {
+            let mut offset = 0;
+            while offset < record.len() {
+                let len = chunk.len().min(record.len() - offset);
+                record.read_at(flash, offset, &mut chunk[..len]);
+                out(&chunk[..len]);
+                offset += len;
+            }
+        }
+    }
+
+    /// Erase every stored frame
+    pub fn clear(&mut self, flash: &mut UnlockedFlash) -> Result {
+        self.log.clear(flash)
+    }
+}
@@ -0,0 +1,77 @@
+//! Factory fallback: boot a read-only golden image when the primary image is damaged.
+//!
+//! The golden image is programmed once at the factory, with the same header as any other image,
+//! into a region whose pages are then write protected through the WRP option bytes, so that no
+//! update can touch it. On every boot the primary image is checked with `validate_image`, and
+//! only if that fails the golden image is checked the same way and started instead. A golden
+//! region that isn't write protected is refused with `Error::Unprotected`.
+
+use core::convert::Infallible;
+
+use crate::{
+    boot_into, validate_image, Error, ImageHeader, Region, SignatureVerifier, UnlockedFlash,
+};
+
+/// Image chosen by `select_image`
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BootImage {
+    Primary(ImageHeader),
+    /// The primary image failed its checks with this error
+    Golden(ImageHeader, Error),
+}
+
+impl BootImage {
+    pub fn header(&self) -> ImageHeader {
+        match self {
+            BootImage::Primary(header) | BootImage::Golden(header, _) => *header,
+        }
+    }
+
+    pub fn is_golden(&self) -> bool {
+        matches!(self, BootImage::Golden(..))
+    }
+}
+
+/// Check the image in `primary` and fall back to the one in `golden` if it fails. Returns the
+/// error of the golden image if both fail.
+pub fn select_image(
+    flash: &UnlockedFlash,
+    primary: &Region,
+    golden: &Region,
+    verifier: &(impl SignatureVerifier + ?Sized),
+) -> core::result::Result<BootImage, Error> {
+    let error = match validate_image(flash, primary, verifier) {
+        Ok(header) => return Ok(BootImage::Primary(header)),
+        Err(error) => error,
+    };
+    let header = validate_golden(flash, golden, verifier)?;
+    Ok(BootImage::Golden(header, error))
+}
+
+/// Start the primary image with `boot_into`, or the golden one if the primary image fails its
+/// checks or its vector table is refused by `boot_into`. Returns only if neither image can be
+/// started.
+pub fn boot_with_fallback(
+    flash: &UnlockedFlash,
+    primary: &Region,
+    golden: &Region,
+    verifier: &(impl SignatureVerifier + ?Sized),
+) -> core::result::Result<Infallible, Error> {
+    if let Ok(header) = validate_image(flash, primary, verifier) {
+        let _ = boot_into(header.entry as usize);
+    }
+    let header = validate_golden(flash, golden, verifier)?;
+    boot_into(header.entry as usize)
+}
+
+fn validate_golden(
+    flash: &UnlockedFlash,
+    golden: &Region,
+    verifier: &(impl SignatureVerifier + ?Sized),
+) -> core::result::Result<ImageHeader, Error> {
+    if (0..golden.pages).any(|i| !flash.is_write_protected(golden.page(i))) {
+        return Err(Error::Unprotected);
+    }
+    validate_image(flash, golden, verifier)
+}
@@ -0,0 +1,52 @@
+use core::ops::{Deref, DerefMut};
+
+use crate::{UnlockedFlash, FLASH};
+
+/// Unlocked flash that sets the LOCK bit again when it goes out of scope.
+pub struct FlashGuard {
+    flash: Option<UnlockedFlash>,
+}
+
+impl FlashGuard {
+    pub fn new(flash: UnlockedFlash) -> Self {
+        FlashGuard { flash: Some(flash) }
+    }
+
+    /// Lock the flash now and hand back the peripheral
+    pub fn lock(mut self) -> FLASH {
+        self.flash.take().unwrap().lock()
+    }
+
+    /// Take the unlocked flash out of the guard without locking it
+    pub fn into_inner(mut self) -> UnlockedFlash {
+        self.flash.take().unwrap()
+    }
+}
+
+impl Deref for FlashGuard {
+    type Target = UnlockedFlash;
+
+    fn deref(&self) -> &UnlockedFlash {
+        self.flash.as_ref().unwrap()
+    }
+}
+
+impl DerefMut for FlashGuard {
+    fn deref_mut(&mut self) -> &mut UnlockedFlash {
+        self.flash.as_mut().unwrap()
+    }
+}
+
+impl Drop for FlashGuard {
+    fn drop(&mut self) {
+        if let Some(flash) = self.flash.take() {
+            flash.lock();
+        }
+    }
+}
+
+impl From<UnlockedFlash> for FlashGuard {
+    fn from(flash: UnlockedFlash) -> Self {
+        FlashGuard::new(flash)
+    }
+}
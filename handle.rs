@@ -0,0 +1,113 @@
+use core::marker::PhantomData;
+
+use crate::{AppendPolicy, Error, Read, Region, RegionToken, Result, UnlockedFlash};
+
+/// Access mode marker: the region can only be read
+#[derive(Copy, Clone, Debug)]
+pub struct ReadOnly;
+
+/// Access mode marker: the region can be read, written and erased
+#[derive(Debug)]
+pub struct ReadWrite;
+
+/// Handle to a region whose allowed operations are fixed by `MODE`.
+#[derive(Debug)]
+pub struct RegionHandle<MODE> {
+    region: Region,
+    _mode: PhantomData<MODE>,
+}
+
+/// Region handle without any erase or write methods
+pub type ReadOnlyRegion = RegionHandle<ReadOnly>;
+
+/// Region handle with erase and write methods
+pub type ReadWriteRegion = RegionHandle<ReadWrite>;
+
+impl Clone for ReadOnlyRegion {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl Copy for ReadOnlyRegion {}
+
+impl<MODE> RegionHandle<MODE> {
+    /// Address of the first byte of the region
+    pub fn start_address(&self) -> usize {
+        self.region.start_address()
+    }
+
+    /// Size of the region in bytes
+    pub fn len(&self) -> usize {
+        self.region.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.region.is_empty()
+    }
+
+    /// Read `buf.len()` bytes from `offset` bytes into the region
+    pub fn read(
+        &self,
+        flash: &impl Read<NativeType = u8>,
+        offset: usize,
+        buf: &mut [u8],
+    ) -> Result {
+        self.check(offset, buf.len())?;
+        flash.read(self.region.start_address() + offset, buf);
+        Ok(())
+    }
+
+    fn check(&self, offset: usize, len: usize) -> Result {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.region.len() => Ok(()),
+            _ => Err(Error::OutOfBounds),
+        }
+    }
+}
+
+impl ReadWriteRegion {
+    /// Give up write access for good
+    pub fn into_read_only(self) -> ReadOnlyRegion {
+        RegionHandle {
+            region: self.region,
+            _mode: PhantomData,
+        }
+    }
+
+    /// Erase every page of the region
+    pub fn erase(&mut self, flash: &mut UnlockedFlash) -> Result {
+        flash.erase_region(&self.region)
+    }
+
+    /// Write `data` at `offset` bytes into the region
+    pub fn write(&mut self, flash: &mut UnlockedFlash, offset: usize, data: &[u8]) -> Result {
+        self.check(offset, data.len())?;
+        flash.write(self.region.start_address() + offset, data)
+    }
+
+    /// `UnlockedFlash::append` restricted to this region
+    pub fn append(
+        &mut self,
+        flash: &mut UnlockedFlash,
+        record: &[u8],
+        policy: AppendPolicy,
+    ) -> core::result::Result<usize, Error> {
+        flash.append(&self.region, record, policy)
+    }
+}
+
+impl From<RegionToken> for ReadWriteRegion {
+    fn from(token: RegionToken) -> Self {
+        RegionHandle {
+            region: token.into_region(),
+            _mode: PhantomData,
+        }
+    }
+}
+
+impl From<RegionToken> for ReadOnlyRegion {
+    fn from(token: RegionToken) -> Self {
+        ReadWriteRegion::from(token).into_read_only()
+    }
+}
@@ -0,0 +1,155 @@
+//! heatshrink decompression in front of an `ImageWriter`.
+//!
+//! heatshrink is an LZSS variant for embedded targets. Its bit stream, most significant bit
+//! first, is a sequence of
+//!
+//! ```text
+//! literal: 1 | byte (8 bits)
+//! copy:    0 | offset - 1 (window bits) | length - 1 (lookahead bits)
+//! ```
+//!
+//! A copy repeats `length` bytes starting `offset` bytes back in the window, which holds the
+//! last `N` bytes of output and starts out zeroed. Compressed chunks of any size go in and are
+//! decompressed into flash right away, so only the window and a small output buffer have to be
+//! kept in RAM. Compress with the same window and lookahead sizes, `heatshrink -w 8 -l 4` for
+//! `HeatshrinkWriter::<256>::new(writer, 4)`.
+
+use crate::{Error, ImageWriter, Result, UnlockedFlash};
+
+const OUT_LEN: usize = 64;
+
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+enum State {
+    Tag,
+    Literal,
+    Offset,
+    Length { offset: usize },
+}
+
+/// Decompressing writer with a window of `N` bytes, a power of two from 16 to 32768
+pub struct HeatshrinkWriter<const N: usize> {
+    writer: ImageWriter,
+    window: [u8; N],
+    head: usize,
+    window_bits: u8,
+    lookahead_bits: u8,
+    state: State,
+    /// Input bits not consumed yet, in the low `bit_count` bits
+    bits: u32,
+    bit_count: u8,
+    out: [u8; OUT_LEN],
+    out_len: usize,
+}
+
+impl<const N: usize> HeatshrinkWriter<N> {
+    /// Decompress into `writer`, with `lookahead_bits` smaller than the window bits.
+    /// `Error::InvalidLength` for sizes heatshrink doesn't support.
+    pub fn new(writer: ImageWriter, lookahead_bits: u8) -> core::result::Result<Self, Error> {
+        let window_bits = N.trailing_zeros() as u8;
+        if !N.is_power_of_two() || !(4..=15).contains(&window_bits) {
+            return Err(Error::InvalidLength);
+        }
+        if lookahead_bits < 3 || lookahead_bits >= window_bits {
+            return Err(Error::InvalidLength);
+        }
+        Ok(HeatshrinkWriter {
+            writer,
+            window: [0u8; N],
+            head: 0,
+            window_bits,
+            lookahead_bits,
+            state: State::Tag,
+            bits: 0,
+            bit_count: 0,
+            out: [0u8; OUT_LEN],
+            out_len: 0,
+        })
+    }
+
+    /// Decompressed bytes so far
+    pub fn written(&self) -> usize {
+        self.writer.written() + self.out_len
+    }
+
+    /// Decompress `data` and write the result
+    pub fn write(&mut self, flash: &mut UnlockedFlash, data: &[u8]) -> Result {
+        for byte in data {
+            self.bits = (self.bits << 8) | *byte as u32;
+            self.bit_count += 8;
+            self.run(flash)?;
+        }
+        Ok(())
+    }
+
+    /// Write the rest of the output and finish the image, returning its length. The padding
+    /// bits of the last input byte are ignored.
+    pub fn finish(mut self, flash: &mut UnlockedFlash) -> core::result::Result<usize, Error> {
+        self.flush(flash)?;
+        self.writer.finish(flash)
+    }
+
+    /// Decode everything the buffered bits allow
+    fn run(&mut self, flash: &mut UnlockedFlash) -> Result {
+        loop {
+            self.state = match self.state {
+                State::Tag => match self.take(1) {
+                    Some(1) => State::Literal,
+                    Some(_) => State::Offset,
+                    None => return Ok(()),
+                },
+                State::Literal => match self.take(8) {
+                    Some(byte) => {
+                        self.emit(flash, byte as u8)?;
+                        State::Tag
+                    }
+                    None => return Ok(()),
+                },
+                State::Offset => match self.take(self.window_bits) {
+                    Some(offset) => State::Length {
+                        offset: offset as usize + 1,
+                    },
+                    None => return Ok(()),
+                },
+                State::Length { offset } => match self.take(self.lookahead_bits) {
+                    Some(length) => {
+                        for _ in 0..=length {
+                            let byte = self.window[self.head.wrapping_sub(offset) & (N - 1)];
+                            self.emit(flash, byte)?;
+                        }
+                        State::Tag
+                    }
+                    None => return Ok(()),
+                },
+            };
+        }
+    }
+
+    /// Next `count` bits of input, `None` until enough have arrived
+    fn take(&mut self, count: u8) -> Option<u32> {
+        if self.bit_count < count {
+            return None;
+        }
+        self.bit_count -= count;
+        let value = (self.bits >> self.bit_count) & ((1 << count) - 1);
+        self.bits &= (1 << self.bit_count) - 1;
+        Some(value)
+    }
+
+    fn emit(&mut self, flash: &mut UnlockedFlash, byte: u8) -> Result {
+        self.window[self.head & (N - 1)] = byte;
+        self.head = self.head.wrapping_add(1);
+        self.out[self.out_len] = byte;
+        self.out_len += 1;
+        if self.out_len == OUT_LEN {
+            self.flush(flash)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self, flash: &mut UnlockedFlash) -> Result {
+        let len = self.out_len;
+        self.out_len = 0;
+        self.writer.write(flash, &self.out[..len])
+    }
+}
@@ -0,0 +1,104 @@
+//! CRC-32 over flash with the CRC peripheral of the F0 and F3.
+//!
+//! The peripheral is set up to reverse the input bits by byte and the output bits, which
+//! together with the final inversion done here gives the same CRC-32 (IEEE 802.3) as `Crc32`
+//! and zlib. Words are fed in one access each, which is what makes it fast: with the 4 AHB
+//! cycles the peripheral takes per word and the loop around it, about 8 cycles per word, the
+//! 7680 words of a 30 KB image take roughly 60 000 cycles. That is about 8 ms at the 8 MHz HSI
+//! the F0 starts on and 1.3 ms at 48 MHz, where the bitwise software CRC needs about 40 cycles
+//! per byte, some 150 ms at 8 MHz.
+//! The F1 CRC unit can't reverse bits and isn't supported.
+
+use crate::{Error, Region, Result};
+
+const CRC_DR: usize = 0x4002_3000;
+const CRC_CR: usize = 0x4002_3008;
+const CRC_INIT: usize = 0x4002_3010;
+const CR_RESET: u32 = 1 << 0;
+const CR_REV_IN_BYTE: u32 = 0b01 << 5;
+const CR_REV_OUT: u32 = 1 << 7;
+
+const RCC_AHBENR: usize = 0x4002_1014;
+const AHBENR_CRCEN: u32 = 1 << 6;
+
+/// CRC peripheral singletons `HardwareCrc` can take ownership of.
+///
+/// # Safety
+///
+/// Only implement this for the PAC's CRC peripheral, `HardwareCrc` drives its registers
+/// directly.
+pub unsafe trait CrcPeripheral {}
+
+#[cfg(not(any(feature = "stm32f3", feature = "metapac")))]
+unsafe impl CrcPeripheral for stm32f0xx_hal::stm32::CRC {}
+#[cfg(feature = "stm32f3")]
+unsafe impl CrcPeripheral for stm32f3xx_hal::pac::CRC {}
+#[cfg(feature = "metapac")]
+unsafe impl CrcPeripheral for stm32_metapac::crc::Crc {}
+
+/// CRC-32 unit owning the CRC peripheral `P`
+pub struct HardwareCrc<P> {
+    crc: P,
+}
+
+impl<P: CrcPeripheral> HardwareCrc<P> {
+    /// Enable the peripheral clock and configure the unit for CRC-32
+    pub fn new(crc: P) -> Self {
+        unsafe {
+            let ahbenr = RCC_AHBENR as *mut u32;
+            ahbenr.write_volatile(ahbenr.read_volatile() | AHBENR_CRCEN);
+        }
+        HardwareCrc { crc }
+    }
+
+    /// CRC-32 of the `len` bytes of memory at `address`
+    pub fn checksum(&mut self, address: usize, len: usize) -> u32 {
+        unsafe {
+            (CRC_INIT as *mut u32).write_volatile(0xffff_ffff);
+            (CRC_CR as *mut u32).write_volatile(CR_REV_IN_BYTE | CR_REV_OUT | CR_RESET);
+        }
+        let end = address + len;
+        let mut address = address;
+        while address < end && address % 4 != 0 {
+            feed_byte(address);
+            address += 1;
+        }
+        while address + 4 <= end {
+            let word = unsafe { (address as *const u32).read_volatile() };
+            // The unit takes the most significant byte of a word first
+            let word = u32::from_be_bytes(word.to_le_bytes());
+            unsafe { (CRC_DR as *mut u32).write_volatile(word) };
+            address += 4;
+        }
+        while address < end {
+            feed_byte(address);
+            address += 1;
+        }
+        !unsafe { (CRC_DR as *const u32).read_volatile() }
+    }
+
+    /// CRC-32 of every byte of `region`
+    pub fn region_crc(&mut self, region: &Region) -> u32 {
+        self.checksum(region.start_address(), region.len())
+    }
+
+    /// Check the CRC-32 of `region` against `expected`, `Error::CrcMismatch` if it differs
+    pub fn verify_region_crc(&mut self, region: &Region, expected: u32) -> Result {
+        if self.region_crc(region) != expected {
+            return Err(Error::CrcMismatch);
+        }
+        Ok(())
+    }
+
+    /// Give the peripheral back
+    pub fn free(self) -> P {
+        self.crc
+    }
+}
+
+fn feed_byte(address: usize) {
+    unsafe {
+        let byte = (address as *const u8).read_volatile();
+        (CRC_DR as *mut u8).write_volatile(byte);
+    }
+}
@@ -0,0 +1,208 @@
+//! Target side of the ST I2C bootloader protocol (AN4221), so a host MCU on the same board can
+//! reflash the F0 as an I2C slave.
+//!
+//! The commands and their fields are those of AN3155, see `SerialBootloader`, but every step
+//! is an I2C write frame from the host followed by a read of the answer: the command and its
+//! complement, then the address and its XOR, then the length and data. Supported are GET,
+//! GET VERSION, GET ID, READ MEMORY, GO, WRITE MEMORY and EXTENDED ERASE, with the same limits
+//! to the application region.
+//!
+//! The I2C slave driver passes every received frame to `on_write` and serves reads from
+//! `on_read`. `on_write` completes erases and writes before it returns, so the driver has to
+//! stretch the clock meanwhile. The no-stretch variants of the commands aren't supported.
+
+use crate::an3155::{
+    device_id, erase_pages, ACK, COMMANDS, EXTENDED_ERASE, GET, GET_ID, GET_VERSION, GO, NACK,
+    READ_MEMORY, VERSION, WRITE_MEMORY,
+};
+use crate::{flash_end, Error, Read, Region, Result, UnlockedFlash, WriteErase, FLASH_START};
+
+/// Next frame the host sends
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+enum State {
+    Command,
+    ReadAddress,
+    ReadLength { address: usize },
+    GoAddress,
+    WriteAddress,
+    WriteData { address: usize },
+    EraseCount,
+    ErasePages { count: usize },
+}
+
+/// AN4221 frame handler for an application region
+pub struct I2cBootloader {
+    region: Region,
+    state: State,
+    response: [u8; 1 + 256],
+    response_len: usize,
+    sent: usize,
+    go: Option<usize>,
+}
+
+impl I2cBootloader {
+    /// Handler that lets the host write, erase and start the application in `region`
+    pub fn new(region: Region) -> Self {
+        I2cBootloader {
+            region,
+            state: State::Command,
+            response: [0u8; 1 + 256],
+            response_len: 0,
+            sent: 0,
+            go: None,
+        }
+    }
+
+    /// Handle a frame written by the host. A frame that fails or doesn't fit the command in
+    /// progress is answered with `NACK` and the host has to start over with a command.
+    pub fn on_write(&mut self, flash: &mut UnlockedFlash, frame: &[u8]) {
+        self.response_len = 0;
+        self.sent = 0;
+        if self.handle(flash, frame).is_err() {
+            self.state = State::Command;
+            self.response_len = 0;
+            self.respond(&[NACK]);
+        }
+    }
+
+    /// Fill `buf` with the next bytes of the answer and return how many there were
+    pub fn on_read(&mut self, buf: &mut [u8]) -> usize {
+        let len = buf.len().min(self.response_len - self.sent);
+        buf[..len].copy_from_slice(&self.response[self.sent..self.sent + len]);
+        self.sent += len;
+        len
+    }
+
+    /// Address of a completed GO command, ready for `boot_into` once the host has read the
+    /// `ACK`
+    pub fn go_address(&self) -> Option<usize> {
+        self.go
+    }
+
+    fn handle(&mut self, flash: &mut UnlockedFlash, frame: &[u8]) -> Result {
+        self.state = match self.state {
+            State::Command => {
+                if frame.len() != 2 || frame[1] != !frame[0] {
+                    return Err(Error::InvalidRecord);
+                }
+                match frame[0] {
+                    GET => {
+                        self.respond(&[ACK, COMMANDS.len() as u8, VERSION]);
+                        self.respond(&COMMANDS);
+                        self.respond(&[ACK]);
+                        State::Command
+                    }
+                    GET_VERSION => {
+                        self.respond(&[ACK, VERSION, ACK]);
+                        State::Command
+                    }
+                    GET_ID => {
+                        let id = device_id();
+                        self.respond(&[ACK, 1, (id >> 8) as u8, id as u8, ACK]);
+                        State::Command
+                    }
+                    READ_MEMORY => State::ReadAddress,
+                    GO => State::GoAddress,
+                    WRITE_MEMORY => State::WriteAddress,
+                    EXTENDED_ERASE => State::EraseCount,
+                    _ => return Err(Error::InvalidRecord),
+                }
+            }
+            State::ReadAddress => {
+                let address = parse_address(frame)?;
+                if address < FLASH_START || address >= flash_end() {
+                    return Err(Error::OutOfBounds);
+                }
+                State::ReadLength { address }
+            }
+            State::ReadLength { address } => {
+                if frame.len() != 2 || frame[1] != !frame[0] {
+                    return Err(Error::InvalidRecord);
+                }
+                let len = frame[0] as usize + 1;
+                if address + len > flash_end() {
+                    return Err(Error::OutOfBounds);
+                }
+                self.respond(&[ACK]);
+                flash.read(address, &mut self.response[1..1 + len]);
+                self.response_len += len;
+                State::Command
+            }
+            State::GoAddress => {
+                let address = parse_address(frame)?;
+                if !self.region.contains(address) {
+                    return Err(Error::OutOfBounds);
+                }
+                self.go = Some(address);
+                State::Command
+            }
+            State::WriteAddress => {
+                let address = parse_address(frame)?;
+                if !self.region.contains(address) {
+                    return Err(Error::OutOfBounds);
+                }
+                State::WriteData { address }
+            }
+            State::WriteData { address } => {
+                let len = *frame.first().ok_or(Error::InvalidRecord)? as usize + 1;
+                if frame.len() != len + 2 || xor(&frame[..len + 1]) != frame[len + 1] {
+                    return Err(Error::InvalidRecord);
+                }
+                if address + len > self.region.end_address() {
+                    return Err(Error::OutOfBounds);
+                }
+                flash.write(address, &frame[1..len + 1])?;
+                State::Command
+            }
+            State::EraseCount => {
+                if frame.len() != 3 || xor(&frame[..2]) != frame[2] {
+                    return Err(Error::InvalidRecord);
+                }
+                match u16::from_be_bytes([frame[0], frame[1]]) {
+                    0xffff => {
+                        flash.erase_region(&self.region)?;
+                        State::Command
+                    }
+                    count if count as usize >= 256 => return Err(Error::InvalidRecord),
+                    count => State::ErasePages {
+                        count: count as usize + 1,
+                    },
+                }
+            }
+            State::ErasePages { count } => {
+                if frame.len() != 2 * count + 1 || xor(&frame[..2 * count]) != frame[2 * count] {
+                    return Err(Error::InvalidRecord);
+                }
+                let mut pages = [0u16; 256];
+                for (page, bytes) in pages.iter_mut().zip(frame.chunks_exact(2)) {
+                    *page = u16::from_be_bytes([bytes[0], bytes[1]]);
+                }
+                erase_pages(flash, &self.region, &pages[..count])?;
+                State::Command
+            }
+        };
+        // Frames without an answer of their own are acknowledged
+        if self.response_len == 0 {
+            self.respond(&[ACK]);
+        }
+        Ok(())
+    }
+
+    fn respond(&mut self, bytes: &[u8]) {
+        self.response[self.response_len..self.response_len + bytes.len()].copy_from_slice(bytes);
+        self.response_len += bytes.len();
+    }
+}
+
+/// Address from 4 big endian bytes and their XOR
+fn parse_address(frame: &[u8]) -> core::result::Result<usize, Error> {
+    if frame.len() != 5 || xor(&frame[..4]) != frame[4] {
+        return Err(Error::InvalidRecord);
+    }
+    Ok(u32::from_be_bytes([frame[0], frame[1], frame[2], frame[3]]) as usize)
+}
+
+fn xor(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |checksum, byte| checksum ^ byte)
+}
@@ -0,0 +1,243 @@
+//! Firmware image header shared by the bootloader and the application.
+//!
+//! A slot starts with the header, the image itself follows at `IMAGE_OFFSET`. All fields are
+//! little endian:
+//!
+//! ```text
+//! magic (u32) | version (u32) | length (u32) | crc (u32) | entry (u32)
+//! ```
+//!
+//! `crc` is the CRC-32 of the `length` bytes of the image and `entry` the address of its vector
+//! table. The offset keeps the vector table aligned for `VTOR` on the F1 and F3. A build script
+//! can prepend the header and the padding to the binary so the whole file is streamed into a
+//! slot as is. To add the header on the device instead, skip `IMAGE_OFFSET` bytes with
+//! `ImageWriter::skip`, write the image and then the header with `write_header`, which programs
+//! the magic last so a reset halfway leaves no valid header. A detached signature can follow the
+//! image, see `SignatureVerifier`.
+
+use crate::{
+    Checksum, Crc32, Error, Read, Region, Result, SignatureVerifier, UnlockedFlash, WriteErase,
+};
+
+/// Marks a programmed header
+pub const IMAGE_MAGIC: u32 = 0x4d49_4653;
+/// Size of the header fields
+pub const IMAGE_HEADER_LEN: usize = 20;
+/// Offset of the image from the start of the slot
+pub const IMAGE_OFFSET: usize = 0x200;
+
+/// Metadata of a firmware image
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ImageHeader {
+    pub version: u32,
+    /// Length of the image in bytes, without the header
+    pub length: u32,
+    /// CRC-32 of the image
+    pub crc: u32,
+    /// Address of the vector table of the image
+    pub entry: u32,
+}
+
+impl ImageHeader {
+    /// Header for the `image` bytes that will be placed at `IMAGE_OFFSET` in a slot
+    pub fn for_image(version: u32, image: &[u8], entry: u32) -> Self {
+        let mut crc = Crc32::new();
+        crc.update(image);
+        ImageHeader {
+            version,
+            length: image.len() as u32,
+            crc: crc.finish(),
+            entry,
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; IMAGE_HEADER_LEN] {
+        let mut bytes = [0u8; IMAGE_HEADER_LEN];
+        let fields = [IMAGE_MAGIC, self.version, self.length, self.crc, self.entry];
+        for (chunk, field) in bytes.chunks_exact_mut(4).zip(fields.iter()) {
+            chunk.copy_from_slice(&field.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Header in `bytes`, `None` without the magic
+    pub fn from_bytes(bytes: &[u8; IMAGE_HEADER_LEN]) -> Option<Self> {
+        let field = |index: usize| {
+            let start = index * 4;
+            u32::from_le_bytes([
+                bytes[start],
+                bytes[start + 1],
+                bytes[start + 2],
+                bytes[start + 3],
+            ])
+        };
+        if field(0) != IMAGE_MAGIC {
+            return None;
+        }
+        Some(ImageHeader {
+            version: field(1),
+            length: field(2),
+            crc: field(3),
+            entry: field(4),
+        })
+    }
+}
+
+/// Program `header` at the start of `slot`, whose header bytes have to be erased
+pub fn write_header(flash: &mut UnlockedFlash, slot: &Region, header: &ImageHeader) -> Result {
+    let bytes = header.to_bytes();
+    let address = slot.start_address();
+    flash.write(address + 4, &bytes[4..])?;
+    flash.write(address, &bytes[..4])
+}
+
+/// Header at the start of `slot`, `None` if there is none
+pub fn parse_header(flash: &impl Read<NativeType = u8>, slot: &Region) -> Option<ImageHeader> {
+    let mut bytes = [0u8; IMAGE_HEADER_LEN];
+    flash.read(slot.start_address(), &mut bytes);
+    ImageHeader::from_bytes(&bytes)
+}
+
+/// Check the header, the image CRC and the signature of `slot` and return the header. Pass
+/// `NoSignature` for unsigned images.
+///
+/// `Error::InvalidImage` if there is no header or it describes an image that doesn't fit the
+/// slot, `Error::CrcMismatch` if the image is damaged or incomplete and `Error::BadSignature` if
+/// `verifier` rejects the signature.
+pub fn validate_image(
+    flash: &impl Read<NativeType = u8>,
+    slot: &Region,
+    verifier: &(impl SignatureVerifier + ?Sized),
+) -> core::result::Result<ImageHeader, Error> {
+    let header = parse_header(flash, slot).ok_or(Error::InvalidImage)?;
+    let length = header.length as usize;
+    if length > slot.len().saturating_sub(IMAGE_OFFSET) {
+        return Err(Error::InvalidImage);
+    }
+    let signature_offset = length
+        .checked_add(3)
+        .and_then(|len| (len / 4 * 4).checked_add(IMAGE_OFFSET))
+        .ok_or(Error::InvalidImage)?;
+    let signature_end = signature_offset
+        .checked_add(verifier.signature_len())
+        .ok_or(Error::InvalidImage)?;
+    if signature_end > slot.len() || !slot.contains(header.entry as usize) {
+        return Err(Error::InvalidImage);
+    }
+    let start = slot.start_address() + IMAGE_OFFSET;
+    let mut crc = Crc32::new();
+    let mut buf = [0u8; 64];
+    let mut checked = 0;
+    while checked < length {
+        let chunk = buf.len().min(length - checked);
+        flash.read(start + checked, &mut buf[..chunk]);
+        crc.update(&buf[..chunk]);
+        checked += chunk;
+    }
+    if crc.finish() != header.crc {
+        return Err(Error::CrcMismatch);
+    }
+    // Flash is memory mapped, the signed bytes and the signature are read in place
+    let (message, signature) = unsafe {
+        let slot_start = slot.start_address() as *const u8;
+        (
+            core::slice::from_raw_parts(slot_start, IMAGE_OFFSET + length),
+            core::slice::from_raw_parts(
+                slot_start.add(signature_offset),
+                verifier.signature_len(),
+            ),
+        )
+    };
+    if !verifier.verify(message, signature) {
+        return Err(Error::BadSignature);
+    }
+    Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FlashPage, NoSignature, FLASH_START, PAGE_SIZE};
+
+    const SLOT_LEN: usize = 2 * PAGE_SIZE as usize;
+
+    /// Slot at the start of the flash, read from RAM
+    struct RamSlot([u8; SLOT_LEN]);
+
+    impl Read for RamSlot {
+        type NativeType = u8;
+
+        fn read_native(&self, address: usize, array: &mut [u8]) {
+            self.read(address, array)
+        }
+
+        fn read(&self, address: usize, buf: &mut [u8]) {
+            let offset = address - FLASH_START;
+            buf.copy_from_slice(&self.0[offset..offset + buf.len()]);
+        }
+    }
+
+    fn slot() -> Region {
+        Region::new(FlashPage(0), 2)
+    }
+
+    fn with_header(header: &ImageHeader, image: &[u8]) -> RamSlot {
+        let mut bytes = [crate::ERASED_BYTE; SLOT_LEN];
+        bytes[..IMAGE_HEADER_LEN].copy_from_slice(&header.to_bytes());
+        bytes[IMAGE_OFFSET..IMAGE_OFFSET + image.len()].copy_from_slice(image);
+        RamSlot(bytes)
+    }
+
+    #[test]
+    fn header_round_trip() {
+        let header = ImageHeader::for_image(7, b"image", FLASH_START as u32 + 0x200);
+        assert_eq!(ImageHeader::from_bytes(&header.to_bytes()), Some(header));
+    }
+
+    #[test]
+    fn missing_magic_is_no_header() {
+        let mut bytes = ImageHeader::for_image(1, b"image", 0).to_bytes();
+        bytes[0] ^= 1;
+        assert_eq!(ImageHeader::from_bytes(&bytes), None);
+        assert_eq!(ImageHeader::from_bytes(&[crate::ERASED_BYTE; IMAGE_HEADER_LEN]), None);
+    }
+
+    #[test]
+    fn image_longer_than_the_slot_is_refused() {
+        let entry = (FLASH_START + IMAGE_OFFSET) as u32;
+        for length in [(SLOT_LEN - IMAGE_OFFSET + 1) as u32, u32::MAX - 2, u32::MAX] {
+            let header = ImageHeader {
+                version: 1,
+                length,
+                crc: 0,
+                entry,
+            };
+            let flash = with_header(&header, &[]);
+            assert!(matches!(
+                validate_image(&flash, &slot(), &NoSignature),
+                Err(Error::InvalidImage)
+            ));
+        }
+    }
+
+    #[test]
+    fn entry_outside_the_slot_is_refused() {
+        let header = ImageHeader::for_image(1, b"image", (FLASH_START + SLOT_LEN) as u32);
+        let flash = with_header(&header, b"image");
+        assert!(matches!(
+            validate_image(&flash, &slot(), &NoSignature),
+            Err(Error::InvalidImage)
+        ));
+    }
+
+    #[test]
+    fn damaged_image_fails_the_crc() {
+        let header = ImageHeader::for_image(1, b"image", (FLASH_START + IMAGE_OFFSET) as u32);
+        let flash = with_header(&header, b"imagf");
+        assert!(matches!(
+            validate_image(&flash, &slot(), &NoSignature),
+            Err(Error::CrcMismatch)
+        ));
+    }
+}
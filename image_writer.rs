@@ -0,0 +1,195 @@
+//! Streaming writer for firmware images and other data that arrives in chunks.
+//!
+//! Chunks of any length are programmed one after another from the start of a region. Every page
+//! is erased right before the first byte goes into it, so nothing has to be erased up front and
+//! the pages behind the end of the image are left alone. An odd byte at the end of a chunk is
+//! held back until the next chunk or `finish` completes its halfword.
+//!
+//! A running CRC-32 is kept over the image bytes of what is written, those from `IMAGE_OFFSET`
+//! on, up to the length from the header if the header is written as well. `commit` checks it
+//! against the header in flash, so a transfer that corrupted the image is refused before the
+//! image can be marked pending. Without the header in the stream, everything written from
+//! `IMAGE_OFFSET` on counts as image.
+
+use crate::{
+    parse_header, Checksum, Crc32, Error, ImageHeader, Read, Region, Result, UnlockedFlash,
+    WriteErase, IMAGE_HEADER_LEN, IMAGE_OFFSET,
+};
+
+/// Sequential writer into a region
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ImageWriter {
+    region: Region,
+    /// Bytes programmed so far, always even
+    offset: usize,
+    /// Pages erased so far
+    erased: usize,
+    pending: Option<u8>,
+    /// Header bytes seen in the stream, erased until written
+    header: [u8; IMAGE_HEADER_LEN],
+    /// CRC-32 of the image bytes written so far
+    crc: Crc32,
+    /// Image bytes in `crc`
+    crc_len: usize,
+}
+
+impl ImageWriter {
+    /// Start writing at the start of `region`
+    pub fn new(region: Region) -> Self {
+        ImageWriter {
+            region,
+            offset: 0,
+            erased: 0,
+            pending: None,
+            header: [crate::ERASED_BYTE; IMAGE_HEADER_LEN],
+            crc: Crc32::new(),
+            crc_len: 0,
+        }
+    }
+
+    /// Continue writing at the even `offset`, after a reset cut an earlier writer short. The page
+    /// holding `offset` is taken as erased unless `offset` is at its start, so resume at a page
+    /// boundary to have the rest written into freshly erased pages. The running CRC is rebuilt
+    /// from the bytes already in flash.
+    pub fn resume(
+        flash: &UnlockedFlash,
+        region: Region,
+        offset: usize,
+    ) -> core::result::Result<Self, Error> {
+        if offset % 2 != 0 {
+            return Err(Error::Unaligned);
+        }
+        if offset > region.len() {
+            return Err(Error::RegionFull);
+        }
+        let page_len = region.len() / region.pages;
+        let mut writer = ImageWriter::new(region);
+        writer.offset = offset;
+        writer.erased = (offset + page_len - 1) / page_len;
+        let mut buf = [0u8; 64];
+        let mut position = 0;
+        while position < offset {
+            let chunk = buf.len().min(offset - position);
+            flash.read(region.start_address() + position, &mut buf[..chunk]);
+            writer.track(position, &buf[..chunk])?;
+            position += chunk;
+        }
+        Ok(writer)
+    }
+
+    pub fn region(&self) -> &Region {
+        &self.region
+    }
+
+    /// Number of bytes written so far
+    pub fn written(&self) -> usize {
+        self.offset + self.pending.is_some() as usize
+    }
+
+    /// Append `data` to what has been written so far. `Error::InvalidImage` if it completes a
+    /// header whose image doesn't fit the region.
+    pub fn write(&mut self, flash: &mut UnlockedFlash, data: &[u8]) -> Result {
+        if self.written() + data.len() > self.region.len() {
+            return Err(Error::RegionFull);
+        }
+        self.track(self.written(), data)?;
+        let mut data = data;
+        if let (Some(low), Some(high)) = (self.pending, data.first()) {
+            self.program(flash, &[low, *high])?;
+            self.pending = None;
+            data = &data[1..];
+        }
+        let even = data.len() & !1;
+        self.program(flash, &data[..even])?;
+        if even < data.len() {
+            self.pending = Some(data[even]);
+        }
+        Ok(())
+    }
+
+    /// Leave the next `len` bytes erased, for example for a header programmed afterwards. Only
+    /// possible at an even number of bytes written.
+    pub fn skip(&mut self, flash: &mut UnlockedFlash, len: usize) -> Result {
+        if self.pending.is_some() || len % 2 != 0 {
+            return Err(Error::Unaligned);
+        }
+        if self.offset + len > self.region.len() {
+            return Err(Error::RegionFull);
+        }
+        let page_len = self.region.len() / self.region.pages;
+        let end = self.offset + len;
+        while self.erased * page_len < end {
+            flash.erase_page(self.region.page(self.erased))?;
+            self.erased += 1;
+        }
+        self.offset = end;
+        Ok(())
+    }
+
+    /// Program the held back byte, padded with an erased byte, and return the number of bytes
+    /// written
+    pub fn finish(mut self, flash: &mut UnlockedFlash) -> core::result::Result<usize, Error> {
+        let written = self.written();
+        if let Some(low) = self.pending.take() {
+            self.program(flash, &[low, crate::ERASED_BYTE])?;
+        }
+        Ok(written)
+    }
+
+    /// Finish the image and check the running CRC against the header in flash, returning the
+    /// header. `Error::InvalidImage` without a header, `Error::CrcMismatch` if the bytes written
+    /// aren't the image the header describes.
+    pub fn commit(mut self, flash: &mut UnlockedFlash) -> core::result::Result<ImageHeader, Error> {
+        let region = self.region;
+        let crc = core::mem::replace(&mut self.crc, Crc32::new());
+        let crc_len = self.crc_len;
+        self.finish(flash)?;
+        let header = parse_header(flash, &region).ok_or(Error::InvalidImage)?;
+        if crc_len != header.length as usize || crc.finish() != header.crc {
+            return Err(Error::CrcMismatch);
+        }
+        Ok(header)
+    }
+
+    /// Take note of `data` written at `position`, for the header and the running CRC.
+    /// `Error::InvalidImage` once the header holds a length that doesn't fit the region.
+    fn track(&mut self, position: usize, data: &[u8]) -> Result {
+        let end = position + data.len();
+        if position < IMAGE_HEADER_LEN {
+            let len = data.len().min(IMAGE_HEADER_LEN - position);
+            self.header[position..position + len].copy_from_slice(&data[..len]);
+        }
+        let image_end = match ImageHeader::from_bytes(&self.header) {
+            Some(header) => IMAGE_OFFSET
+                .checked_add(header.length as usize)
+                .filter(|image_end| *image_end <= self.region.len())
+                .ok_or(Error::InvalidImage)?,
+            None => usize::MAX,
+        };
+        let (start, stop) = (position.max(IMAGE_OFFSET), end.min(image_end));
+        if start < stop {
+            self.crc.update(&data[start - position..stop - position]);
+            self.crc_len += stop - start;
+        }
+        Ok(())
+    }
+
+    /// Program the even length `data` at the current offset, erasing pages as they are reached
+    fn program(&mut self, flash: &mut UnlockedFlash, data: &[u8]) -> Result {
+        let page_len = self.region.len() / self.region.pages;
+        let mut data = data;
+        while !data.is_empty() {
+            let index = self.offset / page_len;
+            if index >= self.erased {
+                flash.erase_page(self.region.page(index))?;
+                self.erased = index + 1;
+            }
+            let len = data.len().min(page_len - self.offset % page_len);
+            flash.write(self.region.start_address() + self.offset, &data[..len])?;
+            self.offset += len;
+            data = &data[len..];
+        }
+        Ok(())
+    }
+}
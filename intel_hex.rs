@@ -0,0 +1,206 @@
+//! Push parser for Intel HEX files, programming the data records as they arrive.
+//!
+//! Every record is `:` followed by hex digits for
+//!
+//! ```text
+//! length (u8) | offset (u16) | type (u8) | data (length bytes) | checksum (u8)
+//! ```
+//!
+//! big endian, with the checksum making the sum of all bytes 0. Data records are placed at the
+//! current extended address plus their offset, extended segment (02) and extended linear (04)
+//! address records change the extended address and the start address records (03, 05) are
+//! kept for `start_address`. The file ends with the end of file record (01). Line breaks and
+//! other characters between records are skipped, the file can be fed in chunks of any size.
+
+use crate::{Error, RecordWriter, Region, Result, UnlockedFlash};
+
+const DATA: u8 = 0x00;
+const END_OF_FILE: u8 = 0x01;
+const EXTENDED_SEGMENT_ADDRESS: u8 = 0x02;
+const START_SEGMENT_ADDRESS: u8 = 0x03;
+const EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+const START_LINEAR_ADDRESS: u8 = 0x05;
+
+/// Length, offset, type and checksum bytes around the data
+const RECORD_OVERHEAD: usize = 5;
+
+/// Intel HEX file being written into a region
+pub struct IntelHex {
+    writer: RecordWriter,
+    record: [u8; RECORD_OVERHEAD + 255],
+    /// Hex digits of the current record so far, `None` between records
+    digits: Option<usize>,
+    base: usize,
+    start_address: Option<u32>,
+    done: bool,
+}
+
+impl IntelHex {
+    /// Parser that programs data records inside `region` only, see `RecordWriter`
+    pub fn new(region: Region) -> core::result::Result<Self, Error> {
+        Ok(IntelHex {
+            writer: RecordWriter::new(region)?,
+            record: [0u8; RECORD_OVERHEAD + 255],
+            digits: None,
+            base: 0,
+            start_address: None,
+            done: false,
+        })
+    }
+
+    /// Parse the next chunk of the file. Fails with `Error::InvalidRecord` for a malformed
+    /// record or a wrong checksum and with `Error::OutOfBounds` for data outside of the region.
+    /// Everything after the end of file record is ignored.
+    pub fn feed(&mut self, flash: &mut UnlockedFlash, bytes: &[u8]) -> Result {
+        for &byte in bytes {
+            if self.done {
+                break;
+            }
+            let digits = match (self.digits, byte) {
+                (None, b':') => {
+                    self.digits = Some(0);
+                    continue;
+                }
+                (None, _) => continue,
+                (Some(digits), _) => digits,
+            };
+            let nibble = match (byte as char).to_digit(16) {
+                Some(nibble) => nibble as u8,
+                None => return Err(Error::InvalidRecord),
+            };
+            let index = digits / 2;
+            if digits % 2 == 0 {
+                self.record[index] = nibble << 4;
+            } else {
+                self.record[index] |= nibble;
+            }
+            let digits = digits + 1;
+            self.digits = Some(digits);
+            if digits % 2 == 0 && digits / 2 == RECORD_OVERHEAD + self.record[0] as usize {
+                self.digits = None;
+                self.process(flash)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the end of file record has been parsed
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Address from a start address record, the entry point for the linear one
+    pub fn start_address(&self) -> Option<u32> {
+        self.start_address
+    }
+
+    /// Program a byte still held back, `Error::InvalidRecord` if the file didn't end with an end
+    /// of file record
+    pub fn finish(mut self, flash: &mut UnlockedFlash) -> Result {
+        self.writer.finish(flash)?;
+        if !self.done {
+            return Err(Error::InvalidRecord);
+        }
+        Ok(())
+    }
+
+    fn process(&mut self, flash: &mut UnlockedFlash) -> Result {
+        let len = self.record[0] as usize;
+        match decode(&self.record[..RECORD_OVERHEAD + len])? {
+            Record::Data(offset, data) => self.writer.write(flash, self.base + offset, data)?,
+            Record::EndOfFile => self.done = true,
+            Record::Base(base) => self.base = base,
+            Record::Start(address) => self.start_address = Some(address),
+        }
+        Ok(())
+    }
+}
+
+/// What a record does
+#[derive(Debug, PartialEq, Eq)]
+enum Record<'a> {
+    /// Data at an offset from the extended address
+    Data(usize, &'a [u8]),
+    EndOfFile,
+    /// New extended address
+    Base(usize),
+    Start(u32),
+}
+
+/// Check the checksum of the `record` bytes, from the length to the checksum, and decode it
+fn decode(record: &[u8]) -> core::result::Result<Record<'_>, Error> {
+    if record.len() < RECORD_OVERHEAD || record.len() != RECORD_OVERHEAD + record[0] as usize {
+        return Err(Error::InvalidRecord);
+    }
+    if record.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+        return Err(Error::InvalidRecord);
+    }
+    let len = record[0] as usize;
+    let offset = u16::from_be_bytes([record[1], record[2]]) as usize;
+    let data = &record[4..4 + len];
+    let value = |len: usize| {
+        data.iter()
+            .take(len)
+            .fold(0u32, |value, byte| (value << 8) | *byte as u32)
+    };
+    match (record[3], len) {
+        (DATA, _) => Ok(Record::Data(offset, data)),
+        (END_OF_FILE, 0) => Ok(Record::EndOfFile),
+        (EXTENDED_SEGMENT_ADDRESS, 2) => Ok(Record::Base((value(2) as usize) << 4)),
+        (EXTENDED_LINEAR_ADDRESS, 2) => Ok(Record::Base((value(2) as usize) << 16)),
+        (START_SEGMENT_ADDRESS, 4) | (START_LINEAR_ADDRESS, 4) => Ok(Record::Start(value(4))),
+        _ => Err(Error::InvalidRecord),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `record` with its checksum appended
+    fn with_checksum(record: &[u8]) -> [u8; 16] {
+        let mut bytes = [0u8; 16];
+        bytes[..record.len()].copy_from_slice(record);
+        let sum = record.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        bytes[record.len()] = sum.wrapping_neg();
+        bytes
+    }
+
+    #[test]
+    fn data_record_is_decoded() {
+        // :0300300002337A1E
+        let record = [0x03, 0x00, 0x30, 0x00, 0x02, 0x33, 0x7a, 0x1e];
+        assert_eq!(decode(&record).ok(), Some(Record::Data(0x30, &[0x02, 0x33, 0x7a])));
+    }
+
+    #[test]
+    fn address_records_are_decoded() {
+        let record = with_checksum(&[0x02, 0x00, 0x00, 0x04, 0x08, 0x00]);
+        assert_eq!(decode(&record[..7]).ok(), Some(Record::Base(0x0800_0000)));
+        let record = with_checksum(&[0x02, 0x00, 0x00, 0x02, 0x10, 0x00]);
+        assert_eq!(decode(&record[..7]).ok(), Some(Record::Base(0x1_0000)));
+        let record = with_checksum(&[0x04, 0x00, 0x00, 0x05, 0x08, 0x00, 0x01, 0x01]);
+        assert_eq!(decode(&record[..9]).ok(), Some(Record::Start(0x0800_0101)));
+        assert_eq!(decode(&[0x00, 0x00, 0x00, 0x01, 0xff]).ok(), Some(Record::EndOfFile));
+    }
+
+    #[test]
+    fn wrong_checksum_is_refused() {
+        let record = [0x03, 0x00, 0x30, 0x00, 0x02, 0x33, 0x7a, 0x1f];
+        assert!(matches!(decode(&record), Err(Error::InvalidRecord)));
+    }
+
+    #[test]
+    fn wrong_length_for_the_type_is_refused() {
+        let record = with_checksum(&[0x01, 0x00, 0x00, 0x04, 0x08]);
+        assert!(matches!(decode(&record[..6]), Err(Error::InvalidRecord)));
+        let record = with_checksum(&[0x00, 0x00, 0x00, 0x07]);
+        assert!(matches!(decode(&record[..5]), Err(Error::InvalidRecord)));
+    }
+
+    #[test]
+    fn truncated_record_is_refused() {
+        assert!(matches!(decode(&[0x03, 0x00, 0x30, 0x00]), Err(Error::InvalidRecord)));
+        assert!(matches!(decode(&[0x03, 0x00, 0x30, 0x00, 0x02]), Err(Error::InvalidRecord)));
+    }
+}
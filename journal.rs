@@ -0,0 +1,174 @@
+//! Append-only journal that survives power loss at any point.
+//!
+//! Every record is a length, a commit marker and a checksum over length and data, CRC-32 unless
+//! another `Checksum` is picked, followed by the data padded to a halfword:
+//!
+//! ```text
+//! length (u16) | commit (u16) | crc (u32) | data
+//! ```
+//!
+//! The length is programmed first, then the data and the CRC, and the commit marker last. A
+//! record whose commit marker is still erased or whose CRC doesn't match was torn by a reset.
+//! `mount` drops it and appends after it, a torn record never reappears.
+
+use core::marker::PhantomData;
+
+use crate::{Checksum, Crc32, Error, Read, Region, Result, UnlockedFlash, WriteErase};
+
+const HEADER_LEN: usize = 8;
+const END: u16 = 0xffff;
+const COMMITTED: u16 = 0x0000;
+
+/// Journal in the pages of a region
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Journal<C = Crc32> {
+    region: Region,
+    end: usize,
+    torn: usize,
+    _checksum: PhantomData<C>,
+}
+
+/// A committed record, as returned by `Journal::records`
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct JournalRecord {
+    address: usize,
+    len: usize,
+}
+
+impl JournalRecord {
+    /// Length of the data in bytes
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Copy the start of the data into `buf` and return how many bytes were copied
+    pub fn read(&self, flash: &impl Read<NativeType = u8>, buf: &mut [u8]) -> usize {
+        let len = self.len.min(buf.len());
+        flash.read(self.address + HEADER_LEN, &mut buf[..len]);
+        len
+    }
+}
+
+impl Journal {
+    /// Scan `region` for the end of the journal, skipping torn records
+    pub fn mount(flash: &UnlockedFlash, region: Region) -> Self {
+        Self::mount_with_checksum(flash, region)
+    }
+}
+
+impl<C: Checksum> Journal<C> {
+    /// `mount` for a journal whose records are checked with `C`
+    pub fn mount_with_checksum(flash: &UnlockedFlash, region: Region) -> Self {
+        let mut journal = Journal {
+            region,
+            end: region.len(),
+            torn: 0,
+            _checksum: PhantomData,
+        };
+        let mut offset = 0;
+        while offset + HEADER_LEN <= region.len() {
+            let address = region.start_address() + offset;
+            let len = flash.read_halfword(address);
+            if len == END {
+                journal.end = offset;
+                break;
+            }
+            if !journal.is_committed(flash, address) {
+                journal.torn += 1;
+            }
+            offset += record_size(len as usize);
+        }
+        journal
+    }
+
+    /// Number of torn records `mount` dropped
+    pub fn torn_records(&self) -> usize {
+        self.torn
+    }
+
+    /// Bytes left for records, including their 8 byte headers
+    pub fn remaining(&self) -> usize {
+        self.region.len() - self.end
+    }
+
+    /// Append `data` as one record, which is only ever seen complete or not at all
+    pub fn append(&mut self, flash: &mut UnlockedFlash, data: &[u8]) -> Result {
+        let size = record_size(data.len());
+        if data.len() >= END as usize || size > self.remaining() {
+            return Err(Error::RegionFull);
+        }
+        let len = data.len() as u16;
+        let mut crc = C::new();
+        crc.update(&len.to_ne_bytes());
+        crc.update(data);
+
+        let address = self.region.start_address() + self.end;
+        // A failed append leaves a torn record behind, the next one goes after it
+        self.end += size;
+        flash.write_native(address, &[len])?;
+        if !data.is_empty() {
+            flash.write(address + HEADER_LEN, data)?;
+        }
+        flash.write(address + 4, &crc.finish().to_ne_bytes())?;
+        flash.write_native(address + 2, &[COMMITTED])
+    }
+
+    /// Committed records, oldest first
+    pub fn records<'a>(
+        &'a self,
+        flash: &'a UnlockedFlash,
+    ) -> impl Iterator<Item = JournalRecord> + 'a {
+        let mut offset = 0;
+        core::iter::from_fn(move || {
+            while offset < self.end {
+                let address = self.region.start_address() + offset;
+                let len = flash.read_halfword(address) as usize;
+                offset += record_size(len);
+                if self.is_committed(flash, address) {
+                    return Some(JournalRecord { address, len });
+                }
+            }
+            None
+        })
+    }
+
+    /// Erase the journal
+    pub fn clear(&mut self, flash: &mut UnlockedFlash) -> Result {
+        flash.erase_region(&self.region)?;
+        self.end = 0;
+        self.torn = 0;
+        Ok(())
+    }
+
+    fn is_committed(&self, flash: &UnlockedFlash, address: usize) -> bool {
+        let len = flash.read_halfword(address);
+        if flash.read_halfword(address + 2) != COMMITTED
+            || address + record_size(len as usize) > self.region.end_address()
+        {
+            return false;
+        }
+        let mut crc = C::new();
+        crc.update(&len.to_ne_bytes());
+        let mut buf = [0u8; 64];
+        let mut checked = 0;
+        while checked < len as usize {
+            let chunk = buf.len().min(len as usize - checked);
+            flash.read(address + HEADER_LEN + checked, &mut buf[..chunk]);
+            crc.update(&buf[..chunk]);
+            checked += chunk;
+        }
+        let mut stored = [0u8; 4];
+        flash.read(address + 4, &mut stored);
+        crc.finish().to_ne_bytes() == stored
+    }
+}
+
+fn record_size(len: usize) -> usize {
+    HEADER_LEN + (len + 1) / 2 * 2
+}
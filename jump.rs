@@ -0,0 +1,104 @@
+//! Starting an application from a bootloader.
+//!
+//! The Cortex-M0 of the F0 has no `VTOR`, so its vector table is copied to the start of SRAM
+//! and SRAM is remapped to address 0 through `SYSCFG_CFGR1`. The application then has to leave
+//! the first `VECTOR_TABLE_LEN` bytes of SRAM alone, by starting its `RAM` region in `memory.x`
+//! behind them. The F1 and F3 point `VTOR` at the vector table in flash instead, which needs
+//! it aligned to 512 bytes.
+
+use core::convert::Infallible;
+use core::ptr;
+
+use crate::{flash_end, Error, FLASH_START};
+
+const SRAM_START: usize = 0x2000_0000;
+/// Largest SRAM of the supported parts
+const SRAM_MAX_LEN: usize = 0x0002_0000;
+
+const FLASH_CR: *mut u32 = 0x4002_2010 as *mut u32;
+const CR_LOCK: u32 = 1 << 7;
+
+const SYST_CSR: *mut u32 = 0xe000_e010 as *mut u32;
+const NVIC_ICER: *mut u32 = 0xe000_e180 as *mut u32;
+const NVIC_ICPR: *mut u32 = 0xe000_e280 as *mut u32;
+#[cfg(not(any(feature = "stm32f1", feature = "stm32f3")))]
+const NVIC_REGISTERS: usize = 1;
+#[cfg(any(feature = "stm32f1", feature = "stm32f3"))]
+const NVIC_REGISTERS: usize = 8;
+
+/// Bytes of SRAM the F0 vector table is copied to, 16 exceptions and 32 interrupts
+#[cfg(not(any(feature = "stm32f1", feature = "stm32f3")))]
+pub const VECTOR_TABLE_LEN: usize = 48 * 4;
+#[cfg(not(any(feature = "stm32f1", feature = "stm32f3")))]
+const RCC_APB2ENR: *mut u32 = 0x4002_1018 as *mut u32;
+#[cfg(not(any(feature = "stm32f1", feature = "stm32f3")))]
+const APB2ENR_SYSCFGEN: u32 = 1 << 0;
+#[cfg(not(any(feature = "stm32f1", feature = "stm32f3")))]
+const SYSCFG_CFGR1: *mut u32 = 0x4001_0000 as *mut u32;
+#[cfg(not(any(feature = "stm32f1", feature = "stm32f3")))]
+const CFGR1_MEM_MODE_SRAM: u32 = 0b11;
+
+#[cfg(any(feature = "stm32f1", feature = "stm32f3"))]
+const SCB_VTOR: *mut u32 = 0xe000_ed08 as *mut u32;
+#[cfg(any(feature = "stm32f1", feature = "stm32f3"))]
+const VTOR_ALIGN: usize = 0x200;
+
+/// Start the application whose vector table is at `address`.
+///
+/// Checks that the initial stack pointer lies in SRAM and the reset vector is a Thumb address
+/// in flash, `Error::InvalidImage` otherwise. Then interrupts are disabled and cleared, SysTick
+/// is stopped, the flash is locked, the vector table is relocated and the stack pointer is
+/// loaded before jumping to the reset handler, with interrupts enabled again as after a reset.
+/// Clocks and other peripherals are left as they are. On success this never returns.
+pub fn boot_into(address: usize) -> core::result::Result<Infallible, Error> {
+    #[cfg(any(feature = "stm32f1", feature = "stm32f3"))]
+    let (aligned, table_len) = (address % VTOR_ALIGN == 0, 8);
+    // The whole table is copied to SRAM, it has to lie in flash
+    #[cfg(not(any(feature = "stm32f1", feature = "stm32f3")))]
+    let (aligned, table_len) = (address % 4 == 0, VECTOR_TABLE_LEN);
+    if !aligned {
+        return Err(Error::Unaligned);
+    }
+    if address < FLASH_START || address.saturating_add(table_len) > flash_end() {
+        return Err(Error::OutOfBounds);
+    }
+    let (sp, reset) = unsafe {
+        let table = address as *const u32;
+        (table.read_volatile() as usize, table.add(1).read_volatile() as usize)
+    };
+    let sp_valid = sp > SRAM_START && sp <= SRAM_START + SRAM_MAX_LEN && sp % 4 == 0;
+    let reset_valid = reset & 1 == 1 && reset > FLASH_START && reset < flash_end();
+    if !sp_valid || !reset_valid {
+        return Err(Error::InvalidImage);
+    }
+
+    cortex_m::interrupt::disable();
+    unsafe {
+        ptr::write_volatile(SYST_CSR, 0);
+        for i in 0..NVIC_REGISTERS {
+            ptr::write_volatile(NVIC_ICER.add(i), 0xffff_ffff);
+            ptr::write_volatile(NVIC_ICPR.add(i), 0xffff_ffff);
+        }
+        ptr::write_volatile(FLASH_CR, ptr::read_volatile(FLASH_CR) | CR_LOCK);
+
+        #[cfg(not(any(feature = "stm32f1", feature = "stm32f3")))]
+        {
+            // Overwrites the start of SRAM, nothing below may use statics
+            ptr::copy_nonoverlapping(
+                address as *const u32,
+                SRAM_START as *mut u32,
+                VECTOR_TABLE_LEN / 4,
+            );
+            ptr::write_volatile(RCC_APB2ENR, ptr::read_volatile(RCC_APB2ENR) | APB2ENR_SYSCFGEN);
+            let cfgr1 = ptr::read_volatile(SYSCFG_CFGR1) & !0b11;
+            ptr::write_volatile(SYSCFG_CFGR1, cfgr1 | CFGR1_MEM_MODE_SRAM);
+        }
+        #[cfg(any(feature = "stm32f1", feature = "stm32f3"))]
+        ptr::write_volatile(SCB_VTOR, address as u32);
+
+        cortex_m::asm::dsb();
+        cortex_m::asm::isb();
+        cortex_m::interrupt::enable();
+        cortex_m::asm::bootstrap(sp as *const u32, reset as *const u32)
+    }
+}
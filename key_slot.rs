@@ -0,0 +1,151 @@
+//! Key storage in a reserved region, kept apart from the other storage layers.
+//!
+//! Every slot holds one key of `N` bytes behind a state halfword:
+//!
+//! ```text
+//! state (u16) | reserved (u16) | key
+//! ```
+//!
+//! The state goes from erased to `STORED` once the key has been programmed, and to `0x0000`
+//! when the key is erased. Erasing a key programs its bytes to zero, which needs no page erase,
+//! so a slot can't be refilled until the whole region is destroyed. Keys are handed out as
+//! `Key` copies that clear themselves when dropped.
+
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use crate::{Error, Read, Region, Result, UnlockedFlash, WriteErase};
+
+const HEADER_LEN: usize = 4;
+const EMPTY: u16 = 0xffff;
+const STORED: u16 = 0x5a5a;
+const CLEARED: u16 = 0x0000;
+
+/// Compare `a` and `b` in time that only depends on their length
+pub fn ct_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let diff = a.iter().zip(b).fold(0u8, |diff, (a, b)| diff | (a ^ b));
+    unsafe { core::ptr::read_volatile(&diff) == 0 }
+}
+
+/// Overwrite `buf` with zeros in a way the compiler can't optimize out
+pub fn zeroize(buf: &mut [u8]) {
+    for byte in buf.iter_mut() {
+        unsafe { core::ptr::write_volatile(byte, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+/// RAM copy of a stored key, zeroed on drop
+pub struct Key<const N: usize>([u8; N]);
+
+impl<const N: usize> Key<N> {
+    pub fn as_bytes(&self) -> &[u8; N] {
+        &self.0
+    }
+
+    /// Compare the key with `other` in constant time
+    pub fn ct_eq(&self, other: &[u8]) -> bool {
+        ct_eq(&self.0, other)
+    }
+}
+
+impl<const N: usize> Drop for Key<N> {
+    fn drop(&mut self) {
+        zeroize(&mut self.0);
+    }
+}
+
+impl<const N: usize> core::fmt::Debug for Key<N> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("Key(..)")
+    }
+}
+
+/// Fixed slots of `N` byte keys
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct KeySlots<const N: usize> {
+    region: Region,
+    slots: usize,
+}
+
+impl<const N: usize> KeySlots<N> {
+    const SLOT_LEN: usize = HEADER_LEN + (N + 1) / 2 * 2;
+
+    pub fn new(region: Region) -> core::result::Result<Self, Error> {
+        let slots = region.len() / Self::SLOT_LEN;
+        if N == 0 || slots == 0 {
+            return Err(Error::InvalidLength);
+        }
+        Ok(KeySlots { region, slots })
+    }
+
+    /// Number of slots in the region
+    pub fn capacity(&self) -> usize {
+        self.slots
+    }
+
+    /// Store `key` in the empty `slot`
+    pub fn store(&self, flash: &mut UnlockedFlash, slot: usize, key: &[u8; N]) -> Result {
+        let address = self.slot_address(slot)?;
+        if flash.read_halfword(address) != EMPTY {
+            return Err(Error::ProgrammingError);
+        }
+        flash.write(address + HEADER_LEN, key)?;
+        flash.write_native(address, &[STORED])
+    }
+
+    pub fn is_stored(&self, flash: &UnlockedFlash, slot: usize) -> bool {
+        match self.slot_address(slot) {
+            Ok(address) => flash.read_halfword(address) == STORED,
+            Err(_) => false,
+        }
+    }
+
+    /// Copy of the key in `slot`, `None` if the slot holds none
+    pub fn load(&self, flash: &UnlockedFlash, slot: usize) -> Option<Key<N>> {
+        if !self.is_stored(flash, slot) {
+            return None;
+        }
+        let mut key = Key([0u8; N]);
+        flash.read(self.slot_address(slot).ok()? + HEADER_LEN, &mut key.0);
+        Some(key)
+    }
+
+    /// Compare the key in `slot` with `candidate` in constant time
+    pub fn matches(&self, flash: &UnlockedFlash, slot: usize, candidate: &[u8]) -> bool {
+        self.load(flash, slot).map_or(false, |key| key.ct_eq(candidate))
+    }
+
+    /// Program the key in `slot` to zeros. The slot stays unusable until `destroy`.
+    pub fn erase(&self, flash: &mut UnlockedFlash, slot: usize) -> Result {
+        let address = self.slot_address(slot)?;
+        zero(flash, address + HEADER_LEN, Self::SLOT_LEN - HEADER_LEN)?;
+        flash.write_native(address, &[CLEARED])
+    }
+
+    /// Program every key to zeros, then erase the region
+    pub fn destroy(&self, flash: &mut UnlockedFlash) -> Result {
+        zero(flash, self.region.start_address(), self.region.len())?;
+        flash.erase_region(&self.region)
+    }
+
+    fn slot_address(&self, slot: usize) -> core::result::Result<usize, Error> {
+        if slot >= self.slots {
+            return Err(Error::OutOfBounds);
+        }
+        Ok(self.region.start_address() + slot * Self::SLOT_LEN)
+    }
+}
+
+/// Program the `len` bytes at `address` to zero, skipping halfwords that already are
+fn zero(flash: &mut UnlockedFlash, address: usize, len: usize) -> Result {
+    for address in (address..address + len).step_by(2) {
+        if flash.read_halfword(address) != 0 {
+            flash.write_native(address, &[0])?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,448 @@
+//! Key-value store over the pages of a region.
+//!
+//! Records are appended page after page, the newest record of a key wins. Every page starts
+//! with an 8 byte header holding a magic and the sequence number of the page, every record
+//! with an 8 byte header of key, value length and a checksum over the three, CRC-32 unless
+//! another `Checksum` is picked, followed by the value padded to a halfword:
+//!
+//! ```text
+//! page:   magic (u16) | reserved (u16) | sequence (u32) | records...
+//! record: key (u16) | length (u16) | crc (u32) | value
+//! ```
+//!
+//! One page is always kept erased behind the one being written. Once the writes move on to the
+//! next page, the oldest page is compacted lazily: only the records that are still the newest
+//! of their key are copied forward before it is erased.
+//!
+//! Before a page is relocated its reserved halfword is programmed to `0x0000`. If a reset cuts
+//! the relocation short, `mount` finds the marked page behind the head and finishes it; records
+//! that were already copied are newer in the head page and aren't copied twice.
+//!
+//! `compact` relocates every page in one go, `set_auto_compact` has `set` and `remove` do so
+//! once a share of the region is taken up by stale records.
+//!
+//! Pages in the mounted `BadPages` table are skipped, the store runs on the remaining ones.
+
+use core::marker::PhantomData;
+
+use crate::{Checksum, Crc32, Error, Read, Region, Result, UnlockedFlash, WriteErase, ERASED_BYTE};
+
+const PAGE_MAGIC: u16 = 0x4b56;
+const PAGE_HEADER_LEN: usize = 8;
+const RECORD_HEADER_LEN: usize = 8;
+
+const END: u16 = 0xffff;
+const REMOVED: u16 = 0xfffe;
+const RELOCATING: u16 = 0x0000;
+
+/// Key-value store with `u16` keys and values of up to `max_value_len` bytes.
+///
+/// Key `0xffff` is reserved. Values, together with their 8 byte header, have to fit a page.
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct KvStore<C = Crc32> {
+    region: Region,
+    head: usize,
+    offset: usize,
+    sequence: u32,
+    auto_compact: Option<u8>,
+    _checksum: PhantomData<C>,
+}
+
+impl KvStore {
+    /// Open the store in `region`, which needs at least two good pages. A region without any
+    /// store pages is formatted.
+    pub fn mount(
+        flash: &mut UnlockedFlash,
+        region: Region,
+    ) -> core::result::Result<Self, Error> {
+        Self::mount_with_checksum(flash, region)
+    }
+}
+
+impl<C: Checksum> KvStore<C> {
+    /// `mount` for a store whose records are checked with `C`
+    pub fn mount_with_checksum(
+        flash: &mut UnlockedFlash,
+        region: Region,
+    ) -> core::result::Result<Self, Error> {
+        if flash.good_pages(&region) < 2 {
+            return Err(Error::InvalidLength);
+        }
+        let mut store = KvStore {
+            region,
+            head: 0,
+            offset: PAGE_HEADER_LEN,
+            sequence: 0,
+            auto_compact: None,
+            _checksum: PhantomData,
+        };
+
+        let newest = (0..region.pages)
+            .filter_map(|page| Some((page, store.page_sequence(flash, page)?)))
+            .max_by_key(|(_, sequence)| *sequence);
+        match newest {
+            Some((page, sequence)) => {
+                store.head = page;
+                store.sequence = sequence;
+                store.offset = store.end_of_page(flash, page);
+                // A reset during compaction leaves the spare page in use
+                store.compact_spare(flash)?;
+            }
+            None => store.format(flash)?,
+        }
+        Ok(store)
+    }
+
+    /// Erase the region and start over without any keys
+    pub fn format(&mut self, flash: &mut UnlockedFlash) -> Result {
+        flash.erase_region(&self.region)?;
+        let first = self.next_page(flash, self.region.pages - 1);
+        self.open_page(flash, first, 0)
+    }
+
+    /// Largest value that can be stored
+    pub fn max_value_len(&self) -> usize {
+        self.page_len() - PAGE_HEADER_LEN - RECORD_HEADER_LEN
+    }
+
+    /// Copy the value of `key` into `buf` and return its length, `None` if the key isn't set.
+    ///
+    /// Fails with `Error::InvalidLength` if `buf` is too short for the value.
+    pub fn get(
+        &self,
+        flash: &UnlockedFlash,
+        key: u16,
+        buf: &mut [u8],
+    ) -> core::result::Result<Option<usize>, Error> {
+        let address = match self.latest(flash, key) {
+            Some(address) => address,
+            None => return Ok(None),
+        };
+        let (_, len) = self.record_header(flash, address);
+        if len == REMOVED {
+            return Ok(None);
+        }
+        let len = len as usize;
+        if buf.len() < len {
+            return Err(Error::InvalidLength);
+        }
+        flash.read(address + RECORD_HEADER_LEN, &mut buf[..len]);
+        Ok(Some(len))
+    }
+
+    /// Set `key` to `value`
+    pub fn set(&mut self, flash: &mut UnlockedFlash, key: u16, value: &[u8]) -> Result {
+        self.set_parts(flash, key, &[value])
+    }
+
+    /// Set `key` to the concatenation of `parts`, all but the last of which have an even length
+    pub(crate) fn set_parts(
+        &mut self,
+        flash: &mut UnlockedFlash,
+        key: u16,
+        parts: &[&[u8]],
+    ) -> Result {
+        let len: usize = parts.iter().map(|part| part.len()).sum();
+        if len > self.max_value_len() {
+            return Err(Error::InvalidLength);
+        }
+        self.append(flash, key, len as u16, parts)?;
+        self.auto_compact(flash)
+    }
+
+    /// Remove `key`, doing nothing if it isn't set
+    pub fn remove(&mut self, flash: &mut UnlockedFlash, key: u16) -> Result {
+        match self.latest(flash, key) {
+            Some(address) if self.record_header(flash, address).1 != REMOVED => {
+                self.append(flash, key, REMOVED, &[])?;
+                self.auto_compact(flash)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Bytes taken up by superseded, removed and torn records
+    pub fn stale_bytes(&self, flash: &UnlockedFlash) -> usize {
+        let mut stale = 0;
+        for page in 0..self.region.pages {
+            if self.page_sequence(flash, page).is_none() {
+                continue;
+            }
+            let end = if page == self.head {
+                self.offset
+            } else {
+                self.end_of_page(flash, page)
+            };
+            let mut offset = PAGE_HEADER_LEN;
+            while offset < end {
+                let address = self.page_address(page) + offset;
+                let (key, len) = self.record_header(flash, address);
+                let size = record_size(value_len(len));
+                if len == REMOVED
+                    || !self.is_valid(flash, address)
+                    || self.latest(flash, key) != Some(address)
+                {
+                    stale += size;
+                }
+                offset += size;
+            }
+        }
+        stale
+    }
+
+    /// Relocate every page, dropping all stale records. `progress` is called with the number
+    /// of pages done and the total after each page.
+    pub fn compact(
+        &mut self,
+        flash: &mut UnlockedFlash,
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result {
+        let total = flash.good_pages(&self.region).saturating_sub(1);
+        for done in 1..=total {
+            let next = self.next_page(flash, self.head);
+            self.open_page(flash, next, self.sequence.wrapping_add(1))?;
+            self.compact_spare(flash)?;
+            progress(done, total);
+        }
+        Ok(())
+    }
+
+    /// Compact after a `set` or `remove` once stale records take up `percent` of the region,
+    /// `None` to only compact lazily. Every check scans the whole store.
+    pub fn set_auto_compact(&mut self, percent: Option<u8>) {
+        self.auto_compact = percent;
+    }
+
+    fn auto_compact(&mut self, flash: &mut UnlockedFlash) -> Result {
+        let percent = match self.auto_compact {
+            Some(percent) => percent as usize,
+            None => return Ok(()),
+        };
+        if self.stale_bytes(flash) * 100 >= self.region.len() * percent {
+            self.compact(flash, |_, _| {})?;
+        }
+        Ok(())
+    }
+
+    fn append(
+        &mut self,
+        flash: &mut UnlockedFlash,
+        key: u16,
+        len: u16,
+        parts: &[&[u8]],
+    ) -> Result {
+        if key == END {
+            return Err(Error::OutOfBounds);
+        }
+        let size = record_size(value_len(len));
+        if self.offset + size > self.page_len() {
+            let next = self.next_page(flash, self.head);
+            self.open_page(flash, next, self.sequence.wrapping_add(1))?;
+            self.compact_spare(flash)?;
+            if self.offset + size > self.page_len() {
+                return Err(Error::RegionFull);
+            }
+        }
+
+        let mut crc = C::new();
+        crc.update(&key.to_ne_bytes());
+        crc.update(&len.to_ne_bytes());
+        for part in parts {
+            crc.update(part);
+        }
+        let crc = crc.finish().to_ne_bytes();
+
+        let address = self.page_address(self.head) + self.offset;
+        // The offset moves on even if programming fails half way, the torn record is skipped
+        self.offset += size;
+        // Key and length first so a torn record can be skipped, the CRC last
+        flash.write_native(address, &[key, len])?;
+        let mut value_address = address + RECORD_HEADER_LEN;
+        for part in parts.iter().filter(|part| !part.is_empty()) {
+            flash.write(value_address, part)?;
+            value_address += part.len();
+        }
+        flash.write(address + 4, &crc)
+    }
+
+    /// Erase the page behind the head, first copying the records that are still current
+    fn compact_spare(&mut self, flash: &mut UnlockedFlash) -> Result {
+        let spare = self.next_page(flash, self.head);
+        if self.page_sequence(flash, spare).is_none() {
+            if self.is_erased(flash, spare) {
+                return Ok(());
+            }
+        } else if flash.read_halfword(self.page_address(spare) + 2) != RELOCATING {
+            flash.write_native(self.page_address(spare) + 2, &[RELOCATING])?;
+        }
+
+        let mut offset = PAGE_HEADER_LEN;
+        let end = self.end_of_page(flash, spare);
+        let mut buf = [0u8; 64];
+        while offset < end {
+            let address = self.page_address(spare) + offset;
+            let (key, len) = self.record_header(flash, address);
+            offset += record_size(value_len(len));
+            if !self.is_valid(flash, address) || self.latest(flash, key) != Some(address) {
+                continue;
+            }
+            if len == REMOVED {
+                continue;
+            }
+            let size = record_size(len as usize);
+            if self.offset + size > self.page_len() {
+                return Err(Error::RegionFull);
+            }
+            let mut header = [0u8; RECORD_HEADER_LEN];
+            flash.read(address, &mut header);
+            let target = self.page_address(self.head) + self.offset;
+            self.offset += size;
+            flash.write(target, &header[..4])?;
+            let mut copied = 0;
+            while copied < len as usize {
+                let chunk = buf.len().min(len as usize - copied);
+                flash.read(address + RECORD_HEADER_LEN + copied, &mut buf[..chunk]);
+                flash.write(target + RECORD_HEADER_LEN + copied, &buf[..chunk])?;
+                copied += chunk;
+            }
+            flash.write(target + 4, &header[4..])?;
+        }
+        flash.erase_page(self.region.page(spare))
+    }
+
+    fn open_page(&mut self, flash: &mut UnlockedFlash, page: usize, sequence: u32) -> Result {
+        let address = self.page_address(page);
+        let sequence = sequence.to_ne_bytes();
+        flash.write_native(
+            address,
+            &[
+                PAGE_MAGIC,
+                0xffff,
+                u16::from_ne_bytes([sequence[0], sequence[1]]),
+                u16::from_ne_bytes([sequence[2], sequence[3]]),
+            ],
+        )?;
+        self.head = page;
+        self.sequence = u32::from_ne_bytes(sequence);
+        self.offset = PAGE_HEADER_LEN;
+        Ok(())
+    }
+
+    /// Address of the newest valid record of `key`, oldest page first
+    fn latest(&self, flash: &UnlockedFlash, key: u16) -> Option<usize> {
+        let mut latest = None;
+        for step in 1..=self.region.pages {
+            let page = (self.head + step) % self.region.pages;
+            if self.page_sequence(flash, page).is_none() {
+                continue;
+            }
+            let end = if page == self.head {
+                self.offset
+            } else {
+                self.end_of_page(flash, page)
+            };
+            let mut offset = PAGE_HEADER_LEN;
+            while offset < end {
+                let address = self.page_address(page) + offset;
+                let (record_key, len) = self.record_header(flash, address);
+                if record_key == key && self.is_valid(flash, address) {
+                    latest = Some(address);
+                }
+                offset += record_size(value_len(len));
+            }
+        }
+        latest
+    }
+
+    /// Offset one past the last record of `page`
+    fn end_of_page(&self, flash: &UnlockedFlash, page: usize) -> usize {
+        let mut offset = PAGE_HEADER_LEN;
+        while offset + RECORD_HEADER_LEN <= self.page_len() {
+            let (key, len) = self.record_header(flash, self.page_address(page) + offset);
+            if key == END && len == END {
+                break;
+            }
+            offset += record_size(value_len(len));
+        }
+        offset.min(self.page_len())
+    }
+
+    fn is_valid(&self, flash: &UnlockedFlash, address: usize) -> bool {
+        let mut header = [0u8; RECORD_HEADER_LEN];
+        flash.read(address, &mut header);
+        let len = u16::from_ne_bytes([header[2], header[3]]);
+        let value_len = value_len(len);
+        if address + record_size(value_len) > self.region.end_address() {
+            return false;
+        }
+
+        let mut crc = C::new();
+        crc.update(&header[..4]);
+        let mut buf = [0u8; 64];
+        let mut checked = 0;
+        while checked < value_len {
+            let chunk = buf.len().min(value_len - checked);
+            flash.read(address + RECORD_HEADER_LEN + checked, &mut buf[..chunk]);
+            crc.update(&buf[..chunk]);
+            checked += chunk;
+        }
+        crc.finish().to_ne_bytes() == header[4..]
+    }
+
+    fn record_header(&self, flash: &UnlockedFlash, address: usize) -> (u16, u16) {
+        (flash.read_halfword(address), flash.read_halfword(address + 2))
+    }
+
+    /// Sequence number of `page`, `None` for a page without a header or marked bad
+    fn page_sequence(&self, flash: &UnlockedFlash, page: usize) -> Option<u32> {
+        let address = self.page_address(page);
+        if flash.is_bad_page(self.region.page(page)) {
+            return None;
+        }
+        if flash.read_halfword(address) != PAGE_MAGIC {
+            return None;
+        }
+        let mut sequence = [0u8; 4];
+        flash.read(address + 4, &mut sequence);
+        Some(u32::from_ne_bytes(sequence))
+    }
+
+    fn is_erased(&self, flash: &UnlockedFlash, page: usize) -> bool {
+        let mut buf = [0u8; 64];
+        let start = self.page_address(page);
+        (start..start + self.page_len())
+            .step_by(buf.len())
+            .all(|address| {
+                flash.read(address, &mut buf);
+                buf.iter().all(|b| *b == ERASED_BYTE)
+            })
+    }
+
+    /// Next page after `page` that isn't marked bad, wrapping around
+    fn next_page(&self, flash: &UnlockedFlash, page: usize) -> usize {
+        (1..=self.region.pages)
+            .map(|step| (page + step) % self.region.pages)
+            .find(|next| !flash.is_bad_page(self.region.page(*next)))
+            .unwrap_or(page)
+    }
+
+    fn page_address(&self, page: usize) -> usize {
+        self.region.page(page).to_address()
+    }
+
+    fn page_len(&self) -> usize {
+        self.region.len() / self.region.pages
+    }
+}
+
+fn value_len(len: u16) -> usize {
+    match len {
+        REMOVED | END => 0,
+        len => len as usize,
+    }
+}
+
+fn record_size(value_len: usize) -> usize {
+    RECORD_HEADER_LEN + (value_len + 1) / 2 * 2
+}
@@ -0,0 +1,43 @@
+use crate::{FlashPage, FLASH_START};
+
+/// Flash geometry known at compile time.
+///
+/// Storage code that is generic over `L: Layout` works unchanged on devices with different page
+/// sizes or page counts.
+pub trait Layout {
+    /// Size of an erasable page in bytes
+    const PAGE_SIZE: u32;
+    /// Number of pages in main flash
+    const NUM_PAGES: u32;
+
+    /// Address of the first byte of `page`
+    fn page_address(page: FlashPage) -> usize {
+        FLASH_START + page.0 * Self::PAGE_SIZE as usize
+    }
+
+    /// Page containing `address`, which must not lie below `FLASH_START`
+    fn page_of(address: usize) -> FlashPage {
+        FlashPage((address - FLASH_START) / Self::PAGE_SIZE as usize)
+    }
+
+    /// Address one past the last byte of main flash
+    fn end_address() -> usize {
+        FLASH_START + (Self::PAGE_SIZE * Self::NUM_PAGES) as usize
+    }
+
+    fn contains_page(page: FlashPage) -> bool {
+        page.0 < Self::NUM_PAGES as usize
+    }
+}
+
+/// Geometry with `PAGE_SIZE` byte pages and `NUM_PAGES` of them.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FlashLayout<const PAGE_SIZE: u32, const NUM_PAGES: u32>;
+
+impl<const PAGE_SIZE: u32, const NUM_PAGES: u32> Layout for FlashLayout<PAGE_SIZE, NUM_PAGES> {
+    const PAGE_SIZE: u32 = PAGE_SIZE;
+    const NUM_PAGES: u32 = NUM_PAGES;
+}
+
+/// Geometry selected by the `flash-*` features
+pub type DefaultLayout = FlashLayout<{ crate::PAGE_SIZE }, { crate::NUM_PAGES }>;
@@ -0,0 +1,183 @@
+//! Build script helper turning the `MEMORY` block of a `memory.x` into `Region` constants.
+//!
+//! ```ignore
+//! // build.rs
+//! let out = std::path::PathBuf::from(std::env::var("OUT_DIR").unwrap()).join("flash_layout.rs");
+//! layout_gen::write_layout("memory.x", &out, 1024).unwrap();
+//! println!("cargo:rerun-if-changed=memory.x");
+//!
+//! // src/main.rs
+//! include!(concat!(env!("OUT_DIR"), "/flash_layout.rs"));
+//! ```
+//!
+//! Every memory region that lies in main flash, e.g. `FLASH`, `CONFIG`, `LOG` or `SCRATCH`,
+//! becomes a `pub const NAME_REGION: Region`, so `FLASH` turns into `FLASH_REGION` and doesn't
+//! collide with the `FLASH` peripheral of the PAC. The linker script stays the single source of
+//! truth, so the runtime bounds can never drift away from what the linker placed.
+
+extern crate std;
+
+use std::fmt::Write as _;
+use std::path::Path;
+use std::string::String;
+use std::vec::Vec;
+use std::{format, fs, io};
+
+use crate::FLASH_START;
+
+// End of the address window main flash can be mapped into
+const FLASH_WINDOW_END: u64 = 0x0810_0000;
+
+/// One entry of the `MEMORY` block
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub name: String,
+    pub origin: u64,
+    pub length: u64,
+}
+
+/// Why `memory.x` could not be turned into a layout
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LayoutError {
+    /// No `MEMORY { ... }` block was found
+    NoMemoryBlock,
+    /// A line of the `MEMORY` block could not be parsed
+    Syntax(String),
+    /// A flash region does not start or end on a page boundary
+    Unaligned(String),
+    /// Two flash regions share a page
+    Overlap(String, String),
+}
+
+/// Parse the `MEMORY` block of a linker script.
+pub fn parse_memory(script: &str) -> Result<Vec<MemoryRegion>, LayoutError> {
+    let script = strip_comments(script);
+    let start = script.find("MEMORY").ok_or(LayoutError::NoMemoryBlock)?;
+    let open = script[start..].find('{').ok_or(LayoutError::NoMemoryBlock)? + start;
+    let close = script[open..].find('}').ok_or(LayoutError::NoMemoryBlock)? + open;
+
+    let mut regions = Vec::new();
+    for line in script[open + 1..close].lines() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        regions.push(parse_line(line).ok_or_else(|| LayoutError::Syntax(line.into()))?);
+    }
+    Ok(regions)
+}
+
+/// Generate Rust source with a `Region` constant per flash region of `script`, named after the
+/// region with a `_REGION` suffix.
+pub fn generate(script: &str, page_size: u32) -> Result<String, LayoutError> {
+    let page_size = page_size as u64;
+    let mut flash: Vec<MemoryRegion> = parse_memory(script)?
+        .into_iter()
+        .filter(|r| r.origin >= FLASH_START as u64 && r.origin < FLASH_WINDOW_END)
+        .collect();
+    flash.sort_by_key(|r| r.origin);
+
+    for pair in flash.windows(2) {
+        if pair[0].origin + pair[0].length > pair[1].origin {
+            return Err(LayoutError::Overlap(pair[0].name.clone(), pair[1].name.clone()));
+        }
+    }
+
+    let mut out = String::from("// Generated from memory.x, do not edit.\n");
+    for region in &flash {
+        let offset = region.origin - FLASH_START as u64;
+        if offset % page_size != 0 || region.length % page_size != 0 {
+            return Err(LayoutError::Unaligned(region.name.clone()));
+        }
+        let _ = writeln!(
+            out,
+            "pub const {}_REGION: Region = Region::new(FlashPage({}), {});",
+            region.name.to_uppercase(),
+            offset / page_size,
+            region.length / page_size,
+        );
+    }
+    Ok(out)
+}
+
+/// Read the linker script at `memory_x` and write the generated layout to `out`.
+pub fn write_layout(
+    memory_x: impl AsRef<Path>,
+    out: impl AsRef<Path>,
+    page_size: u32,
+) -> io::Result<()> {
+    let script = fs::read_to_string(memory_x)?;
+    let source = generate(&script, page_size)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", e)))?;
+    fs::write(out, source)
+}
+
+// `NAME (rx) : ORIGIN = 0x08000000, LENGTH = 24K`
+fn parse_line(line: &str) -> Option<MemoryRegion> {
+    let (head, attrs) = line.split_once(':')?;
+    let name = head.split('(').next()?.trim();
+    if name.is_empty() {
+        return None;
+    }
+
+    let mut origin = None;
+    let mut length = None;
+    for field in attrs.split(',') {
+        let (key, value) = field.split_once('=')?;
+        let value = parse_number(value.trim())?;
+        match key.trim() {
+            "ORIGIN" | "org" | "o" => origin = Some(value),
+            "LENGTH" | "len" | "l" => length = Some(value),
+            _ => return None,
+        }
+    }
+
+    Some(MemoryRegion {
+        name: name.into(),
+        origin: origin?,
+        length: length?,
+    })
+}
+
+fn parse_number(text: &str) -> Option<u64> {
+    let (digits, scale) = match text.as_bytes().last()? {
+        b'K' | b'k' => (&text[..text.len() - 1], 1024),
+        b'M' | b'm' => (&text[..text.len() - 1], 1024 * 1024),
+        _ => (text, 1),
+    };
+    let value = match digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+        None => digits.parse().ok()?,
+    };
+    Some(value * scale)
+}
+
+fn strip_comments(script: &str) -> String {
+    let mut out = String::with_capacity(script.len());
+    let mut rest = script;
+    while let Some(start) = rest.find("/*") {
+        out.push_str(&rest[..start]);
+        rest = match rest[start..].find("*/") {
+            Some(end) => &rest[start + end + 2..],
+            None => "",
+        };
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flash_regions_get_suffixed_constants() {
+        let script = "MEMORY\n{\n  FLASH : ORIGIN = 0x08000000, LENGTH = 30K\n  \
+                      CONFIG : ORIGIN = 0x08007800, LENGTH = 2K\n  \
+                      RAM : ORIGIN = 0x20000000, LENGTH = 6K\n}\n";
+        let out = generate(script, 1024).unwrap();
+        assert!(out.contains("pub const FLASH_REGION: Region = Region::new(FlashPage(0), 30);"));
+        assert!(out.contains("pub const CONFIG_REGION: Region = Region::new(FlashPage(30), 2);"));
+        assert!(!out.contains("RAM"));
+    }
+}
@@ -0,0 +1,74 @@
+//! littlefs2 storage driver.
+
+use littlefs2::consts::{U1, U64};
+use littlefs2::driver::Storage;
+use littlefs2::io::{Error as IoError, Result as IoResult};
+
+use crate::{FlashPage, Read, UnlockedFlash, WriteErase, NUM_PAGES, PAGE_SIZE};
+
+/// littlefs `Storage` over `PAGES` pages starting at page `START`, one block per page.
+///
+/// The range is fixed at compile time because littlefs2 takes the block count as a constant.
+pub struct LittleFsStorage<const START: usize, const PAGES: usize> {
+    flash: UnlockedFlash,
+}
+
+impl<const START: usize, const PAGES: usize> LittleFsStorage<START, PAGES> {
+    const IN_FLASH: () = assert!(
+        START + PAGES <= NUM_PAGES as usize,
+        "littlefs page range exceeds the flash"
+    );
+
+    pub fn new(flash: UnlockedFlash) -> Self {
+        let () = Self::IN_FLASH;
+        LittleFsStorage { flash }
+    }
+
+    pub fn into_inner(self) -> UnlockedFlash {
+        self.flash
+    }
+
+    fn address(off: usize, len: usize) -> IoResult<usize> {
+        match off.checked_add(len) {
+            Some(end) if end <= PAGES * PAGE_SIZE as usize => {
+                Ok(FlashPage(START).to_address() + off)
+            }
+            _ => Err(IoError::Io),
+        }
+    }
+}
+
+impl<const START: usize, const PAGES: usize> Storage for LittleFsStorage<START, PAGES> {
+    const READ_SIZE: usize = 1;
+    // littlefs always programs whole halfwords at even offsets
+    const WRITE_SIZE: usize = 2;
+    const BLOCK_SIZE: usize = PAGE_SIZE as usize;
+    const BLOCK_COUNT: usize = PAGES;
+    type CACHE_SIZE = U64;
+    type LOOKAHEAD_SIZE = U1;
+
+    fn read(&mut self, off: usize, buf: &mut [u8]) -> IoResult<usize> {
+        let address = Self::address(off, buf.len())?;
+        Read::read(&self.flash, address, buf);
+        Ok(buf.len())
+    }
+
+    fn write(&mut self, off: usize, data: &[u8]) -> IoResult<usize> {
+        let address = Self::address(off, data.len())?;
+        self.flash
+            .write_strict(address, data)
+            .map_err(|_| IoError::Io)?;
+        Ok(data.len())
+    }
+
+    fn erase(&mut self, off: usize, len: usize) -> IoResult<usize> {
+        let address = Self::address(off, len)?;
+        let first = FlashPage::from_address(address).0;
+        for page in first..first + len / PAGE_SIZE as usize {
+            self.flash
+                .erase_page(FlashPage(page))
+                .map_err(|_| IoError::Io)?;
+        }
+        Ok(len)
+    }
+}
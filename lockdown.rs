@@ -0,0 +1,47 @@
+use core::convert::Infallible;
+
+use crate::{Error, FlashPage, RdpLevel, UnlockedFlash, WriteErase};
+
+impl UnlockedFlash {
+    /// Tamper response: destroy `secret_pages`, enable RDP level 1 and reload the option bytes.
+    ///
+    /// Every halfword of the secret pages is first programmed to `0x0000`, so the secrets are
+    /// gone even if the following erase is interrupted. Software protection is ignored for these
+    /// pages. Devices already at RDP level 1 or 2 keep their level. On success the option byte
+    /// load resets the device and this never returns.
+    pub fn lockdown(
+        mut self,
+        secret_pages: impl IntoIterator<Item = FlashPage>,
+    ) -> core::result::Result<Infallible, Error> {
+        self.soft_protected = 0;
+        self.skip_identical = false;
+
+        for page in secret_pages {
+            self.zeroize_page(page)?;
+            self.erase_page(page)?;
+        }
+
+        if self.rdp_level() == RdpLevel::Level0 {
+            self.set_rdp(RdpLevel::Level1)?;
+        }
+
+        #[cfg(not(feature = "stm32f1"))]
+        self.launch_option_bytes();
+        // The F1 loads option bytes on every system reset
+        #[cfg(feature = "stm32f1")]
+        cortex_m::peripheral::SCB::sys_reset();
+    }
+
+    fn zeroize_page(&mut self, page: FlashPage) -> crate::Result {
+        if page.0 >= self.num_pages() {
+            return Err(Error::PageOutOfRange);
+        }
+        let zeros = [0u16; 32];
+        let mut address = page.to_address();
+        while address < page.to_address() + crate::PAGE_SIZE as usize {
+            self.write_native(address, &zeros)?;
+            address += core::mem::size_of_val(&zeros);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,165 @@
+//! MCUboot image trailer at the end of a slot.
+//!
+//! Layout from the end of the slot, every field padded to `TRAILER_ALIGN` bytes:
+//!
+//! ```text
+//! swap status entries | swap size | swap info | copy done | image ok | magic (16 bytes)
+//! ```
+//!
+//! Flags and status entries each own an erased halfword, so they are programmed one at a time
+//! without erasing the slot.
+
+use crate::{Error, Read, Region, Result, UnlockedFlash, ERASED_BYTE};
+
+/// MCUboot `BOOT_MAX_ALIGN`, the padding of every trailer field
+pub const TRAILER_ALIGN: usize = 8;
+
+/// Image trailer magic
+pub const BOOT_MAGIC: [u8; 16] = [
+    0x77, 0xc2, 0x95, 0xf3, 0x60, 0xd2, 0xef, 0x7f, 0x35, 0x52, 0x50, 0x0f, 0x2c, 0xb6, 0x79, 0x80,
+];
+
+const BOOT_FLAG_SET: u8 = 0x01;
+const MAGIC_LEN: usize = BOOT_MAGIC.len();
+
+/// State of the trailer magic or of a flag
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TrailerState {
+    /// Written with the expected value
+    Set,
+    /// Still erased
+    Unset,
+    /// Neither, the trailer is corrupt
+    Bad,
+}
+
+/// Image trailer of a slot with room for `status_entries` swap status entries.
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ImageTrailer {
+    slot: Region,
+    status_entries: usize,
+}
+
+impl ImageTrailer {
+    pub const fn new(slot: Region, status_entries: usize) -> Self {
+        ImageTrailer {
+            slot,
+            status_entries,
+        }
+    }
+
+    /// Size of the trailer in bytes
+    pub const fn size(&self) -> usize {
+        (self.status_entries + 4) * TRAILER_ALIGN + MAGIC_LEN
+    }
+
+    /// Address of the first byte of the trailer
+    pub const fn start_address(&self) -> usize {
+        self.slot.end_address() - self.size()
+    }
+
+    pub fn magic(&self, flash: &impl Read<NativeType = u8>) -> TrailerState {
+        let mut magic = [0u8; MAGIC_LEN];
+        flash.read(self.magic_address(), &mut magic);
+        if magic == BOOT_MAGIC {
+            TrailerState::Set
+        } else if magic.iter().all(|b| *b == ERASED_BYTE) {
+            TrailerState::Unset
+        } else {
+            TrailerState::Bad
+        }
+    }
+
+    pub fn write_magic(&self, flash: &mut UnlockedFlash) -> Result {
+        flash.write_strict(self.magic_address(), &BOOT_MAGIC)
+    }
+
+    pub fn image_ok(&self, flash: &impl Read<NativeType = u8>) -> TrailerState {
+        flag(flash, self.field_address(1))
+    }
+
+    /// Confirm the image so MCUboot keeps it instead of reverting
+    pub fn set_image_ok(&self, flash: &mut UnlockedFlash) -> Result {
+        set_byte(flash, self.field_address(1), BOOT_FLAG_SET)
+    }
+
+    pub fn copy_done(&self, flash: &impl Read<NativeType = u8>) -> TrailerState {
+        flag(flash, self.field_address(2))
+    }
+
+    pub fn set_copy_done(&self, flash: &mut UnlockedFlash) -> Result {
+        set_byte(flash, self.field_address(2), BOOT_FLAG_SET)
+    }
+
+    /// Swap type in the low and image number in the high nibble, `None` while erased
+    pub fn swap_info(&self, flash: &impl Read<NativeType = u8>) -> Option<u8> {
+        let mut info = [0u8; 1];
+        flash.read(self.field_address(3), &mut info);
+        Some(info[0]).filter(|b| *b != ERASED_BYTE)
+    }
+
+    pub fn set_swap_info(&self, flash: &mut UnlockedFlash, swap_type: u8, image: u8) -> Result {
+        set_byte(flash, self.field_address(3), (image << 4) | (swap_type & 0x0f))
+    }
+
+    /// Number of bytes being swapped, `None` while erased
+    pub fn swap_size(&self, flash: &impl Read<NativeType = u8>) -> Option<u32> {
+        let mut size = [0u8; 4];
+        flash.read(self.field_address(4), &mut size);
+        Some(u32::from_le_bytes(size)).filter(|s| *s != u32::MAX)
+    }
+
+    pub fn set_swap_size(&self, flash: &mut UnlockedFlash, size: u32) -> Result {
+        flash.write_strict(self.field_address(4), &size.to_le_bytes())
+    }
+
+    /// Swap status entry `index`, `None` while erased
+    pub fn status(
+        &self,
+        flash: &impl Read<NativeType = u8>,
+        index: usize,
+    ) -> core::result::Result<Option<u8>, Error> {
+        let address = self.status_address(index)?;
+        let mut state = [0u8; 1];
+        flash.read(address, &mut state);
+        Ok(Some(state[0]).filter(|b| *b != ERASED_BYTE))
+    }
+
+    /// Record swap progress in entry `index`, which has to be erased
+    pub fn set_status(&self, flash: &mut UnlockedFlash, index: usize, state: u8) -> Result {
+        set_byte(flash, self.status_address(index)?, state)
+    }
+
+    fn magic_address(&self) -> usize {
+        self.slot.end_address() - MAGIC_LEN
+    }
+
+    /// Address of the `n`th field in front of the magic
+    fn field_address(&self, n: usize) -> usize {
+        self.magic_address() - n * TRAILER_ALIGN
+    }
+
+    fn status_address(&self, index: usize) -> core::result::Result<usize, Error> {
+        if index >= self.status_entries {
+            return Err(Error::OutOfBounds);
+        }
+        Ok(self.start_address() + index * TRAILER_ALIGN)
+    }
+}
+
+fn flag(flash: &impl Read<NativeType = u8>, address: usize) -> TrailerState {
+    let mut value = [0u8; 1];
+    flash.read(address, &mut value);
+    match value[0] {
+        BOOT_FLAG_SET => TrailerState::Set,
+        ERASED_BYTE => TrailerState::Unset,
+        _ => TrailerState::Bad,
+    }
+}
+
+// The byte shares its halfword with the erased padding after it
+fn set_byte(flash: &mut UnlockedFlash, address: usize, value: u8) -> Result {
+    flash.write_strict(address, &[value, ERASED_BYTE])
+}
@@ -0,0 +1,115 @@
+//! Redundant copies of a critical value, such as a calibration block, in separate pages.
+//!
+//! Every copy is a page starting with an 8 byte header followed by the bytes of the value:
+//!
+//! ```text
+//! magic (u16) | length (u16) | crc (u32) | value
+//! ```
+//!
+//! `store` rewrites the copies one after another, programming the magic of each last. A reset
+//! during the write leaves at most the copy being written broken, and since the copies are
+//! written in order the first good copy is always the newest complete value. `load` returns
+//! that copy, `repair` rewrites the other copies from it.
+
+use core::marker::PhantomData;
+use core::mem;
+
+use bytemuck::Pod;
+
+use crate::{Checksum, Crc32, Error, FlashPage, Read, Result, UnlockedFlash, WriteErase, PAGE_SIZE};
+
+const MAGIC: u16 = 0x4d52;
+const HEADER_LEN: usize = 8;
+
+/// A `T` kept in `N` pages
+pub struct Mirrored<T, const N: usize = 2> {
+    pages: [FlashPage; N],
+    _value: PhantomData<T>,
+}
+
+impl<T: Pod, const N: usize> Mirrored<T, N> {
+    /// Keep the copies in `pages`, which should not share a page with anything else
+    pub fn new(pages: [FlashPage; N]) -> core::result::Result<Self, Error> {
+        if N == 0 || HEADER_LEN + mem::size_of::<T>() > PAGE_SIZE as usize {
+            return Err(Error::InvalidLength);
+        }
+        Ok(Mirrored {
+            pages,
+            _value: PhantomData,
+        })
+    }
+
+    /// The value of the first good copy, `None` if every copy is broken or missing
+    pub fn load(&self, flash: &UnlockedFlash) -> Option<T> {
+        self.pages.iter().find_map(|page| read_copy(flash, *page))
+    }
+
+    /// Number of copies that pass their check
+    pub fn good_copies(&self, flash: &UnlockedFlash) -> usize {
+        self.pages
+            .iter()
+            .filter(|page| read_copy::<T>(flash, **page).is_some())
+            .count()
+    }
+
+    /// Write `value` to every copy in turn
+    pub fn store(&self, flash: &mut UnlockedFlash, value: &T) -> Result {
+        for page in self.pages.iter() {
+            write_copy(flash, *page, value)?;
+        }
+        Ok(())
+    }
+
+    /// Rewrite the copies that are broken or differ from the first good one. Returns how many
+    /// were rewritten, `Error::CrcMismatch` if no copy is good.
+    pub fn repair(&self, flash: &mut UnlockedFlash) -> core::result::Result<usize, Error> {
+        let value = self.load(flash).ok_or(Error::CrcMismatch)?;
+        let mut repaired = 0;
+        for page in self.pages.iter() {
+            let good = read_copy::<T>(flash, *page)
+                .map_or(false, |copy| bytemuck::bytes_of(&copy) == bytemuck::bytes_of(&value));
+            if !good {
+                write_copy(flash, *page, &value)?;
+                repaired += 1;
+            }
+        }
+        Ok(repaired)
+    }
+}
+
+fn read_copy<T: Pod>(flash: &UnlockedFlash, page: FlashPage) -> Option<T> {
+    let address = page.to_address();
+    let mut header = [0u8; HEADER_LEN];
+    flash.read(address, &mut header);
+    if u16::from_ne_bytes([header[0], header[1]]) != MAGIC
+        || u16::from_ne_bytes([header[2], header[3]]) as usize != mem::size_of::<T>()
+    {
+        return None;
+    }
+    let mut value = T::zeroed();
+    flash.read(address + HEADER_LEN, bytemuck::bytes_of_mut(&mut value));
+    let mut crc = Crc32::new();
+    crc.update(&header[2..4]);
+    crc.update(bytemuck::bytes_of(&value));
+    if crc.finish().to_ne_bytes() != header[4..] {
+        return None;
+    }
+    Some(value)
+}
+
+fn write_copy<T: Pod>(flash: &mut UnlockedFlash, page: FlashPage, value: &T) -> Result {
+    let bytes = bytemuck::bytes_of(value);
+    let len = (bytes.len() as u16).to_ne_bytes();
+    let mut crc = Crc32::new();
+    crc.update(&len);
+    crc.update(bytes);
+
+    let address = page.to_address();
+    flash.erase_page(page)?;
+    if !bytes.is_empty() {
+        flash.write(address + HEADER_LEN, bytes)?;
+    }
+    flash.write(address + 2, &len)?;
+    flash.write(address + 4, &crc.finish().to_ne_bytes())?;
+    flash.write_native(address, &[MAGIC])
+}
@@ -0,0 +1,197 @@
+//! `embedded-storage` support.
+//!
+//! Offsets are relative to `FLASH_START` and the capacity is the whole main flash.
+
+use embedded_storage::nor_flash::{
+    check_erase, check_read, check_write, ErrorType, MultiwriteNorFlash, NorFlash, NorFlashError,
+    NorFlashErrorKind, ReadNorFlash,
+};
+
+use crate::{Error, FlashPage, Read, UnlockedFlash, WriteErase, FLASH_START, PAGE_SIZE};
+
+const ERASED_HALFWORD: u16 = 0xffff;
+
+impl NorFlashError for Error {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            Error::Unaligned => NorFlashErrorKind::NotAligned,
+            Error::OutOfBounds | Error::PageOutOfRange => NorFlashErrorKind::OutOfBounds,
+            _ => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+impl From<NorFlashErrorKind> for Error {
+    fn from(kind: NorFlashErrorKind) -> Self {
+        match kind {
+            NorFlashErrorKind::NotAligned => Error::Unaligned,
+            NorFlashErrorKind::OutOfBounds => Error::OutOfBounds,
+            _ => Error::Failure,
+        }
+    }
+}
+
+impl ErrorType for UnlockedFlash {
+    type Error = Error;
+}
+
+impl ReadNorFlash for UnlockedFlash {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error> {
+        check_read(self, offset, bytes.len())?;
+        Read::read(self, FLASH_START + offset as usize, bytes);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.num_pages() * PAGE_SIZE as usize
+    }
+}
+
+impl NorFlash for UnlockedFlash {
+    const WRITE_SIZE: usize = 2;
+    const ERASE_SIZE: usize = PAGE_SIZE as usize;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        check_erase(self, from, to)?;
+        for offset in (from..to).step_by(Self::ERASE_SIZE) {
+            self.erase_page(FlashPage(offset as usize / Self::ERASE_SIZE))?;
+        }
+        Ok(())
+    }
+
+    /// Every halfword is programmed with the AND of its current and new value, halfwords that
+    /// would not change are skipped. All halfwords are checked before the first one is
+    /// programmed, see `halfword_update`.
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Error> {
+        check_write(self, offset, bytes.len())?;
+        write_halfwords(self, FLASH_START + offset as usize, bytes)
+    }
+}
+
+/// Halfword access the `NorFlash` writes are built on
+pub(crate) trait Halfwords {
+    fn halfword(&self, address: usize) -> u16;
+
+    fn program_halfword(&mut self, address: usize, value: u16) -> Result<(), Error>;
+}
+
+impl Halfwords for UnlockedFlash {
+    fn halfword(&self, address: usize) -> u16 {
+        self.read_halfword(address)
+    }
+
+    fn program_halfword(&mut self, address: usize, value: u16) -> Result<(), Error> {
+        self.write_native(address, &[value])
+    }
+}
+
+/// Store the halfwords of `bytes` from `start` on as `NorFlash::write` does, checking all of
+/// them with `halfword_update` before the first one is programmed
+pub(crate) fn write_halfwords(
+    flash: &mut impl Halfwords,
+    start: usize,
+    bytes: &[u8],
+) -> Result<(), Error> {
+    let halfwords = bytes.chunks_exact(2).enumerate().map(|(i, chunk)| {
+        let address = start + 2 * i;
+        (address, u16::from_ne_bytes([chunk[0], chunk[1]]))
+    });
+    for (address, new) in halfwords.clone() {
+        halfword_update(flash.halfword(address), new)?;
+    }
+    for (address, new) in halfwords {
+        if let Some(value) = halfword_update(flash.halfword(address), new)? {
+            flash.program_halfword(address, value)?;
+        }
+    }
+    Ok(())
+}
+
+/// Value to program over `current` to store `new`, `None` if the halfword already holds it.
+///
+/// The controller programs a halfword only while it is erased, or with `0x0000` over any value,
+/// anything else fails with PGERR. Such writes are refused up front with
+/// `Error::ProgrammingError` instead of leaving a partly programmed range behind.
+fn halfword_update(current: u16, new: u16) -> Result<Option<u16>, Error> {
+    let desired = current & new;
+    if desired == current {
+        Ok(None)
+    } else if current == ERASED_HALFWORD || desired == 0 {
+        Ok(Some(desired))
+    } else {
+        Err(Error::ProgrammingError)
+    }
+}
+
+/// Clearing programmed halfwords to zero, as for tombstones and flags, works without an erase.
+/// Any other change to a programmed halfword is refused with `Error::ProgrammingError`, see
+/// `halfword_update`.
+impl MultiwriteNorFlash for UnlockedFlash {}
+
+/// The controller stalls the core while it erases or programs, so these complete without
+/// yielding. They let async storage layers drive the flash directly.
+#[cfg(feature = "async")]
+impl embedded_storage_async::nor_flash::ReadNorFlash for UnlockedFlash {
+    const READ_SIZE: usize = <Self as ReadNorFlash>::READ_SIZE;
+
+    async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error> {
+        ReadNorFlash::read(self, offset, bytes)
+    }
+
+    fn capacity(&self) -> usize {
+        ReadNorFlash::capacity(self)
+    }
+}
+
+#[cfg(feature = "async")]
+impl embedded_storage_async::nor_flash::NorFlash for UnlockedFlash {
+    const WRITE_SIZE: usize = <Self as NorFlash>::WRITE_SIZE;
+    const ERASE_SIZE: usize = <Self as NorFlash>::ERASE_SIZE;
+
+    async fn erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        NorFlash::erase(self, from, to)
+    }
+
+    async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Error> {
+        NorFlash::write(self, offset, bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn erased_halfword_takes_any_value() {
+        assert_eq!(halfword_update(0xffff, 0x1234).unwrap(), Some(0x1234));
+        assert_eq!(halfword_update(0xffff, 0x0000).unwrap(), Some(0x0000));
+    }
+
+    #[test]
+    fn unchanged_halfword_is_skipped() {
+        assert_eq!(halfword_update(0x1234, 0x1234).unwrap(), None);
+        assert_eq!(halfword_update(0xffff, 0xffff).unwrap(), None);
+        // Bits can't be set again, the AND with the current value leaves it unchanged
+        assert_eq!(halfword_update(0x1234, 0xffff).unwrap(), None);
+    }
+
+    #[test]
+    fn programmed_halfword_can_be_cleared() {
+        assert_eq!(halfword_update(0x1234, 0x0000).unwrap(), Some(0x0000));
+        assert_eq!(halfword_update(0x00ff, 0xff00).unwrap(), Some(0x0000));
+    }
+
+    #[test]
+    fn programmed_halfword_refuses_partial_clear() {
+        assert!(matches!(
+            halfword_update(0x1234, 0x1230),
+            Err(Error::ProgrammingError)
+        ));
+        assert!(matches!(
+            halfword_update(0xff00, 0x0f00),
+            Err(Error::ProgrammingError)
+        ));
+    }
+}
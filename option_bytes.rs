@@ -0,0 +1,620 @@
+//! Option bytes, see RM0091 section 4 "Option bytes".
+
+#[cfg(not(feature = "stm32f1"))]
+use crate::regs::CR_OBL_LAUNCH;
+use crate::regs::{
+    FlashRegisters, CR_OPTER, CR_OPTPG, CR_OPTWRE, CR_STRT, SR_EOP, SR_PGERR, SR_WRPRT,
+};
+use crate::{
+    cs, ram, Error, FlashPage, Result, UnlockedFlash, FLASH, FLASH_KEY1, FLASH_KEY2, NUM_PAGES,
+    PAGE_SIZE,
+};
+
+/// Address of the option byte block in system memory
+pub const OPTION_BYTES_ADDRESS: usize = 0x1fff_f800;
+
+// Offsets of the option bytes, each stored as value and complement in one halfword
+const RDP_OFFSET: usize = 0x0;
+const USER_OFFSET: usize = 0x2;
+const DATA0_OFFSET: usize = 0x4;
+const DATA1_OFFSET: usize = 0x6;
+const WRP_OFFSET: usize = 0x8;
+// WRP2 and WRP3 only exist on parts with more than 64 KB
+const WRP_BYTES: usize = if NUM_PAGES * PAGE_SIZE > 64 * 1024 { 4 } else { 2 };
+
+/// Pages covered by one write protection bit, each bit protects a 4 KB sector
+pub const WRP_PAGES_PER_BIT: usize = 4096 / PAGE_SIZE as usize;
+
+// RDP byte values, anything else selects level 1
+const RDP_LEVEL0: u8 = 0xaa;
+const RDP_LEVEL1: u8 = 0xbb;
+const RDP_LEVEL2: u8 = 0xcc;
+
+// FLASH_OBR fields
+const OBR_OPTERR: u32 = 1 << 0;
+const OBR_RDPRT_SHIFT: u32 = 1;
+const OBR_RDPRT_MASK: u32 = 0b11;
+const OBR_USER_SHIFT: u32 = 8;
+const OBR_DATA0_SHIFT: u32 = 16;
+const OBR_DATA1_SHIFT: u32 = 24;
+
+// Bits of the USER option byte
+const USER_WDG_SW: u8 = 1 << 0;
+const USER_NRST_STOP: u8 = 1 << 1;
+const USER_NRST_STDBY: u8 = 1 << 2;
+const USER_NBOOT0: u8 = 1 << 3;
+const USER_NBOOT1: u8 = 1 << 4;
+const USER_VDDA_MONITOR: u8 = 1 << 5;
+const USER_RAM_PARITY_CHECK: u8 = 1 << 6;
+const USER_BOOT_SEL: u8 = 1 << 7;
+
+/// Readout protection level
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RdpLevel {
+    /// No protection
+    Level0,
+    /// Flash can't be read by the debugger or from RAM/system memory boot
+    Level1,
+    /// Like level 1 with the debug port disabled for good. Irreversible.
+    Level2,
+}
+
+/// Acknowledgement that RDP level 2 disables debugging and option byte changes for good.
+///
+/// Only `set_rdp_level2` takes it, so level 2 can't be reached by passing the wrong enum value.
+#[derive(Debug)]
+pub struct IrreversibleToken {
+    _private: (),
+}
+
+impl IrreversibleToken {
+    /// Create the token. Programming level 2 with it can never be undone.
+    pub const fn i_understand_level2_is_permanent() -> Self {
+        IrreversibleToken { _private: () }
+    }
+}
+
+/// Acknowledgement that leaving RDP level 1 mass-erases all of main flash, including the
+/// running firmware.
+#[derive(Debug)]
+pub struct MassEraseAck {
+    _private: (),
+}
+
+impl MassEraseAck {
+    /// Create the acknowledgement. Regressing with it wipes main flash.
+    pub const fn i_understand_main_flash_is_erased() -> Self {
+        MassEraseAck { _private: () }
+    }
+}
+
+/// User option bits. `true` means the bit is set.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct UserOptions {
+    /// Software watchdog, hardware watchdog when cleared
+    pub wdg_sw: bool,
+    /// No reset when entering Stop mode
+    pub n_rst_stop: bool,
+    /// No reset when entering Standby mode
+    pub n_rst_stdby: bool,
+    /// BOOT0 value when `boot_sel` is cleared
+    pub n_boot0: bool,
+    /// Together with BOOT0 selects the boot memory
+    pub n_boot1: bool,
+    /// VDDA power supply supervisor enabled
+    pub vdda_monitor: bool,
+    /// RAM parity check disabled
+    pub ram_parity_check: bool,
+    /// BOOT0 taken from the pin, from `n_boot0` when cleared
+    pub boot_sel: bool,
+}
+
+/// Decoded option bytes as loaded at the last option byte load.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct OptionBytes {
+    pub rdp: RdpLevel,
+    pub user: UserOptions,
+    pub data0: u8,
+    pub data1: u8,
+    /// Write protection bitmap, a set bit means the corresponding page group is protected
+    pub write_protection: u32,
+    /// The option bytes did not match their complements at the last load
+    pub option_error: bool,
+}
+
+impl RdpLevel {
+    const fn byte(self) -> u8 {
+        match self {
+            RdpLevel::Level0 => RDP_LEVEL0,
+            RdpLevel::Level1 => RDP_LEVEL1,
+            RdpLevel::Level2 => RDP_LEVEL2,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Self {
+        match byte {
+            RDP_LEVEL0 => RdpLevel::Level0,
+            RDP_LEVEL2 => RdpLevel::Level2,
+            _ => RdpLevel::Level1,
+        }
+    }
+
+    fn from_rdprt(bits: u32) -> Self {
+        match bits {
+            0b00 => RdpLevel::Level0,
+            0b11 => RdpLevel::Level2,
+            _ => RdpLevel::Level1,
+        }
+    }
+}
+
+impl UserOptions {
+    pub const fn from_bits(bits: u8) -> Self {
+        UserOptions {
+            wdg_sw: bits & USER_WDG_SW != 0,
+            n_rst_stop: bits & USER_NRST_STOP != 0,
+            n_rst_stdby: bits & USER_NRST_STDBY != 0,
+            n_boot0: bits & USER_NBOOT0 != 0,
+            n_boot1: bits & USER_NBOOT1 != 0,
+            vdda_monitor: bits & USER_VDDA_MONITOR != 0,
+            ram_parity_check: bits & USER_RAM_PARITY_CHECK != 0,
+            boot_sel: bits & USER_BOOT_SEL != 0,
+        }
+    }
+
+    pub const fn bits(&self) -> u8 {
+        (self.wdg_sw as u8) * USER_WDG_SW
+            | (self.n_rst_stop as u8) * USER_NRST_STOP
+            | (self.n_rst_stdby as u8) * USER_NRST_STDBY
+            | (self.n_boot0 as u8) * USER_NBOOT0
+            | (self.n_boot1 as u8) * USER_NBOOT1
+            | (self.vdda_monitor as u8) * USER_VDDA_MONITOR
+            | (self.ram_parity_check as u8) * USER_RAM_PARITY_CHECK
+            | (self.boot_sel as u8) * USER_BOOT_SEL
+    }
+}
+
+impl OptionBytes {
+    fn decode(obr: u32, wrpr: u32) -> Self {
+        OptionBytes {
+            rdp: RdpLevel::from_rdprt((obr >> OBR_RDPRT_SHIFT) & OBR_RDPRT_MASK),
+            user: UserOptions::from_bits((obr >> OBR_USER_SHIFT) as u8),
+            data0: (obr >> OBR_DATA0_SHIFT) as u8,
+            data1: (obr >> OBR_DATA1_SHIFT) as u8,
+            // WRP bits read as 0 while protection is active
+            write_protection: !wrpr,
+            option_error: obr & OBR_OPTERR != 0,
+        }
+    }
+
+    /// Decode the halfwords of the option byte block, starting with RDP. A value that doesn't
+    /// match its complement, as in an erased block, sets `option_error`.
+    fn decode_block(block: &[u16; 4 + WRP_BYTES]) -> Self {
+        let byte = |offset: usize| block[offset / 2] as u8;
+        let mut write_protection = 0;
+        for i in 0..WRP_BYTES {
+            write_protection |= (!byte(WRP_OFFSET + 2 * i) as u32) << (8 * i);
+        }
+        OptionBytes {
+            rdp: RdpLevel::from_byte(byte(RDP_OFFSET)),
+            user: UserOptions::from_bits(byte(USER_OFFSET)),
+            data0: byte(DATA0_OFFSET),
+            data1: byte(DATA1_OFFSET),
+            write_protection,
+            option_error: block
+                .iter()
+                .any(|halfword| (*halfword >> 8) as u8 != !(*halfword as u8)),
+        }
+    }
+}
+
+/// Fails with `Error::Irreversible` unless going from the loaded RDP level `current` to `new` is
+/// a change `program_option_bytes` may make without an explicit acknowledgement
+fn check_rdp_transition(current: RdpLevel, new: RdpLevel) -> Result {
+    match (current, new) {
+        (RdpLevel::Level2, _) | (_, RdpLevel::Level2) => Err(Error::Irreversible),
+        (RdpLevel::Level1, RdpLevel::Level0) => Err(Error::Irreversible),
+        _ => Ok(()),
+    }
+}
+
+// The last bit covers all remaining sectors on parts with more than 32 of them
+fn wrp_bit(page: FlashPage) -> u32 {
+    1 << core::cmp::min(page.0 / WRP_PAGES_PER_BIT, 31)
+}
+
+/// Decode the option bytes from FLASH_OBR and FLASH_WRPR
+pub fn read_option_bytes(flash: &FLASH) -> OptionBytes {
+    OptionBytes::decode(flash.read_obr(), flash.read_wrpr())
+}
+
+/// Builder for the user option bits, started from the pending values by `modify_user_options`.
+///
+/// All changes are programmed together in one option byte pass by `write`.
+pub struct UserOptionsWriter<'a> {
+    flash: &'a mut UnlockedFlash,
+    user: UserOptions,
+}
+
+macro_rules! user_option_setters {
+    ($($(#[$doc:meta])* $name:ident),*) => {
+        $(
+            $(#[$doc])*
+            pub fn $name(mut self, value: bool) -> Self {
+                self.user.$name = value;
+                self
+            }
+        )*
+    };
+}
+
+impl<'a> UserOptionsWriter<'a> {
+    user_option_setters!(
+        /// Software watchdog when set, hardware watchdog when cleared
+        wdg_sw,
+        /// No reset when entering Stop mode
+        n_rst_stop,
+        /// No reset when entering Standby mode
+        n_rst_stdby,
+        /// BOOT0 value when `boot_sel` is cleared
+        n_boot0,
+        /// Together with BOOT0 selects the boot memory
+        n_boot1,
+        /// VDDA power supply supervisor enabled
+        vdda_monitor,
+        /// RAM parity check disabled
+        ram_parity_check,
+        /// BOOT0 taken from the pin, from `n_boot0` when cleared
+        boot_sel
+    );
+
+    /// Program the user option bits, keeping all other option bytes.
+    ///
+    /// Takes effect after the next option byte load.
+    pub fn write(self) -> Result {
+        let mut ob = self.flash.read_pending_option_bytes();
+        if ob.user == self.user {
+            return Ok(());
+        }
+        ob.user = self.user;
+        self.flash.program_option_bytes(&ob)
+    }
+}
+
+/// Option bytes opened for erasing and programming. OPTWRE is cleared again when it is dropped.
+///
+/// Changes only take effect after the next option byte load.
+pub struct OptionByteSession<'a> {
+    flash: &'a mut UnlockedFlash,
+}
+
+impl UnlockedFlash {
+    /// Decode the option bytes from FLASH_OBR and FLASH_WRPR
+    pub fn read_option_bytes(&self) -> OptionBytes {
+        read_option_bytes(&self.f)
+    }
+
+    /// Decode the option bytes programmed in the block at `OPTION_BYTES_ADDRESS`.
+    ///
+    /// Unlike `read_option_bytes` this includes changes waiting for the next option byte load,
+    /// so the helpers changing some option bytes start from here and don't drop earlier
+    /// changes to the others. A block that doesn't match its complements, left by an
+    /// interrupted erase or programming pass, gives the loaded option bytes instead, so its
+    /// erased RDP byte doesn't turn into a change to level 1.
+    pub fn read_pending_option_bytes(&self) -> OptionBytes {
+        let mut block = [0u16; 4 + WRP_BYTES];
+        for (i, halfword) in block.iter_mut().enumerate() {
+            *halfword = unsafe { ((OPTION_BYTES_ADDRESS + 2 * i) as *const u16).read_volatile() };
+        }
+        let ob = OptionBytes::decode_block(&block);
+        if ob.option_error {
+            return self.read_option_bytes();
+        }
+        ob
+    }
+
+    /// Unlock the option bytes with the OPTKEYR key sequence
+    pub fn unlock_option_bytes(&mut self) -> core::result::Result<OptionByteSession<'_>, Error> {
+        while self.f.is_busy() {}
+
+        self.f.write_optkeyr(FLASH_KEY1);
+        self.f.write_optkeyr(FLASH_KEY2);
+
+        if self.f.read_cr() & CR_OPTWRE == 0 {
+            return Err(Error::Locked);
+        }
+        Ok(OptionByteSession { flash: self })
+    }
+
+    /// Replace all option bytes with `ob` in one erase and program pass.
+    ///
+    /// `option_error` is ignored. The new values are only loaded at the next option byte load.
+    /// The RDP level of `ob` is checked against the loaded one, `Error::Irreversible` refuses to
+    /// select level 2, see `set_rdp_level2`, to drop from level 1 to level 0, which mass-erases
+    /// main flash, see `regress_rdp_to_level0`, and any change at level 2.
+    pub fn program_option_bytes(&mut self, ob: &OptionBytes) -> Result {
+        check_rdp_transition(self.rdp_level(), ob.rdp)?;
+        self.write_option_bytes(ob)
+    }
+
+    /// Snapshot of the option byte block for `restore_option_bytes`, changes not loaded yet
+    /// included
+    pub fn backup_option_bytes(&self) -> OptionBytes {
+        self.read_pending_option_bytes()
+    }
+
+    /// Program the option bytes back to a snapshot taken with `backup_option_bytes`.
+    ///
+    /// Like `program_option_bytes` this can't go back from RDP level 1 to level 0. Takes effect
+    /// after the next option byte load.
+    pub fn restore_option_bytes(&mut self, backup: &OptionBytes) -> Result {
+        self.program_option_bytes(backup)
+    }
+
+    /// User option bits as loaded
+    pub fn user_options(&self) -> UserOptions {
+        self.read_option_bytes().user
+    }
+
+    /// Start changing the user option bits from the values in the option byte block, which
+    /// include changes not loaded yet
+    pub fn modify_user_options(&mut self) -> UserOptionsWriter<'_> {
+        let user = self.read_pending_option_bytes().user;
+        UserOptionsWriter { flash: self, user }
+    }
+
+    /// The Data0 and Data1 user option bytes as loaded.
+    ///
+    /// They are not touched by a main flash mass erase, which makes them handy for a board
+    /// revision or a boot mode flag.
+    pub fn read_user_data(&self) -> (u8, u8) {
+        let ob = self.read_option_bytes();
+        (ob.data0, ob.data1)
+    }
+
+    /// Program the Data0 and Data1 user option bytes, keeping all other option bytes.
+    ///
+    /// Takes effect after the next option byte load.
+    pub fn write_user_data(&mut self, data0: u8, data1: u8) -> Result {
+        let mut ob = self.read_pending_option_bytes();
+        if (ob.data0, ob.data1) == (data0, data1) {
+            return Ok(());
+        }
+        ob.data0 = data0;
+        ob.data1 = data1;
+        self.program_option_bytes(&ob)
+    }
+
+    /// Currently active readout protection level
+    pub fn rdp_level(&self) -> RdpLevel {
+        self.read_option_bytes().rdp
+    }
+
+    /// Change the readout protection level, keeping all other option bytes.
+    ///
+    /// Like `program_option_bytes` this only raises level 0 to level 1, level 2 and the level 1
+    /// to level 0 regression are refused with `Error::Irreversible`.
+    pub fn set_rdp(&mut self, level: RdpLevel) -> Result {
+        let mut ob = self.read_pending_option_bytes();
+        ob.rdp = level;
+        self.program_option_bytes(&ob)
+    }
+
+    /// Write protection bitmap as loaded, a set bit means the sector is protected
+    pub fn write_protection_map(&self) -> u32 {
+        self.read_option_bytes().write_protection
+    }
+
+    /// Whether `page` is write protected by the WRP option bytes
+    pub fn is_write_protected(&self, page: FlashPage) -> bool {
+        self.write_protection_map() & wrp_bit(page) != 0
+    }
+
+    /// Write protect exactly the sectors containing `pages`, keeping all other option bytes.
+    ///
+    /// Protection works on `WRP_PAGES_PER_BIT` pages at a time, so neighbouring pages of the
+    /// same sector get protected as well. Takes effect after the next option byte load.
+    pub fn set_write_protection(&mut self, pages: impl IntoIterator<Item = FlashPage>) -> Result {
+        let mut map = 0;
+        for page in pages {
+            if page.0 >= self.num_pages() {
+                return Err(Error::PageOutOfRange);
+            }
+            map |= wrp_bit(page);
+        }
+        let mut ob = self.read_pending_option_bytes();
+        ob.write_protection = map;
+        self.program_option_bytes(&ob)
+    }
+
+    /// Permanently select RDP level 2, keeping all other option bytes. Nothing is programmed
+    /// if level 2 is already loaded.
+    pub fn set_rdp_level2(&mut self, _token: IrreversibleToken) -> Result {
+        if self.rdp_level() == RdpLevel::Level2 {
+            return Ok(());
+        }
+        let mut ob = self.read_pending_option_bytes();
+        ob.rdp = RdpLevel::Level2;
+        self.write_option_bytes(&ob)
+    }
+
+    /// Load the option bytes right away by setting OBL_LAUNCH.
+    ///
+    /// The option byte load resets the whole device, so this never returns. Everything not yet
+    /// in flash is lost, make sure pending writes have completed before calling it.
+    #[cfg(not(feature = "stm32f1"))]
+    pub fn launch_option_bytes(self) -> ! {
+        while self.f.is_busy() {}
+
+        // OBL_LAUNCH can only be set while the option bytes are unlocked
+        self.f.write_optkeyr(FLASH_KEY1);
+        self.f.write_optkeyr(FLASH_KEY2);
+        self.f.set_cr(CR_OBL_LAUNCH);
+
+        loop {
+            cortex_m::asm::nop();
+        }
+    }
+
+    /// Go back from RDP level 1 to level 0, keeping the user, data and WRP option bytes.
+    ///
+    /// The hardware mass-erases main flash as part of the transition, so the option byte rewrite
+    /// runs from RAM with interrupts disabled, waits for every step to complete and finishes with
+    /// an option byte load that resets the blank device. It only returns when refused with
+    /// `Error::Irreversible` because the loaded level isn't level 1.
+    pub fn regress_rdp_to_level0(
+        &mut self,
+        _ack: MassEraseAck,
+    ) -> core::result::Result<core::convert::Infallible, Error> {
+        if self.rdp_level() != RdpLevel::Level1 {
+            return Err(Error::Irreversible);
+        }
+        let ob = self.read_pending_option_bytes();
+        let mut image = [(0usize, 0u8); 4 + WRP_BYTES];
+        image[0] = (OPTION_BYTES_ADDRESS + RDP_OFFSET, RDP_LEVEL0);
+        image[1] = (OPTION_BYTES_ADDRESS + USER_OFFSET, ob.user.bits());
+        image[2] = (OPTION_BYTES_ADDRESS + DATA0_OFFSET, ob.data0);
+        image[3] = (OPTION_BYTES_ADDRESS + DATA1_OFFSET, ob.data1);
+        for i in 0..WRP_BYTES {
+            let wrp = !(ob.write_protection >> (8 * i)) as u8;
+            image[4 + i] = (OPTION_BYTES_ADDRESS + WRP_OFFSET + 2 * i, wrp);
+        }
+
+        while self.f.is_busy() {}
+        self.f.clear_sr(SR_PGERR | SR_WRPRT | SR_EOP);
+        self.f.write_optkeyr(FLASH_KEY1);
+        self.f.write_optkeyr(FLASH_KEY2);
+
+        cortex_m::interrupt::disable();
+        unsafe { ram::rewrite_option_bytes(image.as_ptr(), image.len()) }
+    }
+
+    /// Leave the new option bytes to be loaded at the next power-on reset and lock the flash.
+    ///
+    /// A plain system reset does not load option bytes, until the next power cycle FLASH_OBR
+    /// and everything read from it keep reporting the old values.
+    pub fn defer_option_bytes(self) -> FLASH {
+        self.lock()
+    }
+
+    fn write_option_bytes(&mut self, ob: &OptionBytes) -> Result {
+        let mut session = self.unlock_option_bytes()?;
+        session.erase()?;
+        session.program_all(ob)
+    }
+}
+
+impl<'a> OptionByteSession<'a> {
+    /// Erase the option byte block.
+    ///
+    /// An erased RDP byte selects level 1, so the block has to be programmed afterwards.
+    pub fn erase(&mut self) -> Result {
+        let f = &self.flash.f;
+        while f.is_busy() {}
+        f.clear_sr(SR_PGERR | SR_WRPRT);
+
+        cs::free(|| {
+            f.set_cr(CR_OPTER);
+            f.set_cr(CR_STRT);
+        });
+        let result = self.flash.wait();
+        self.flash.f.clear_cr(CR_OPTER);
+        self.finish(result)
+    }
+
+    /// Program one option byte at `offset` into the block. The hardware writes the complement
+    /// into the upper half of the halfword.
+    pub fn program_byte(&mut self, offset: usize, value: u8) -> Result {
+        let f = &self.flash.f;
+        while f.is_busy() {}
+        f.clear_sr(SR_PGERR | SR_WRPRT);
+
+        f.set_cr(CR_OPTPG);
+        cs::free(|| unsafe {
+            ((OPTION_BYTES_ADDRESS + offset) as *mut u16).write_volatile(value as u16);
+        });
+        let result = self.flash.wait();
+        self.flash.f.clear_cr(CR_OPTPG);
+        self.finish(result)?;
+
+        let stored = unsafe { ((OPTION_BYTES_ADDRESS + offset) as *const u16).read_volatile() };
+        if stored != (value as u16 | (!value as u16) << 8) {
+            return Err(Error::ProgrammingError);
+        }
+        Ok(())
+    }
+
+    /// Program every option byte of `ob` into a freshly erased block
+    pub fn program_all(&mut self, ob: &OptionBytes) -> Result {
+        self.program_byte(RDP_OFFSET, ob.rdp.byte())?;
+        self.program_byte(USER_OFFSET, ob.user.bits())?;
+        self.program_byte(DATA0_OFFSET, ob.data0)?;
+        self.program_byte(DATA1_OFFSET, ob.data1)?;
+        for i in 0..WRP_BYTES {
+            // WRP bits are cleared to protect
+            let wrp = !(ob.write_protection >> (8 * i)) as u8;
+            self.program_byte(WRP_OFFSET + 2 * i, wrp)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, result: Result) -> Result {
+        if self.flash.f.read_sr() & SR_EOP != 0 {
+            self.flash.f.clear_sr(SR_EOP);
+        }
+        result
+    }
+}
+
+impl<'a> Drop for OptionByteSession<'a> {
+    fn drop(&mut self) {
+        self.flash.f.clear_cr(CR_OPTWRE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn halfword(value: u8) -> u16 {
+        value as u16 | (!value as u16) << 8
+    }
+
+    #[test]
+    fn programmed_block_is_decoded() {
+        let mut block = [halfword(0xff); 4 + WRP_BYTES];
+        block[RDP_OFFSET / 2] = halfword(RDP_LEVEL0);
+        block[USER_OFFSET / 2] = halfword(USER_WDG_SW | USER_BOOT_SEL);
+        block[DATA0_OFFSET / 2] = halfword(0x12);
+        block[DATA1_OFFSET / 2] = halfword(0x34);
+        block[WRP_OFFSET / 2] = halfword(!0b101);
+        let ob = OptionBytes::decode_block(&block);
+        assert_eq!(ob.rdp, RdpLevel::Level0);
+        assert_eq!(ob.user.bits(), USER_WDG_SW | USER_BOOT_SEL);
+        assert_eq!((ob.data0, ob.data1), (0x12, 0x34));
+        assert_eq!(ob.write_protection, 0b101);
+        assert!(!ob.option_error);
+    }
+
+    #[test]
+    fn rdp_transitions_need_acknowledgement() {
+        use RdpLevel::*;
+        assert!(check_rdp_transition(Level0, Level0).is_ok());
+        assert!(check_rdp_transition(Level0, Level1).is_ok());
+        assert!(check_rdp_transition(Level1, Level1).is_ok());
+        assert!(matches!(check_rdp_transition(Level1, Level0), Err(Error::Irreversible)));
+        assert!(matches!(check_rdp_transition(Level0, Level2), Err(Error::Irreversible)));
+        assert!(matches!(check_rdp_transition(Level1, Level2), Err(Error::Irreversible)));
+        assert!(matches!(check_rdp_transition(Level2, Level2), Err(Error::Irreversible)));
+    }
+
+    #[test]
+    fn erased_block_is_level1_with_option_error() {
+        let ob = OptionBytes::decode_block(&[0xffff; 4 + WRP_BYTES]);
+        assert_eq!(ob.rdp, RdpLevel::Level1);
+        assert!(ob.option_error);
+    }
+}
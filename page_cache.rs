@@ -0,0 +1,122 @@
+//! One page RAM cache that coalesces small writes into a single page update.
+//!
+//! Writes land in a copy of the page in RAM and are written back with `update_page`, which
+//! only erases when a halfword has to go from programmed to anything but `0x0000`. Many small
+//! updates of the same page so cost one erase and program cycle instead of one each. Nothing
+//! reaches flash before the write-back, so a reset loses every change since the last one.
+
+use crate::{Error, FlashPage, Read, Result, UnlockedFlash, PAGE_SIZE};
+
+/// When `PageCache` writes the cached page back
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FlushPolicy {
+    /// Only on `flush`. Writing to another page while the cached one has changes fails with
+    /// `Error::UnflushedCache`.
+    Explicit,
+    /// On `flush` and before writing to another page
+    OnPageSwitch,
+    /// Like `OnPageSwitch`, and when the cache is dropped. Errors during the drop are lost.
+    OnDrop,
+}
+
+/// Write-back cache of one page in front of the flash
+pub struct PageCache<'a> {
+    flash: &'a mut UnlockedFlash,
+    policy: FlushPolicy,
+    page: Option<FlashPage>,
+    dirty: bool,
+    buf: [u8; PAGE_SIZE as usize],
+}
+
+impl<'a> PageCache<'a> {
+    pub fn new(flash: &'a mut UnlockedFlash, policy: FlushPolicy) -> Self {
+        PageCache {
+            flash,
+            policy,
+            page: None,
+            dirty: false,
+            buf: [0u8; PAGE_SIZE as usize],
+        }
+    }
+
+    /// Whether the cached page has changes that haven't been written back
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Read `buf.len()` bytes at `address`, including the changes still in the cache
+    pub fn read(&self, address: usize, buf: &mut [u8]) {
+        self.flash.read(address, buf);
+        if let Some(page) = self.page {
+            let start = address.max(page.to_address());
+            let end = (address + buf.len()).min(page.to_address() + PAGE_SIZE as usize);
+            if start < end {
+                let offset = start - page.to_address();
+                buf[start - address..end - address]
+                    .copy_from_slice(&self.buf[offset..offset + end - start]);
+            }
+        }
+    }
+
+    /// Write `data` at `address` into the cache, moving the cache to every page it touches
+    pub fn write(&mut self, address: usize, data: &[u8]) -> Result {
+        if address < crate::FLASH_START || address + data.len() > self.flash.end_address() {
+            return Err(Error::OutOfBounds);
+        }
+        let mut address = address;
+        let mut data = data;
+        while !data.is_empty() {
+            let page = FlashPage::from_address(address);
+            self.load(page)?;
+            let offset = address - page.to_address();
+            let len = data.len().min(PAGE_SIZE as usize - offset);
+            if self.buf[offset..offset + len] != data[..len] {
+                self.buf[offset..offset + len].copy_from_slice(&data[..len]);
+                self.dirty = true;
+            }
+            address += len;
+            data = &data[len..];
+        }
+        Ok(())
+    }
+
+    /// Write the cached page back if it has changes
+    pub fn flush(&mut self) -> Result {
+        if let (Some(page), true) = (self.page, self.dirty) {
+            self.flash.update_page(page, &self.buf)?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+
+    /// Drop the changes in the cache
+    pub fn discard(&mut self) {
+        self.page = None;
+        self.dirty = false;
+    }
+
+    /// Make `page` the cached page
+    fn load(&mut self, page: FlashPage) -> Result {
+        if self.page.map_or(false, |cached| cached.0 == page.0) {
+            return Ok(());
+        }
+        if self.dirty {
+            if self.policy == FlushPolicy::Explicit {
+                return Err(Error::UnflushedCache);
+            }
+            self.flush()?;
+        }
+        self.flash.read(page.to_address(), &mut self.buf);
+        self.page = Some(page);
+        Ok(())
+    }
+}
+
+impl Drop for PageCache<'_> {
+    fn drop(&mut self) {
+        if self.policy == FlushPolicy::OnDrop {
+            let _ = self.flush();
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Panic messages that survive the reset.
+//!
+//! The message is streamed straight into a reserved page, so no buffer is needed in a panic
+//! handler. The page holds a magic and the message length, programmed after the message:
+//!
+//! ```text
+//! magic (u16) | length (u16) | message
+//! ```
+//!
+//! ```ignore
+//! static FLASH: SharedFlash = SharedFlash::new();
+//!
+//! #[panic_handler]
+//! fn panic(info: &PanicInfo) -> ! {
+//!     FLASH.try_with_mut(|flash| persist_panic(flash, PANIC_PAGE, info));
+//!     cortex_m::peripheral::SCB::sys_reset()
+//! }
+//! ```
+
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+
+use crate::{FlashPage, Read, Result, UnlockedFlash, WriteErase, ERASED_BYTE, PAGE_SIZE};
+
+const MAGIC: u16 = 0x5041;
+const HEADER_LEN: usize = 4;
+
+/// Largest message that fits the page, longer ones are truncated
+pub const MAX_PANIC_MESSAGE_LEN: usize = PAGE_SIZE as usize - HEADER_LEN;
+
+/// Write `info` to `page`, replacing whatever message was stored there
+pub fn persist_panic(flash: &mut UnlockedFlash, page: FlashPage, info: &PanicInfo) -> Result {
+    persist_message(flash, page, format_args!("{}", info))
+}
+
+/// Write a formatted message to `page`, replacing whatever message was stored there
+pub fn persist_message(
+    flash: &mut UnlockedFlash,
+    page: FlashPage,
+    args: fmt::Arguments,
+) -> Result {
+    // The panic may have interrupted an erase or a write
+    flash.clear_errors();
+    flash.erase_page(page)?;
+
+    let mut writer = PageWriter {
+        flash,
+        address: page.to_address() + HEADER_LEN,
+        end: page.to_address() + PAGE_SIZE as usize,
+        pending: None,
+        len: 0,
+        result: Ok(()),
+    };
+    // Errors are kept in `result`, formatting stops quietly at the end of the page
+    let _ = writer.write_fmt(args);
+    writer.flush();
+    let (len, result) = (writer.len, writer.result);
+    result?;
+    flash.write_native(page.to_address() + 2, &[len as u16])?;
+    flash.write_native(page.to_address(), &[MAGIC])
+}
+
+/// Copy the stored message into `buf` and return its length, `None` if there is none
+pub fn read_panic(
+    flash: &impl Read<NativeType = u8>,
+    page: FlashPage,
+    buf: &mut [u8],
+) -> Option<usize> {
+    let mut header = [0u8; HEADER_LEN];
+    flash.read(page.to_address(), &mut header);
+    if u16::from_ne_bytes([header[0], header[1]]) != MAGIC {
+        return None;
+    }
+    let len = (u16::from_ne_bytes([header[2], header[3]]) as usize)
+        .min(MAX_PANIC_MESSAGE_LEN)
+        .min(buf.len());
+    flash.read(page.to_address() + HEADER_LEN, &mut buf[..len]);
+    Some(len)
+}
+
+/// Forget the stored message
+pub fn clear_panic(flash: &mut UnlockedFlash, page: FlashPage) -> Result {
+    let mut magic = [0u8; 2];
+    flash.read(page.to_address(), &mut magic);
+    if magic == [ERASED_BYTE; 2] {
+        return Ok(());
+    }
+    flash.erase_page(page)
+}
+
+/// Programs formatted text halfword by halfword
+struct PageWriter<'a> {
+    flash: &'a mut UnlockedFlash,
+    address: usize,
+    end: usize,
+    pending: Option<u8>,
+    len: usize,
+    result: Result,
+}
+
+impl PageWriter<'_> {
+    fn push(&mut self, byte: u8) {
+        if self.result.is_err() || self.address >= self.end {
+            return;
+        }
+        self.len += 1;
+        match self.pending.take() {
+            None => self.pending = Some(byte),
+            Some(first) => self.program([first, byte]),
+        }
+    }
+
+    fn flush(&mut self) {
+        if let Some(first) = self.pending.take() {
+            self.program([first, ERASED_BYTE]);
+        }
+    }
+
+    fn program(&mut self, bytes: [u8; 2]) {
+        self.result = self
+            .flash
+            .write_native(self.address, &[u16::from_ne_bytes(bytes)]);
+        self.address += 2;
+    }
+}
+
+impl Write for PageWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.push(byte);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,273 @@
+//! Partition table in a reserved region, so the bootloader and the application find the flash
+//! layout at runtime instead of both compiling in matching constants.
+//!
+//! All fields are little endian:
+//!
+//! ```text
+//! magic (u32) | count (u16) | 0xffff (u16) | entries | crc (u32)
+//! entry: name (12 bytes, NUL padded) | start page (u16) | pages (u16) | flags (u32)
+//! ```
+//!
+//! `crc` is the CRC-32 of everything before it. `write` erases the table before programming
+//! the new one, so a reset in between leaves no table and `mount` fails with
+//! `Error::InvalidTable` until it is written again.
+
+use crate::{Checksum, Crc32, Error, FlashPage, Read, Region, UnlockedFlash, WriteErase};
+
+/// Marks a partition table
+pub const PARTITION_TABLE_MAGIC: u32 = 0x5442_5450;
+/// Longest partition name in bytes
+pub const PARTITION_NAME_LEN: usize = 12;
+
+const HEADER_LEN: usize = 8;
+const ENTRY_LEN: usize = PARTITION_NAME_LEN + 8;
+const CRC_LEN: usize = 4;
+
+/// Named range of pages
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Partition {
+    name: [u8; PARTITION_NAME_LEN],
+    pub region: Region,
+    /// Application defined
+    pub flags: u32,
+}
+
+impl Partition {
+    /// `Error::InvalidLength` for an empty name or one longer than `PARTITION_NAME_LEN`
+    pub fn new(name: &str, region: Region, flags: u32) -> core::result::Result<Self, Error> {
+        if name.is_empty() || name.len() > PARTITION_NAME_LEN || name.contains('\0') {
+            return Err(Error::InvalidLength);
+        }
+        let mut bytes = [0u8; PARTITION_NAME_LEN];
+        bytes[..name.len()].copy_from_slice(name.as_bytes());
+        Ok(Partition {
+            name: bytes,
+            region,
+            flags,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        let len = self
+            .name
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(PARTITION_NAME_LEN);
+        core::str::from_utf8(&self.name[..len]).unwrap_or("")
+    }
+
+    fn to_bytes(self) -> [u8; ENTRY_LEN] {
+        let mut bytes = [0u8; ENTRY_LEN];
+        bytes[..PARTITION_NAME_LEN].copy_from_slice(&self.name);
+        let fields = &mut bytes[PARTITION_NAME_LEN..];
+        fields[..2].copy_from_slice(&(self.region.start.0 as u16).to_le_bytes());
+        fields[2..4].copy_from_slice(&(self.region.pages as u16).to_le_bytes());
+        fields[4..].copy_from_slice(&self.flags.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8; ENTRY_LEN]) -> Self {
+        let mut name = [0u8; PARTITION_NAME_LEN];
+        name.copy_from_slice(&bytes[..PARTITION_NAME_LEN]);
+        let fields = &bytes[PARTITION_NAME_LEN..];
+        let start = u16::from_le_bytes([fields[0], fields[1]]) as usize;
+        let pages = u16::from_le_bytes([fields[2], fields[3]]) as usize;
+        Partition {
+            name,
+            region: Region::new(FlashPage(start), pages),
+            flags: u32::from_le_bytes([fields[4], fields[5], fields[6], fields[7]]),
+        }
+    }
+}
+
+/// Validated partition table
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PartitionTable {
+    region: Region,
+    count: usize,
+}
+
+impl PartitionTable {
+    /// Read the table in `region`. `Error::InvalidTable` without a table, with a wrong CRC or
+    /// with partitions that overlap each other or the table or lie outside of the flash.
+    pub fn mount(flash: &UnlockedFlash, region: Region) -> core::result::Result<Self, Error> {
+        let mut header = [0u8; HEADER_LEN];
+        flash.read(region.start_address(), &mut header);
+        let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let count = u16::from_le_bytes([header[4], header[5]]) as usize;
+        if magic != PARTITION_TABLE_MAGIC || count > Self::capacity(&region) {
+            return Err(Error::InvalidTable);
+        }
+        let table = PartitionTable { region, count };
+        let mut crc = Crc32::new();
+        crc.update(&header);
+        for partition in table.iter(flash) {
+            crc.update(&partition.to_bytes());
+        }
+        let mut stored = [0u8; CRC_LEN];
+        flash.read(table.entry_address(count), &mut stored);
+        if crc.finish() != u32::from_le_bytes(stored) {
+            return Err(Error::InvalidTable);
+        }
+        for (i, partition) in table.iter(flash).enumerate() {
+            let others = table.iter(flash).skip(i + 1);
+            if check(flash, &region, &partition, others).is_err() {
+                return Err(Error::InvalidTable);
+            }
+        }
+        Ok(table)
+    }
+
+    /// Erase `region` and store `partitions` as the new table. `Error::RegionOverlap` if
+    /// partitions overlap each other or the table, `Error::PageOutOfRange` if one doesn't fit
+    /// the flash and `Error::RegionFull` if there are more than fit the region.
+    pub fn write(
+        flash: &mut UnlockedFlash,
+        region: Region,
+        partitions: &[Partition],
+    ) -> core::result::Result<Self, Error> {
+        if partitions.len() > Self::capacity(&region) {
+            return Err(Error::RegionFull);
+        }
+        for (i, partition) in partitions.iter().enumerate() {
+            check(flash, &region, partition, partitions[i + 1..].iter().copied())?;
+        }
+        flash.erase_region(&region)?;
+        let table = PartitionTable {
+            region,
+            count: partitions.len(),
+        };
+        let mut header = [0xffu8; HEADER_LEN];
+        header[..4].copy_from_slice(&PARTITION_TABLE_MAGIC.to_le_bytes());
+        header[4..6].copy_from_slice(&(partitions.len() as u16).to_le_bytes());
+        let mut crc = Crc32::new();
+        crc.update(&header);
+        flash.write(region.start_address(), &header)?;
+        for (i, partition) in partitions.iter().enumerate() {
+            let bytes = partition.to_bytes();
+            crc.update(&bytes);
+            flash.write(table.entry_address(i), &bytes)?;
+        }
+        flash.write(
+            table.entry_address(partitions.len()),
+            &crc.finish().to_le_bytes(),
+        )?;
+        Ok(table)
+    }
+
+    /// Most partitions a table in `region` can hold
+    pub fn capacity(region: &Region) -> usize {
+        (region.len().saturating_sub(HEADER_LEN + CRC_LEN) / ENTRY_LEN).min(u16::MAX as usize)
+    }
+
+    /// Number of partitions
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn get(&self, flash: &UnlockedFlash, index: usize) -> Option<Partition> {
+        if index >= self.count {
+            return None;
+        }
+        let mut bytes = [0u8; ENTRY_LEN];
+        flash.read(self.entry_address(index), &mut bytes);
+        Some(Partition::from_bytes(&bytes))
+    }
+
+    /// First partition called `name`
+    pub fn find(&self, flash: &UnlockedFlash, name: &str) -> Option<Partition> {
+        self.iter(flash).find(|partition| partition.name() == name)
+    }
+
+    /// Partitions in the order of the table
+    pub fn iter<'a>(&'a self, flash: &'a UnlockedFlash) -> impl Iterator<Item = Partition> + 'a {
+        (0..self.count).filter_map(move |index| self.get(flash, index))
+    }
+
+    fn entry_address(&self, index: usize) -> usize {
+        self.region.start_address() + HEADER_LEN + index * ENTRY_LEN
+    }
+}
+
+/// Fails if `partition` doesn't fit the flash or overlaps `table` or any of `others`
+fn check(
+    flash: &UnlockedFlash,
+    table: &Region,
+    partition: &Partition,
+    mut others: impl Iterator<Item = Partition>,
+) -> crate::Result {
+    let region = &partition.region;
+    if region.is_empty() || region.start.0 + region.pages > flash.num_pages() {
+        return Err(Error::PageOutOfRange);
+    }
+    if overlaps(region, table) || others.any(|other| overlaps(region, &other.region)) {
+        return Err(Error::RegionOverlap);
+    }
+    Ok(())
+}
+
+fn overlaps(a: &Region, b: &Region) -> bool {
+    a.start.0 < b.start.0 + b.pages && b.start.0 < a.start.0 + a.pages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_round_trip() {
+        let partition = Partition::new("app", Region::new(FlashPage(8), 24), 0x1234_5678).unwrap();
+        let parsed = Partition::from_bytes(&partition.to_bytes());
+        assert_eq!(parsed.name(), "app");
+        assert_eq!((parsed.region.start.0, parsed.region.pages), (8, 24));
+        assert_eq!(parsed.flags, 0x1234_5678);
+    }
+
+    #[test]
+    fn name_fills_the_whole_field() {
+        let partition = Partition::new("bootloader12", Region::new(FlashPage(0), 1), 0).unwrap();
+        assert_eq!(Partition::from_bytes(&partition.to_bytes()).name(), "bootloader12");
+    }
+
+    #[test]
+    fn invalid_names_are_refused() {
+        for name in ["", "bootloader123", "a\0b"] {
+            assert!(matches!(
+                Partition::new(name, Region::new(FlashPage(0), 1), 0),
+                Err(Error::InvalidLength)
+            ));
+        }
+    }
+
+    #[test]
+    fn name_that_isnt_utf8_reads_empty() {
+        let mut bytes = [0u8; ENTRY_LEN];
+        bytes[0] = 0xff;
+        assert_eq!(Partition::from_bytes(&bytes).name(), "");
+    }
+
+    #[test]
+    fn overlapping_regions() {
+        let region = |start, pages| Region::new(FlashPage(start), pages);
+        assert!(overlaps(&region(0, 4), &region(3, 2)));
+        assert!(overlaps(&region(3, 2), &region(0, 4)));
+        assert!(overlaps(&region(2, 1), &region(0, 4)));
+        assert!(!overlaps(&region(0, 4), &region(4, 2)));
+        assert!(!overlaps(&region(4, 2), &region(0, 4)));
+    }
+
+    #[test]
+    fn capacity_leaves_room_for_header_and_crc() {
+        let region = Region::new(FlashPage(0), 1);
+        let capacity = PartitionTable::capacity(&region);
+        assert!(HEADER_LEN + capacity * ENTRY_LEN + CRC_LEN <= region.len());
+        assert!(HEADER_LEN + (capacity + 1) * ENTRY_LEN + CRC_LEN > region.len());
+        assert_eq!(PartitionTable::capacity(&Region::new(FlashPage(0), 0)), 0);
+    }
+}
@@ -0,0 +1,70 @@
+use crate::{Error, FlashPage, Result, UnlockedFlash, FLASH_START};
+
+impl UnlockedFlash {
+    /// Software-lock `pages`: erasing or writing them fails with `Error::SoftProtected` until
+    /// they are unprotected again. Unlike the WRP option bytes this takes effect immediately and
+    /// is forgotten on reset.
+    pub fn protect(&mut self, pages: impl IntoIterator<Item = FlashPage>) {
+        for page in pages {
+            self.soft_protected |= page_bit(page);
+        }
+    }
+
+    /// Lift the software lock from `pages`
+    pub fn unprotect(&mut self, pages: impl IntoIterator<Item = FlashPage>) {
+        for page in pages {
+            self.soft_protected &= !page_bit(page);
+        }
+    }
+
+    pub fn is_protected(&self, page: FlashPage) -> bool {
+        self.soft_protected & page_bit(page) != 0
+    }
+
+    /// Refuse every erase or write below `boundary` with `Error::FirmwareRegion`.
+    ///
+    /// Pass the end of the running firmware image, or `FLASH_START` to disable the guard again.
+    pub fn set_firmware_boundary(&mut self, boundary: usize) {
+        self.firmware_end = boundary;
+    }
+
+    /// Guard the running firmware, using the end of its load image from the cortex-m-rt linker
+    /// symbols as the boundary.
+    pub fn protect_firmware(&mut self) {
+        extern "C" {
+            static __sidata: u32;
+            static __sdata: u32;
+            static __edata: u32;
+        }
+        // .data is the last section placed in flash, its load image ends the firmware
+        let end = unsafe {
+            let data_len = &__edata as *const u32 as usize - &__sdata as *const u32 as usize;
+            &__sidata as *const u32 as usize + data_len
+        };
+        self.set_firmware_boundary(end);
+    }
+
+    /// Fails if any byte of `address..address + len` lies below the firmware boundary or in a
+    /// software-locked page
+    pub(crate) fn check_protection(&self, address: usize, len: usize) -> Result {
+        if len == 0 {
+            return Ok(());
+        }
+        if address < self.firmware_end {
+            return Err(Error::FirmwareRegion);
+        }
+        if self.soft_protected == 0 || address < FLASH_START {
+            return Ok(());
+        }
+        let first = FlashPage::from_address(address).0;
+        let last = FlashPage::from_address(address + len - 1).0;
+        if (first..=last).any(|page| self.is_protected(FlashPage(page))) {
+            return Err(Error::SoftProtected);
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn page_bit(page: FlashPage) -> u128 {
+    1u128.checked_shl(page.0 as u32).unwrap_or(0)
+}
@@ -0,0 +1,117 @@
+//! Routines that keep running while main flash is erased underneath them.
+//!
+//! They live in `.data`, which cortex-m-rt copies to RAM at startup, and touch the flash
+//! controller through raw register addresses only. Everything they call must be inlined, a call
+//! back into flash would fetch instructions from erased memory.
+
+use core::ptr;
+
+#[cfg(not(feature = "stm32f1"))]
+use crate::regs::CR_OBL_LAUNCH;
+use crate::regs::{CR_OPTER, CR_OPTPG, CR_PER, CR_PG, CR_STRT, SR_BSY};
+
+const FLASH_BASE: usize = 0x4002_2000;
+const FLASH_SR: *mut u32 = (FLASH_BASE + 0x0c) as *mut u32;
+const FLASH_CR: *mut u32 = (FLASH_BASE + 0x10) as *mut u32;
+const FLASH_AR: *mut u32 = (FLASH_BASE + 0x14) as *mut u32;
+
+// Cortex-M application interrupt and reset control register
+const SCB_AIRCR: *mut u32 = 0xe000_ed0c as *mut u32;
+const AIRCR_SYSRESETREQ: u32 = 0x05fa_0004;
+
+#[inline(always)]
+unsafe fn wait_busy() {
+    while ptr::read_volatile(FLASH_SR) & SR_BSY != 0 {}
+}
+
+#[inline(always)]
+unsafe fn set_cr(bits: u32) {
+    ptr::write_volatile(FLASH_CR, ptr::read_volatile(FLASH_CR) | bits);
+}
+
+#[inline(always)]
+unsafe fn clear_cr(bits: u32) {
+    ptr::write_volatile(FLASH_CR, ptr::read_volatile(FLASH_CR) & !bits);
+}
+
+/// Reset so that the freshly programmed option bytes are loaded
+#[inline(always)]
+unsafe fn reload_option_bytes() -> ! {
+    #[cfg(not(feature = "stm32f1"))]
+    set_cr(CR_OBL_LAUNCH);
+    // The F1 loads option bytes on every system reset
+    #[cfg(feature = "stm32f1")]
+    ptr::write_volatile(SCB_AIRCR, AIRCR_SYSRESETREQ);
+    loop {}
+}
+
+/// Erase the option bytes, program `count` halfwords of `image` and reload them.
+///
+/// Each entry of `image` is an address in the option byte block and the byte to program there.
+/// The caller has unlocked the option bytes and disabled interrupts.
+///
+/// # Safety
+///
+/// `image` must point to `count` entries in RAM. When RDP level 1 is left, the option byte erase
+/// mass-erases main flash, so nothing may run from flash afterwards.
+#[link_section = ".data.flash_ram"]
+#[inline(never)]
+pub(crate) unsafe fn rewrite_option_bytes(image: *const (usize, u8), count: usize) -> ! {
+    wait_busy();
+    set_cr(CR_OPTER);
+    set_cr(CR_STRT);
+    wait_busy();
+    clear_cr(CR_OPTER);
+
+    set_cr(CR_OPTPG);
+    let mut i = 0;
+    while i < count {
+        let (address, value) = ptr::read(image.add(i));
+        ptr::write_volatile(address as *mut u16, value as u16);
+        wait_busy();
+        i += 1;
+    }
+    clear_cr(CR_OPTPG);
+
+    reload_option_bytes()
+}
+
+/// Erase the pages at `dst`, copy `len` bytes from `src` over them and reset.
+///
+/// The caller has unlocked the flash and disabled interrupts.
+///
+/// # Safety
+///
+/// `src`, `dst` and `len` must be halfword aligned and `dst` page aligned, and the two ranges
+/// must not overlap. The running firmware may be erased, so nothing may run from flash
+/// afterwards.
+#[link_section = ".data.flash_ram"]
+#[inline(never)]
+pub(crate) unsafe fn copy_and_reset(src: usize, dst: usize, len: usize, page_size: usize) -> ! {
+    wait_busy();
+    let mut offset = 0;
+    while offset < len {
+        set_cr(CR_PER);
+        ptr::write_volatile(FLASH_AR, (dst + offset) as u32);
+        set_cr(CR_STRT);
+        wait_busy();
+        clear_cr(CR_PER);
+        offset += page_size;
+    }
+
+    set_cr(CR_PG);
+    let mut offset = 0;
+    while offset < len {
+        let halfword = ptr::read_volatile((src + offset) as *const u16);
+        // Erased halfwords are already in place
+        if halfword != 0xffff {
+            ptr::write_volatile((dst + offset) as *mut u16, halfword);
+            wait_busy();
+        }
+        offset += 2;
+    }
+    clear_cr(CR_PG);
+
+    ptr::write_volatile(SCB_AIRCR, AIRCR_SYSRESETREQ);
+    loop {}
+}
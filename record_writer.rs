@@ -0,0 +1,95 @@
+//! Back-end for firmware formats made of addressed records, such as Intel HEX and S-records.
+//!
+//! Records may come in any order. Every page of the target region is erased the first time a
+//! record reaches into it and never again, so records can't overwrite each other. A record
+//! ending on an odd address keeps its last byte back until the next record either continues
+//! right behind it or goes elsewhere, so contiguous records with odd lengths program cleanly.
+
+use crate::{Error, Region, Result, UnlockedFlash, WriteErase, ERASED_BYTE, PAGE_SIZE};
+
+/// Writer of data at absolute addresses inside a region of at most 128 pages
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RecordWriter {
+    region: Region,
+    /// Pages of the region erased so far, one bit each
+    erased: u128,
+    pending: Option<(usize, u8)>,
+}
+
+impl RecordWriter {
+    /// Accept records inside `region` only
+    pub fn new(region: Region) -> core::result::Result<Self, Error> {
+        if region.pages > 128 {
+            return Err(Error::InvalidLength);
+        }
+        Ok(RecordWriter {
+            region,
+            erased: 0,
+            pending: None,
+        })
+    }
+
+    pub fn region(&self) -> &Region {
+        &self.region
+    }
+
+    /// Program `data` at `address`, `Error::OutOfBounds` if it doesn't lie inside the region
+    pub fn write(&mut self, flash: &mut UnlockedFlash, address: usize, data: &[u8]) -> Result {
+        if data.is_empty() {
+            return Ok(());
+        }
+        if !self.region.contains(address) || address + data.len() > self.region.end_address() {
+            return Err(Error::OutOfBounds);
+        }
+        let mut address = address;
+        let mut data = data;
+        if let Some((pending, byte)) = self.pending.take() {
+            if address == pending + 1 {
+                self.program(flash, pending, &[byte, data[0]])?;
+                address += 1;
+                data = &data[1..];
+            } else {
+                self.program(flash, pending, &[byte, ERASED_BYTE])?;
+            }
+        }
+        if address % 2 != 0 && !data.is_empty() {
+            self.program(flash, address - 1, &[ERASED_BYTE, data[0]])?;
+            address += 1;
+            data = &data[1..];
+        }
+        let even = data.len() & !1;
+        self.program(flash, address, &data[..even])?;
+        if even < data.len() {
+            self.pending = Some((address + even, data[even]));
+        }
+        Ok(())
+    }
+
+    /// Program a byte still held back
+    pub fn finish(&mut self, flash: &mut UnlockedFlash) -> Result {
+        match self.pending.take() {
+            Some((address, byte)) => self.program(flash, address, &[byte, ERASED_BYTE]),
+            None => Ok(()),
+        }
+    }
+
+    /// Program the even length `data` at the even `address`, erasing pages on first use
+    fn program(&mut self, flash: &mut UnlockedFlash, address: usize, data: &[u8]) -> Result {
+        let page_len = PAGE_SIZE as usize;
+        let mut address = address;
+        let mut data = data;
+        while !data.is_empty() {
+            let index = (address - self.region.start_address()) / page_len;
+            if self.erased & (1 << index) == 0 {
+                flash.erase_page(self.region.page(index))?;
+                self.erased |= 1 << index;
+            }
+            let len = data.len().min(page_len - address % page_len);
+            flash.write(address, &data[..len])?;
+            address += len;
+            data = &data[len..];
+        }
+        Ok(())
+    }
+}
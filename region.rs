@@ -0,0 +1,175 @@
+use core::marker::PhantomData;
+
+use crate::{DefaultLayout, Error, FlashPage, Layout, Result, UnlockedFlash, WriteErase};
+
+/// Value of an erased flash byte
+pub const ERASED_BYTE: u8 = 0xff;
+
+/// A contiguous range of flash pages used as one storage area, with pages sized by `L`.
+#[derive(Debug)]
+pub struct Region<L = DefaultLayout> {
+    /// First page of the region
+    pub start: FlashPage,
+    /// Number of pages in the region
+    pub pages: usize,
+    _layout: PhantomData<L>,
+}
+
+impl<L> Clone for Region<L> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<L> Copy for Region<L> {}
+
+#[cfg(feature = "defmt")]
+impl<L> defmt::Format for Region<L> {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "Region {{ start: {}, pages: {} }}", self.start, self.pages)
+    }
+}
+
+/// What `append` does when the record no longer fits into the region.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AppendPolicy {
+    /// Return `Error::RegionFull` and leave the region untouched
+    Fail,
+    /// Erase the whole region and place the record at offset 0
+    EraseAndWrap,
+}
+
+impl<L: Layout> Region<L> {
+    pub const fn new(start: FlashPage, pages: usize) -> Self {
+        Region {
+            start,
+            pages,
+            _layout: PhantomData,
+        }
+    }
+
+    /// Address of the first byte of the region
+    pub const fn start_address(&self) -> usize {
+        crate::FLASH_START + self.start.0 * L::PAGE_SIZE as usize
+    }
+
+    /// Address one past the last byte of the region
+    pub const fn end_address(&self) -> usize {
+        self.start_address() + self.len()
+    }
+
+    /// Size of the region in bytes
+    pub const fn len(&self) -> usize {
+        self.pages * L::PAGE_SIZE as usize
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.pages == 0
+    }
+
+    /// Returns the `index`th page of the region
+    pub const fn page(&self, index: usize) -> FlashPage {
+        FlashPage(self.start.0 + index)
+    }
+
+    pub const fn contains(&self, address: usize) -> bool {
+        address >= self.start_address() && address < self.end_address()
+    }
+}
+
+/// Fails to compile for a layout whose pages aren't the hardware pages `erase_page` erases
+struct HardwarePages<L>(PhantomData<L>);
+
+impl<L: Layout> HardwarePages<L> {
+    const ASSERT: () = assert!(
+        L::PAGE_SIZE == crate::PAGE_SIZE,
+        "layout page size differs from the hardware page size"
+    );
+}
+
+impl UnlockedFlash {
+    /// Erase every page of `region` that isn't marked bad. `L` has to have the page size of the
+    /// hardware, other layouts are refused at compile time.
+    pub fn erase_region<L: Layout>(&mut self, region: &Region<L>) -> Result {
+        let () = HardwarePages::<L>::ASSERT;
+        for index in 0..region.pages {
+            let page = FlashPage::from_address(L::page_address(region.page(index)));
+            if !self.is_bad_page(page) {
+                self.erase_page(page)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Offset of the first halfword of the erased tail of `region`.
+    ///
+    /// Everything from the returned offset to the end of the region reads as erased. Only the
+    /// records `append` accepts are guaranteed to end before it.
+    pub fn first_blank_offset<L: Layout>(&self, region: &Region<L>) -> usize {
+        let mut offset = region.len();
+        while offset >= 2 && self.read_halfword(region.start_address() + offset - 2) == 0xffff {
+            offset -= 2;
+        }
+        offset
+    }
+
+    /// Program `record` at the first blank offset of `region` and return that offset.
+    ///
+    /// The last halfword of the record, padded with an erased byte to an even length, mustn't
+    /// read as erased or the next append would overwrite it, `Error::ErasedTail` otherwise.
+    /// When the record does not fit in the remaining space the `policy` decides whether to
+    /// fail or to erase the region and start over at offset 0.
+    pub fn append<L: Layout>(
+        &mut self,
+        region: &Region<L>,
+        record: &[u8],
+        policy: AppendPolicy,
+    ) -> core::result::Result<usize, Error> {
+        if record.len() > region.len() {
+            return Err(Error::RegionFull);
+        }
+        if ends_erased(record) {
+            return Err(Error::ErasedTail);
+        }
+
+        let mut offset = self.first_blank_offset(region);
+        if offset + record.len() > region.len() {
+            match policy {
+                AppendPolicy::Fail => return Err(Error::RegionFull),
+                AppendPolicy::EraseAndWrap => {
+                    self.erase_region(region)?;
+                    offset = 0;
+                }
+            }
+        }
+
+        if !record.is_empty() {
+            self.write(region.start_address() + offset, record)?;
+        }
+        Ok(offset)
+    }
+}
+
+/// Whether the last halfword of `record`, padded with an erased byte, reads as erased
+fn ends_erased(record: &[u8]) -> bool {
+    match record.len() {
+        0 => false,
+        len if len % 2 == 1 => record[len - 1] == ERASED_BYTE,
+        len => record[len - 2..] == [ERASED_BYTE; 2],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn erased_tail_is_refused() {
+        assert!(ends_erased(&[0x12, 0x34, 0xff]));
+        assert!(ends_erased(&[0x12, 0x34, 0xff, 0xff]));
+        assert!(!ends_erased(&[0x12, 0xff]));
+        assert!(!ends_erased(&[0x12, 0x34]));
+        assert!(!ends_erased(&[]));
+    }
+}
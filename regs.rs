@@ -0,0 +1,148 @@
+//! Flash controller register access.
+//!
+//! The F0, F1 and F3 flash controllers share the same register layout and bit positions, so the
+//! driver only talks to them through `FlashRegisters`. The backend is picked with the `stm32f1`,
+//! `stm32f3` or `metapac` feature and defaults to `stm32f0xx-hal`.
+
+#[cfg(not(any(feature = "stm32f1", feature = "stm32f3", feature = "metapac")))]
+pub use stm32f0xx_hal::stm32::FLASH;
+#[cfg(feature = "stm32f1")]
+pub use stm32f1xx_hal::pac::FLASH;
+#[cfg(feature = "stm32f3")]
+pub use stm32f3xx_hal::pac::FLASH;
+/// Flash register block from `stm32-metapac`, pass `stm32_metapac::FLASH` to `unlock`
+#[cfg(feature = "metapac")]
+pub use stm32_metapac::flash::Flash as FLASH;
+
+// FLASH_SR bits
+pub(crate) const SR_BSY: u32 = 1 << 0;
+pub(crate) const SR_PGERR: u32 = 1 << 2;
+pub(crate) const SR_WRPRT: u32 = 1 << 4;
+pub(crate) const SR_EOP: u32 = 1 << 5;
+
+// FLASH_CR bits
+pub(crate) const CR_PG: u32 = 1 << 0;
+pub(crate) const CR_PER: u32 = 1 << 1;
+pub(crate) const CR_MER: u32 = 1 << 2;
+pub(crate) const CR_STRT: u32 = 1 << 6;
+pub(crate) const CR_LOCK: u32 = 1 << 7;
+pub(crate) const CR_OPTPG: u32 = 1 << 4;
+pub(crate) const CR_OPTER: u32 = 1 << 5;
+pub(crate) const CR_OPTWRE: u32 = 1 << 9;
+pub(crate) const CR_ERRIE: u32 = 1 << 10;
+pub(crate) const CR_EOPIE: u32 = 1 << 12;
+#[cfg(not(feature = "stm32f1"))]
+pub(crate) const CR_OBL_LAUNCH: u32 = 1 << 13;
+
+pub(crate) trait FlashRegisters {
+    /// Raw FLASH_SR
+    fn read_sr(&self) -> u32;
+    /// Clear the write-1-to-clear SR flags in `bits`
+    fn clear_sr(&self, bits: u32);
+    /// Raw FLASH_CR
+    fn read_cr(&self) -> u32;
+    /// Set the CR bits in `set` and clear the ones in `clear`
+    fn modify_cr(&self, set: u32, clear: u32);
+    fn write_keyr(&self, key: u32);
+    fn write_ar(&self, address: u32);
+    fn write_optkeyr(&self, key: u32);
+    /// Raw FLASH_OBR, the option bytes as loaded at the last option byte load
+    fn read_obr(&self) -> u32;
+    /// Raw FLASH_WRPR, the write protection option bytes as loaded
+    fn read_wrpr(&self) -> u32;
+
+    fn is_busy(&self) -> bool {
+        self.read_sr() & SR_BSY != 0
+    }
+
+    fn is_locked(&self) -> bool {
+        self.read_cr() & CR_LOCK != 0
+    }
+
+    fn set_cr(&self, bits: u32) {
+        self.modify_cr(bits, 0);
+    }
+
+    fn clear_cr(&self, bits: u32) {
+        self.modify_cr(0, bits);
+    }
+}
+
+#[cfg(not(feature = "metapac"))]
+impl FlashRegisters for FLASH {
+    fn read_sr(&self) -> u32 {
+        self.sr.read().bits()
+    }
+
+    fn clear_sr(&self, bits: u32) {
+        self.sr.write(|w| unsafe { w.bits(bits) });
+    }
+
+    fn read_cr(&self) -> u32 {
+        self.cr.read().bits()
+    }
+
+    fn modify_cr(&self, set: u32, clear: u32) {
+        self.cr
+            .modify(|r, w| unsafe { w.bits((r.bits() | set) & !clear) });
+    }
+
+    fn write_keyr(&self, key: u32) {
+        self.keyr.write(|w| unsafe { w.bits(key) });
+    }
+
+    fn write_ar(&self, address: u32) {
+        self.ar.write(|w| unsafe { w.bits(address) });
+    }
+
+    fn write_optkeyr(&self, key: u32) {
+        self.optkeyr.write(|w| unsafe { w.bits(key) });
+    }
+
+    fn read_obr(&self) -> u32 {
+        self.obr.read().bits()
+    }
+
+    fn read_wrpr(&self) -> u32 {
+        self.wrpr.read().bits()
+    }
+}
+
+#[cfg(feature = "metapac")]
+impl FlashRegisters for FLASH {
+    fn read_sr(&self) -> u32 {
+        self.sr().read().0
+    }
+
+    fn clear_sr(&self, bits: u32) {
+        self.sr().write_value(stm32_metapac::flash::regs::Sr(bits));
+    }
+
+    fn read_cr(&self) -> u32 {
+        self.cr().read().0
+    }
+
+    fn modify_cr(&self, set: u32, clear: u32) {
+        self.cr().modify(|w| w.0 = (w.0 | set) & !clear);
+    }
+
+    fn write_keyr(&self, key: u32) {
+        self.keyr().write_value(key);
+    }
+
+    fn write_ar(&self, address: u32) {
+        self.ar().write_value(address);
+    }
+
+    fn write_optkeyr(&self, key: u32) {
+        self.optkeyr().write_value(key);
+    }
+
+    fn read_obr(&self) -> u32 {
+        self.obr().read().0
+    }
+
+    fn read_wrpr(&self) -> u32 {
+        self.wrpr().read()
+    }
+}
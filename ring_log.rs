@@ -0,0 +1,244 @@
+//! Circular log over the pages of a region.
+//!
+//! Every page starts with a magic and a sequence number, followed by length prefixed records
+//! padded to a halfword:
+//!
+//! ```text
+//! page:   magic (u16) | reserved (u16) | sequence (u32) | records...
+//! record: length (u16) | data
+//! ```
+//!
+//! The page with the highest sequence number is the head, so mounting reads one header per
+//! page and then hops over the records of the head page by their lengths. When the head page is
+//! full the oldest page is erased and becomes the new head. Pages in the mounted `BadPages`
+//! table are skipped.
+
+use crate::{Error, Read, Region, Result, UnlockedFlash, WriteErase};
+
+const PAGE_MAGIC: u16 = 0x4c47;
+const PAGE_HEADER_LEN: usize = 8;
+const RECORD_HEADER_LEN: usize = 2;
+const END: u16 = 0xffff;
+
+/// Ring of records that overwrites the oldest page once it is full
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RingLog {
+    region: Region,
+    head: usize,
+    offset: usize,
+    sequence: u32,
+}
+
+/// A record, as returned by `RingLog::records`
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LogRecord {
+    address: usize,
+    len: usize,
+}
+
+impl LogRecord {
+    /// Length of the data in bytes
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Copy the start of the data into `buf` and return how many bytes were copied
+    pub fn read(&self, flash: &impl Read<NativeType = u8>, buf: &mut [u8]) -> usize {
+        let len = self.len.min(buf.len());
+        flash.read(self.address, &mut buf[..len]);
+        len
+    }
+
+    /// Copy data from `offset` bytes into the record into `buf`, returning how many bytes were
+    /// copied
+    pub fn read_at(
+        &self,
+        flash: &impl Read<NativeType = u8>,
+        offset: usize,
+        buf: &mut [u8],
+    ) -> usize {
+        let len = self.len.saturating_sub(offset).min(buf.len());
+        flash.read(self.address + offset, &mut buf[..len]);
+        len
+    }
+}
+
+impl RingLog {
+    /// Find the head of the log in `region`, formatting the region if it holds no log pages.
+    /// The region needs a good page.
+    pub fn mount(
+        flash: &mut UnlockedFlash,
+        region: Region,
+    ) -> core::result::Result<Self, Error> {
+        if flash.good_pages(&region) == 0 {
+            return Err(Error::InvalidLength);
+        }
+        let mut log = RingLog {
+            region,
+            head: 0,
+            offset: PAGE_HEADER_LEN,
+            sequence: 0,
+        };
+        let newest = (0..region.pages)
+            .filter_map(|page| Some((page, log.page_sequence(flash, page)?)))
+            .max_by_key(|(_, sequence)| *sequence);
+        match newest {
+            Some((page, sequence)) => {
+                log.head = page;
+                log.sequence = sequence;
+                log.offset = log.end_of_page(flash, page);
+            }
+            None => log.clear(flash)?,
+        }
+        Ok(log)
+    }
+
+    /// Largest record that fits a page
+    pub fn max_record_len(&self) -> usize {
+        self.page_len() - PAGE_HEADER_LEN - RECORD_HEADER_LEN
+    }
+
+    /// Append `data`, erasing the oldest page if the head page is full
+    pub fn append(&mut self, flash: &mut UnlockedFlash, data: &[u8]) -> Result {
+        self.append_parts(flash, &[data])
+    }
+
+    /// Append the concatenation of `parts` as one record, all but the last part have an even
+    /// length
+    pub(crate) fn append_parts(&mut self, flash: &mut UnlockedFlash, parts: &[&[u8]]) -> Result {
+        let len: usize = parts.iter().map(|part| part.len()).sum();
+        if len > self.max_record_len() {
+            return Err(Error::InvalidLength);
+        }
+        let size = record_size(len);
+        if self.offset + size > self.page_len() {
+            let next = self.next_page(flash, self.head);
+            self.open_page(flash, next, self.sequence.wrapping_add(1))?;
+        }
+
+        let address = self.page_address(self.head) + self.offset;
+        self.offset += size;
+        flash.write_native(address, &[len as u16])?;
+        let mut part_address = address + RECORD_HEADER_LEN;
+        for part in parts.iter().filter(|part| !part.is_empty()) {
+            flash.write(part_address, part)?;
+            part_address += part.len();
+        }
+        Ok(())
+    }
+
+    /// Records from the oldest to the newest
+    pub fn records<'a>(
+        &'a self,
+        flash: &'a UnlockedFlash,
+    ) -> impl Iterator<Item = LogRecord> + 'a {
+        let pages = self.region.pages;
+        (1..=pages)
+            .map(move |step| (self.head + step) % pages)
+            .filter(move |page| self.page_sequence(flash, *page).is_some())
+            .flat_map(move |page| {
+                let end = if page == self.head {
+                    self.offset
+                } else {
+                    self.end_of_page(flash, page)
+                };
+                let mut offset = PAGE_HEADER_LEN;
+                core::iter::from_fn(move || {
+                    if offset >= end {
+                        return None;
+                    }
+                    let address = self.page_address(page) + offset;
+                    let len = flash.read_halfword(address) as usize;
+                    offset += record_size(len);
+                    Some(LogRecord {
+                        address: address + RECORD_HEADER_LEN,
+                        len,
+                    })
+                })
+            })
+    }
+
+    /// Erase every page and start over
+    pub fn clear(&mut self, flash: &mut UnlockedFlash) -> Result {
+        let first = self.next_page(flash, self.region.pages - 1);
+        for page in (0..self.region.pages).filter(|page| *page != first) {
+            if !flash.is_bad_page(self.region.page(page)) {
+                flash.erase_page(self.region.page(page))?;
+            }
+        }
+        self.open_page(flash, first, 0)
+    }
+
+    /// Erase `page` and make it the head
+    fn open_page(&mut self, flash: &mut UnlockedFlash, page: usize, sequence: u32) -> Result {
+        flash.erase_page(self.region.page(page))?;
+        let address = self.page_address(page);
+        let bytes = sequence.to_ne_bytes();
+        flash.write_native(
+            address,
+            &[
+                PAGE_MAGIC,
+                0xffff,
+                u16::from_ne_bytes([bytes[0], bytes[1]]),
+                u16::from_ne_bytes([bytes[2], bytes[3]]),
+            ],
+        )?;
+        self.head = page;
+        self.sequence = sequence;
+        self.offset = PAGE_HEADER_LEN;
+        Ok(())
+    }
+
+    /// Offset one past the last record of `page`
+    fn end_of_page(&self, flash: &UnlockedFlash, page: usize) -> usize {
+        let mut offset = PAGE_HEADER_LEN;
+        while offset + RECORD_HEADER_LEN <= self.page_len() {
+            let len = flash.read_halfword(self.page_address(page) + offset);
+            if len == END {
+                break;
+            }
+            offset += record_size(len as usize);
+        }
+        offset.min(self.page_len())
+    }
+
+    /// Sequence number of `page`, `None` for a page without a header or marked bad
+    fn page_sequence(&self, flash: &UnlockedFlash, page: usize) -> Option<u32> {
+        let address = self.page_address(page);
+        if flash.is_bad_page(self.region.page(page)) {
+            return None;
+        }
+        if flash.read_halfword(address) != PAGE_MAGIC {
+            return None;
+        }
+        let mut sequence = [0u8; 4];
+        flash.read(address + 4, &mut sequence);
+        Some(u32::from_ne_bytes(sequence))
+    }
+
+    /// Next page after `page` that isn't marked bad, wrapping around
+    fn next_page(&self, flash: &UnlockedFlash, page: usize) -> usize {
+        (1..=self.region.pages)
+            .map(|step| (page + step) % self.region.pages)
+            .find(|next| !flash.is_bad_page(self.region.page(*next)))
+            .unwrap_or(page)
+    }
+
+    fn page_address(&self, page: usize) -> usize {
+        self.region.page(page).to_address()
+    }
+
+    fn page_len(&self) -> usize {
+        self.region.len() / self.region.pages
+    }
+}
+
+fn record_size(len: usize) -> usize {
+    RECORD_HEADER_LEN + (len + 1) / 2 * 2
+}
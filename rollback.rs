@@ -0,0 +1,101 @@
+//! Minimum firmware version that only ever goes up, against downgrades to vulnerable images.
+//!
+//! The minimum is kept in an append-only list of entries that are never erased:
+//!
+//! ```text
+//! version (u32) | !version (u32)
+//! ```
+//!
+//! The controller can't program single bits, so every raise takes one 8 byte entry and the
+//! highest valid entry is the minimum. The complement is programmed after the version, which
+//! makes an entry torn by a reset invalid, the next raise goes after it. A 1 KB page holds 128
+//! raises. Once the region is full the minimum can't be raised any more, which still refuses
+//! every image below it. Don't erase the region, that resets the minimum to 0.
+
+use crate::{
+    validate_image, Error, ImageHeader, Read, Region, Result, SignatureVerifier, UnlockedFlash,
+    WriteErase,
+};
+
+const ENTRY_LEN: usize = 8;
+
+/// Monotonic minimum version in its own region
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RollbackCounter {
+    region: Region,
+    end: usize,
+    min_version: u32,
+}
+
+impl RollbackCounter {
+    /// Pick up the minimum stored in `region`, 0 for an erased region
+    pub fn mount(flash: &UnlockedFlash, region: Region) -> Self {
+        let mut counter = RollbackCounter {
+            region,
+            end: 0,
+            min_version: 0,
+        };
+        while counter.end + ENTRY_LEN <= region.len() {
+            let mut bytes = [0u8; ENTRY_LEN];
+            flash.read(region.start_address() + counter.end, &mut bytes);
+            if bytes == [crate::ERASED_BYTE; ENTRY_LEN] {
+                break;
+            }
+            let version = u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            let check = u32::from_ne_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+            if check == !version {
+                counter.min_version = counter.min_version.max(version);
+            }
+            counter.end += ENTRY_LEN;
+        }
+        counter
+    }
+
+    /// Lowest version `check` accepts
+    pub fn min_version(&self) -> u32 {
+        self.min_version
+    }
+
+    /// Raises left before the region is full
+    pub fn remaining(&self) -> usize {
+        (self.region.len() - self.end) / ENTRY_LEN
+    }
+
+    /// `Error::Rollback` if `header` is older than the minimum
+    pub fn check(&self, header: &ImageHeader) -> Result {
+        if header.version < self.min_version {
+            return Err(Error::Rollback);
+        }
+        Ok(())
+    }
+
+    /// `validate_image` followed by `check`
+    pub fn validate(
+        &self,
+        flash: &impl Read<NativeType = u8>,
+        slot: &Region,
+        verifier: &(impl SignatureVerifier + ?Sized),
+    ) -> core::result::Result<ImageHeader, Error> {
+        let header = validate_image(flash, slot, verifier)?;
+        self.check(&header)?;
+        Ok(header)
+    }
+
+    /// Raise the minimum to `version`. Does nothing if it is already at least `version`.
+    pub fn raise(&mut self, flash: &mut UnlockedFlash, version: u32) -> Result {
+        if version <= self.min_version {
+            return Ok(());
+        }
+        if self.remaining() == 0 {
+            return Err(Error::RegionFull);
+        }
+        let address = self.region.start_address() + self.end;
+        // A torn entry stays behind, the next raise goes after it
+        self.end += ENTRY_LEN;
+        flash.write(address, &version.to_ne_bytes())?;
+        flash.write(address + 4, &(!version).to_ne_bytes())?;
+        self.min_version = version;
+        Ok(())
+    }
+}
@@ -0,0 +1,49 @@
+//! Replacing the running firmware with an image staged elsewhere in flash, for parts too small
+//! for a separate bootloader and two slots.
+
+use core::convert::Infallible;
+
+use crate::regs::{FlashRegisters, SR_EOP, SR_PGERR, SR_WRPRT};
+use crate::{ram, Error, Region, UnlockedFlash};
+
+impl UnlockedFlash {
+    /// Copy the image staged in `src` over `dst` and reset, for devices with room for only
+    /// about one and a half images where `dst` holds the running firmware.
+    ///
+    /// The erase and copy run from RAM with interrupts disabled, since the code calling this is
+    /// erased along the way. All of `src` is copied to the start of `dst`, the pages of `dst`
+    /// behind it are kept. Software protection and the firmware boundary are ignored. A reset or
+    /// power loss during the copy leaves no bootable firmware in `dst`, so this needs a
+    /// bootloader outside of `dst` that can start over. On success the device resets and this
+    /// never returns.
+    pub fn self_update(
+        self,
+        src: &Region,
+        dst: &Region,
+    ) -> core::result::Result<Infallible, Error> {
+        if src.len() > dst.len() {
+            return Err(Error::InvalidLength);
+        }
+        if src.start_address() < dst.end_address() && dst.start_address() < src.end_address() {
+            return Err(Error::RegionOverlap);
+        }
+        if src.start.0 + src.pages > self.num_pages()
+            || dst.start.0 + dst.pages > self.num_pages()
+        {
+            return Err(Error::PageOutOfRange);
+        }
+
+        while self.f.is_busy() {}
+        self.f.clear_sr(SR_PGERR | SR_WRPRT | SR_EOP);
+
+        cortex_m::interrupt::disable();
+        unsafe {
+            ram::copy_and_reset(
+                src.start_address(),
+                dst.start_address(),
+                src.len(),
+                crate::PAGE_SIZE as usize,
+            )
+        }
+    }
+}
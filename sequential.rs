@@ -0,0 +1,201 @@
+//! `sequential-storage` on top of the `NorFlash` impls of `UnlockedFlash`.
+//!
+//! Pass the flash and `region.flash_range()` to `sequential_storage::map` or `::queue`. Things
+//! to keep in mind on this chip:
+//!
+//! - Items are padded to the 2 byte write size, keys and values don't need any alignment.
+//! - The map needs at least two pages to be able to migrate items out of a full page, the queue
+//!   works with one.
+//! - Pages are 1 KB or 2 KB depending on the part, size the region in pages rather than bytes.
+//! - Erased or popped items are marked by programming `0x0000` over them, which relies on the
+//!   `MultiwriteNorFlash` impl and needs no erase.
+//! - Erasing and programming stall the core, so the async API completes without yielding.
+//!
+//! The tests run the map and the queue on a RAM copy of the flash that is written through the
+//! same halfword checks as `UnlockedFlash`, they need the `async` feature.
+
+use core::ops::Range;
+
+use crate::{Layout, Region, FLASH_START};
+
+impl<L: Layout> Region<L> {
+    /// The region as offsets into the flash, the form `NorFlash` and `sequential-storage` use
+    pub const fn flash_range(&self) -> Range<u32> {
+        let start = (self.start_address() - FLASH_START) as u32;
+        start..start + self.len() as u32
+    }
+}
+
+/// Runs `sequential-storage` on a RAM copy of the flash with the alignment and bounds checks of
+/// `check_write` and `check_erase` and the halfword checks of the `NorFlash` impls of
+/// `UnlockedFlash`
+#[cfg(all(test, feature = "async"))]
+mod tests {
+    use core::future::Future;
+    use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+    use embedded_storage_async::nor_flash::{
+        ErrorType, MultiwriteNorFlash, NorFlash, ReadNorFlash,
+    };
+    use sequential_storage::cache::NoCache;
+    use sequential_storage::{map, queue};
+
+    use super::*;
+    use crate::nor_flash::{write_halfwords, Halfwords};
+    use crate::{Error, FlashPage, PAGE_SIZE};
+
+    const PAGES: usize = 4;
+
+    struct RamFlash {
+        bytes: [u8; PAGES * PAGE_SIZE as usize],
+    }
+
+    impl RamFlash {
+        fn new() -> Self {
+            RamFlash {
+                bytes: [crate::ERASED_BYTE; PAGES * PAGE_SIZE as usize],
+            }
+        }
+    }
+
+    impl Halfwords for RamFlash {
+        fn halfword(&self, address: usize) -> u16 {
+            let offset = address - FLASH_START;
+            u16::from_ne_bytes([self.bytes[offset], self.bytes[offset + 1]])
+        }
+
+        fn program_halfword(&mut self, address: usize, value: u16) -> Result<(), Error> {
+            let offset = address - FLASH_START;
+            self.bytes[offset..offset + 2].copy_from_slice(&value.to_ne_bytes());
+            Ok(())
+        }
+    }
+
+    impl ErrorType for RamFlash {
+        type Error = Error;
+    }
+
+    impl ReadNorFlash for RamFlash {
+        const READ_SIZE: usize = 1;
+
+        async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error> {
+            let offset = offset as usize;
+            let source = self.bytes.get(offset..offset + bytes.len());
+            bytes.copy_from_slice(source.ok_or(Error::OutOfBounds)?);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.bytes.len()
+        }
+    }
+
+    impl NorFlash for RamFlash {
+        const WRITE_SIZE: usize = 2;
+        const ERASE_SIZE: usize = PAGE_SIZE as usize;
+
+        async fn erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+            let (from, to) = (from as usize, to as usize);
+            if from % Self::ERASE_SIZE != 0 || to % Self::ERASE_SIZE != 0 {
+                return Err(Error::Unaligned);
+            }
+            let pages = self.bytes.get_mut(from..to).ok_or(Error::OutOfBounds)?;
+            pages.fill(crate::ERASED_BYTE);
+            Ok(())
+        }
+
+        async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Error> {
+            let offset = offset as usize;
+            if offset % Self::WRITE_SIZE != 0 || bytes.len() % Self::WRITE_SIZE != 0 {
+                return Err(Error::Unaligned);
+            }
+            if offset + bytes.len() > self.bytes.len() {
+                return Err(Error::OutOfBounds);
+            }
+            write_halfwords(self, FLASH_START + offset, bytes)
+        }
+    }
+
+    impl MultiwriteNorFlash for RamFlash {}
+
+    /// The flash never makes a future wait, so polling once completes it
+    fn block_on<F: Future>(future: F) -> F::Output {
+        fn clone(_: *const ()) -> RawWaker {
+            RawWaker::new(core::ptr::null(), &VTABLE)
+        }
+        fn noop(_: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+        let waker = unsafe { Waker::from_raw(clone(core::ptr::null())) };
+        let mut context = Context::from_waker(&waker);
+        let mut future = core::pin::pin!(future);
+        match future.as_mut().poll(&mut context) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("flash operation didn't complete"),
+        }
+    }
+
+    #[test]
+    fn flash_range_is_offset_from_flash_start() {
+        let region: Region = Region::new(FlashPage(2), 2);
+        assert_eq!(region.flash_range(), 2 * PAGE_SIZE..4 * PAGE_SIZE);
+    }
+
+    #[test]
+    fn map_stores_and_fetches_items() {
+        let mut flash = RamFlash::new();
+        let range = Region::<crate::DefaultLayout>::new(FlashPage(0), 2).flash_range();
+        let mut buf = [0u8; 32];
+        block_on(async {
+            for round in 0..200u32 {
+                let key = (round % 5) as u8;
+                map::store_item(
+                    &mut flash,
+                    range.clone(),
+                    &mut NoCache::new(),
+                    &mut buf,
+                    &key,
+                    &round,
+                )
+                .await
+                .unwrap();
+            }
+            for key in 0..5u8 {
+                let value = map::fetch_item::<u8, u32, _>(
+                    &mut flash,
+                    range.clone(),
+                    &mut NoCache::new(),
+                    &mut buf,
+                    &key,
+                )
+                .await
+                .unwrap();
+                assert_eq!(value, Some(195 + key as u32));
+            }
+        });
+    }
+
+    #[test]
+    fn queue_pops_in_push_order() {
+        let mut flash = RamFlash::new();
+        let range = Region::<crate::DefaultLayout>::new(FlashPage(2), 1).flash_range();
+        let mut buf = [0u8; 16];
+        block_on(async {
+            // Odd lengths get padded to the halfword write size
+            for item in [&b"a"[..], b"bcd", b"efghi"] {
+                queue::push(&mut flash, range.clone(), &mut NoCache::new(), item, false)
+                    .await
+                    .unwrap();
+            }
+            for item in [&b"a"[..], b"bcd", b"efghi"] {
+                let popped = queue::pop(&mut flash, range.clone(), &mut NoCache::new(), &mut buf)
+                    .await
+                    .unwrap();
+                assert_eq!(popped.as_deref(), Some(item));
+            }
+            let popped = queue::pop(&mut flash, range.clone(), &mut NoCache::new(), &mut buf)
+                .await
+                .unwrap();
+            assert!(popped.is_none());
+        });
+    }
+}
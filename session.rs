@@ -0,0 +1,49 @@
+use crate::regs::{FlashRegisters, CR_PG, SR_EOP};
+use crate::{cs, Error, Result, UnlockedFlash};
+
+/// Flash held in programming mode.
+///
+/// PG is set once when the session starts and cleared when it is dropped, so many halfwords can
+/// be programmed without touching CR for each of them.
+pub struct ProgrammingSession<'a> {
+    flash: &'a mut UnlockedFlash,
+}
+
+impl UnlockedFlash {
+    /// Enter programming mode until the returned session is dropped
+    pub fn programming_session(&mut self) -> ProgrammingSession<'_> {
+        // wait while memory interface is busy
+        while self.f.is_busy() {}
+        self.clear_errors();
+
+        self.f.set_cr(CR_PG);
+        ProgrammingSession { flash: self }
+    }
+}
+
+impl<'a> ProgrammingSession<'a> {
+    /// Program one halfword at the halfword aligned `address`
+    pub fn program_halfword(&mut self, address: usize, word: u16) -> Result {
+        if address.checked_add(2).map_or(true, |end| end > self.flash.end_address()) {
+            return Err(Error::OutOfBounds);
+        }
+        self.flash.check_protection(address, 2)?;
+
+        cs::free(|| unsafe {
+            (address as *mut u16).write_volatile(word);
+        });
+
+        self.flash.wait()?;
+
+        if self.flash.f.read_sr() & SR_EOP != 0 {
+            self.flash.f.clear_sr(SR_EOP);
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Drop for ProgrammingSession<'a> {
+    fn drop(&mut self) {
+        self.flash.f.clear_cr(CR_PG);
+    }
+}
@@ -0,0 +1,100 @@
+//! Settings that carry their schema version and are migrated on load.
+//!
+//! Every settings blob is stored in a `KvStore` under its key, prefixed with the schema version
+//! it was saved with. Loading a blob saved by older firmware runs the registered migrations one
+//! version at a time and saves the result, so every migration runs once.
+
+use crate::{Error, KvStore, Region, Result, UnlockedFlash};
+
+const VERSION_LEN: usize = 2;
+
+/// Upgrade of a blob from version `from` to `from + 1`
+#[derive(Copy, Clone, Debug)]
+pub struct Migration {
+    pub from: u16,
+    /// Rewrites the `len` byte blob at the start of the buffer in place and returns its new
+    /// length, `None` if the blob can't be migrated
+    pub migrate: fn(&mut [u8], usize) -> Option<usize>,
+}
+
+/// Layout of one settings blob
+#[derive(Copy, Clone, Debug)]
+pub struct Schema<'a> {
+    /// Key the blob is stored under
+    pub key: u16,
+    /// Version the firmware reads and writes
+    pub version: u16,
+    pub migrations: &'a [Migration],
+}
+
+/// Versioned settings blobs in a region
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Settings {
+    store: KvStore,
+}
+
+impl Settings {
+    pub fn mount(
+        flash: &mut UnlockedFlash,
+        region: Region,
+    ) -> core::result::Result<Self, Error> {
+        KvStore::mount(flash, region).map(|store| Settings { store })
+    }
+
+    /// Read the blob of `schema` into `buf`, migrating it to `schema.version` first, and return
+    /// its length. `None` if it was never saved.
+    ///
+    /// `buf` needs 2 bytes more than the largest version of the blob, a migration returning a
+    /// longer blob fails with `Error::InvalidLength`. Blobs from newer firmware or without a
+    /// migration path fail with `Error::SchemaVersion`.
+    pub fn load(
+        &mut self,
+        flash: &mut UnlockedFlash,
+        schema: &Schema,
+        buf: &mut [u8],
+    ) -> core::result::Result<Option<usize>, Error> {
+        let stored = match self.store.get(flash, schema.key, buf)? {
+            Some(stored) if stored >= VERSION_LEN => stored,
+            Some(_) => return Err(Error::SchemaVersion),
+            None => return Ok(None),
+        };
+        let mut version = u16::from_ne_bytes([buf[0], buf[1]]);
+        let mut len = stored - VERSION_LEN;
+        if version > schema.version {
+            return Err(Error::SchemaVersion);
+        }
+
+        if version < schema.version {
+            while version < schema.version {
+                let migration = schema
+                    .migrations
+                    .iter()
+                    .find(|migration| migration.from == version)
+                    .ok_or(Error::SchemaVersion)?;
+                len = (migration.migrate)(&mut buf[VERSION_LEN..], len)
+                    .ok_or(Error::SchemaVersion)?;
+                if len > buf.len() - VERSION_LEN {
+                    return Err(Error::InvalidLength);
+                }
+                version += 1;
+            }
+            buf[..VERSION_LEN].copy_from_slice(&version.to_ne_bytes());
+            self.store.set(flash, schema.key, &buf[..VERSION_LEN + len])?;
+        }
+
+        buf.copy_within(VERSION_LEN..VERSION_LEN + len, 0);
+        Ok(Some(len))
+    }
+
+    /// Save `data` as the current version of `schema`
+    pub fn save(&mut self, flash: &mut UnlockedFlash, schema: &Schema, data: &[u8]) -> Result {
+        let version = schema.version.to_ne_bytes();
+        self.store.set_parts(flash, schema.key, &[&version, data])
+    }
+
+    /// Forget the blob of `schema`
+    pub fn remove(&mut self, flash: &mut UnlockedFlash, schema: &Schema) -> Result {
+        self.store.remove(flash, schema.key)
+    }
+}
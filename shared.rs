@@ -0,0 +1,62 @@
+use core::cell::RefCell;
+
+#[cfg(not(feature = "critical-section"))]
+use cortex_m::interrupt::{free as with, Mutex};
+#[cfg(feature = "critical-section")]
+use critical_section::{with, Mutex};
+
+use crate::UnlockedFlash;
+
+/// Flash that can be reached from more than one execution context.
+///
+/// Every access runs inside a critical section and only borrows the flash for the duration of
+/// the closure, so it can be kept in a `static` and used from both thread mode and interrupts.
+pub struct SharedFlash {
+    flash: Mutex<RefCell<Option<UnlockedFlash>>>,
+}
+
+impl SharedFlash {
+    /// An empty handle, suitable for a `static`
+    pub const fn new() -> Self {
+        SharedFlash {
+            flash: Mutex::new(RefCell::new(None)),
+        }
+    }
+
+    /// Move the flash into the shared handle
+    pub fn init(&self, flash: UnlockedFlash) {
+        with(|cs| {
+            self.flash.borrow(cs).replace(Some(flash));
+        });
+    }
+
+    /// Take the flash back out of the shared handle
+    pub fn release(&self) -> Option<UnlockedFlash> {
+        with(|cs| self.flash.borrow(cs).take())
+    }
+
+    /// Run `f` with shared access to the flash, `None` if it has not been initialised.
+    pub fn with<T>(&self, f: impl FnOnce(&UnlockedFlash) -> T) -> Option<T> {
+        with(|cs| self.flash.borrow(cs).borrow().as_ref().map(f))
+    }
+
+    /// Run `f` with exclusive access to the flash, `None` if it has not been initialised.
+    pub fn with_mut<T>(&self, f: impl FnOnce(&mut UnlockedFlash) -> T) -> Option<T> {
+        with(|cs| self.flash.borrow(cs).borrow_mut().as_mut().map(f))
+    }
+
+    /// `with_mut` that returns `None` instead of panicking when the flash is already borrowed,
+    /// for panic and fault handlers that may have interrupted a `with_mut`
+    pub fn try_with_mut<T>(&self, f: impl FnOnce(&mut UnlockedFlash) -> T) -> Option<T> {
+        with(|cs| {
+            let mut flash = self.flash.borrow(cs).try_borrow_mut().ok()?;
+            flash.as_mut().map(f)
+        })
+    }
+}
+
+impl Default for SharedFlash {
+    fn default() -> Self {
+        SharedFlash::new()
+    }
+}
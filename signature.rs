@@ -0,0 +1,58 @@
+//! Detached image signatures checked by `validate_image`.
+//!
+//! The signature is stored right after the image, at `IMAGE_OFFSET` plus the image length
+//! rounded up to 4 bytes. It signs the slot from its first byte to the end of the image, so
+//! the header with the version and the CRC is covered as well. Verifiers get the signed bytes
+//! through the memory map of the flash, without copying them.
+
+/// Checks the signature of an image
+pub trait SignatureVerifier {
+    /// Bytes of the signature stored after the image
+    fn signature_len(&self) -> usize;
+
+    /// Whether `signature` is a valid signature of `message`
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool;
+}
+
+/// Verifier for unsigned images, accepts every image
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct NoSignature;
+
+impl SignatureVerifier for NoSignature {
+    fn signature_len(&self) -> usize {
+        0
+    }
+
+    fn verify(&self, _message: &[u8], _signature: &[u8]) -> bool {
+        true
+    }
+}
+
+#[cfg(feature = "ed25519-dalek")]
+impl SignatureVerifier for ed25519_dalek::VerifyingKey {
+    fn signature_len(&self) -> usize {
+        ed25519_dalek::SIGNATURE_LENGTH
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        ed25519_dalek::Signature::from_slice(signature)
+            .map_or(false, |signature| self.verify_strict(message, &signature).is_ok())
+    }
+}
+
+#[cfg(feature = "salty")]
+impl SignatureVerifier for salty::PublicKey {
+    fn signature_len(&self) -> usize {
+        salty::constants::SIGNATURE_SERIALIZED_LENGTH
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        let bytes: &[u8; salty::constants::SIGNATURE_SERIALIZED_LENGTH] =
+            match signature.try_into() {
+                Ok(bytes) => bytes,
+                Err(_) => return false,
+            };
+        salty::PublicKey::verify(self, message, &salty::Signature::from(bytes)).is_ok()
+    }
+}
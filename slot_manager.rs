@@ -0,0 +1,401 @@
+//! A/B application slots and the state that picks the one to boot.
+//!
+//! The state is a `RingLog` of small records in its own pages, the newest record wins:
+//!
+//! ```text
+//! active (u8) | pending (u8) | confirmed (u8) | trials (u8) | check (u8)
+//! ```
+//!
+//! Records of 4 bytes, without `trials`, were written before trial boots were counted. They are
+//! still read, an unconfirmed slot from such a record starts its trial with no boots counted.
+//!
+//! The application stages a new image into the inactive slot with `stage` and marks it with
+//! `mark_pending`. On the next boot the bootloader calls `boot_slot`, which makes the pending
+//! slot the active one on trial, and the application calls `mark_boot_successful` once it runs.
+//! Every boot of an image on trial is counted, and once `max_trials` boots went by without the
+//! confirmation, for example because the IWDG reset a hanging application, `boot_slot` reverts
+//! to the previous slot if its image still passes the checks. `stage` is refused during a
+//! trial, so the previous slot can't be overwritten before the new image is confirmed.
+//!
+//! `mark_pending` and `boot_slot` only accept an image that passes `validate_image` with the
+//! verifier of the manager, `NoSignature` unless mounted with `mount_with_verifier`. With a
+//! `RollbackCounter` set they also refuse images below its minimum version, and
+//! `mark_boot_successful` raises the minimum to the version of the confirmed image.
+//!
+//! Use at least two state pages, with a single page a reset while the full page is erased and
+//! rewritten loses the state.
+
+use crate::{
+    parse_header, validate_image, Error, ImageWriter, NoSignature, Region, Result, RingLog,
+    RollbackCounter, SignatureVerifier, UnlockedFlash,
+};
+
+const RECORD_LEN: usize = 5;
+/// Records without the trial count
+const LEGACY_RECORD_LEN: usize = 4;
+const NONE: u8 = 0xff;
+const CHECK: u8 = 0x5a;
+
+/// One of the two application slots
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Slot {
+    A = 0,
+    B = 1,
+}
+
+impl Slot {
+    /// The other slot
+    pub const fn other(self) -> Slot {
+        match self {
+            Slot::A => Slot::B,
+            Slot::B => Slot::A,
+        }
+    }
+
+    const fn from_u8(value: u8) -> Option<Slot> {
+        match value {
+            0 => Some(Slot::A),
+            1 => Some(Slot::B),
+            _ => None,
+        }
+    }
+}
+
+/// Boot state of the slots
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SlotState {
+    /// Slot the bootloader starts
+    pub active: Slot,
+    /// Slot that becomes the active one on the next boot
+    pub pending: Option<Slot>,
+    /// Whether the application in the active slot has confirmed that it works, it is on trial
+    /// otherwise
+    pub confirmed: bool,
+    /// Boots of the active slot while on trial
+    pub trials: u8,
+}
+
+impl SlotState {
+    fn to_bytes(self) -> [u8; RECORD_LEN] {
+        let bytes = [
+            self.active as u8,
+            self.pending.map_or(NONE, |slot| slot as u8),
+            self.confirmed as u8,
+            self.trials,
+        ];
+        [bytes[0], bytes[1], bytes[2], bytes[3], check(&bytes)]
+    }
+
+    /// State from a record of `len` bytes at the start of `bytes`, in the current or the legacy
+    /// layout
+    fn from_record(bytes: [u8; RECORD_LEN], len: usize) -> Option<Self> {
+        match len {
+            RECORD_LEN => Self::from_bytes(bytes),
+            LEGACY_RECORD_LEN => {
+                if bytes[3] != check(&bytes[..3]) {
+                    return None;
+                }
+                Some(SlotState {
+                    active: Slot::from_u8(bytes[0])?,
+                    pending: Slot::from_u8(bytes[1]),
+                    confirmed: bytes[2] == 1,
+                    trials: 0,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn from_bytes(bytes: [u8; RECORD_LEN]) -> Option<Self> {
+        if bytes[4] != check(&bytes[..4]) {
+            return None;
+        }
+        Some(SlotState {
+            active: Slot::from_u8(bytes[0])?,
+            pending: Slot::from_u8(bytes[1]),
+            confirmed: bytes[2] == 1,
+            trials: bytes[3],
+        })
+    }
+}
+
+fn check(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(CHECK, |check, byte| check ^ byte)
+}
+
+/// Two application slots and their boot state
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SlotManager<V = NoSignature> {
+    slots: [Region; 2],
+    log: RingLog,
+    state: SlotState,
+    rollback: Option<RollbackCounter>,
+    verifier: V,
+    max_trials: u8,
+}
+
+impl SlotManager {
+    /// Pick up the state kept in `state`. Without any, slot A is active and confirmed.
+    pub fn mount(
+        flash: &mut UnlockedFlash,
+        slot_a: Region,
+        slot_b: Region,
+        state: Region,
+    ) -> core::result::Result<Self, Error> {
+        Self::mount_with_verifier(flash, slot_a, slot_b, state, NoSignature)
+    }
+}
+
+impl<V: SignatureVerifier> SlotManager<V> {
+    /// Like `mount`, checking image signatures with `verifier`
+    pub fn mount_with_verifier(
+        flash: &mut UnlockedFlash,
+        slot_a: Region,
+        slot_b: Region,
+        state: Region,
+        verifier: V,
+    ) -> core::result::Result<Self, Error> {
+        if slot_a.pages != slot_b.pages {
+            return Err(Error::InvalidLength);
+        }
+        let log = RingLog::mount(flash, state)?;
+        let state = log
+            .records(flash)
+            .filter_map(|record| {
+                let mut bytes = [0u8; RECORD_LEN];
+                record.read(flash, &mut bytes);
+                SlotState::from_record(bytes, record.len())
+            })
+            .last()
+            .unwrap_or(SlotState {
+                active: Slot::A,
+                pending: None,
+                confirmed: true,
+                trials: 0,
+            });
+        Ok(SlotManager {
+            slots: [slot_a, slot_b],
+            log,
+            state,
+            rollback: None,
+            verifier,
+            max_trials: 1,
+        })
+    }
+
+    /// Check images against `counter` before accepting them, or stop checking with `None`
+    pub fn set_rollback_counter(&mut self, counter: Option<RollbackCounter>) {
+        self.rollback = counter;
+    }
+
+    pub fn rollback_counter(&self) -> Option<&RollbackCounter> {
+        self.rollback.as_ref()
+    }
+
+    /// Boots an image gets on trial before `boot_slot` reverts it, 1 by default. 0 is taken
+    /// as 1.
+    pub fn set_max_trials(&mut self, max_trials: u8) {
+        self.max_trials = max_trials.max(1);
+    }
+
+    pub fn state(&self) -> SlotState {
+        self.state
+    }
+
+    /// Slot the bootloader starts
+    pub fn active(&self) -> Slot {
+        self.state.active
+    }
+
+    /// Slot new images are staged into
+    pub fn inactive(&self) -> Slot {
+        self.state.active.other()
+    }
+
+    pub fn region(&self, slot: Slot) -> Region {
+        self.slots[slot as usize]
+    }
+
+    /// Writer for a new image in the inactive slot. A pending image in there is no longer
+    /// pending.
+    ///
+    /// `Error::InTrial` while the active slot is on trial, the inactive slot then holds the
+    /// confirmed image a failed trial reverts to.
+    pub fn stage(&mut self, flash: &mut UnlockedFlash) -> core::result::Result<ImageWriter, Error> {
+        if !self.state.confirmed {
+            return Err(Error::InTrial);
+        }
+        if self.state.pending.is_some() {
+            self.save(
+                flash,
+                SlotState {
+                    pending: None,
+                    ..self.state
+                },
+            )?;
+        }
+        Ok(ImageWriter::new(self.region(self.inactive())))
+    }
+
+    /// Boot the inactive slot on the next reset. The image has to be valid and, with a rollback
+    /// counter, not older than its minimum.
+    pub fn mark_pending(&mut self, flash: &mut UnlockedFlash) -> Result {
+        self.check_image(flash, self.inactive())?;
+        self.save(
+            flash,
+            SlotState {
+                pending: Some(self.inactive()),
+                ..self.state
+            },
+        )
+    }
+
+    /// Finish the image of `writer` from `stage` with `ImageWriter::commit` and mark it pending,
+    /// so an image whose running CRC doesn't match its header is never booted
+    pub fn commit(&mut self, flash: &mut UnlockedFlash, writer: ImageWriter) -> Result {
+        if writer.region().start.0 != self.region(self.inactive()).start.0 {
+            return Err(Error::OutOfBounds);
+        }
+        writer.commit(flash)?;
+        self.mark_pending(flash)
+    }
+
+    /// Whether the active slot is on trial and gets reverted unless `mark_boot_successful` is
+    /// called
+    pub fn in_trial(&self) -> bool {
+        !self.state.confirmed
+    }
+
+    /// Record that the application in the active slot works, ending its trial. With a rollback
+    /// counter its minimum is raised to the version of the image.
+    pub fn mark_boot_successful(&mut self, flash: &mut UnlockedFlash) -> Result {
+        if !self.state.confirmed {
+            self.save(
+                flash,
+                SlotState {
+                    confirmed: true,
+                    trials: 0,
+                    ..self.state
+                },
+            )?;
+        }
+        let active = self.region(self.active());
+        if let Some(rollback) = &mut self.rollback {
+            let header = parse_header(flash, &active).ok_or(Error::InvalidImage)?;
+            rollback.raise(flash, header.version)?;
+        }
+        Ok(())
+    }
+
+    /// Called by the bootloader on every boot, returns the slot to start.
+    ///
+    /// A pending slot becomes the active one on trial, unless its image fails `mark_pending`'s
+    /// checks and is dropped. An active slot on trial that used up its boots is reverted to the
+    /// previous slot, otherwise the boot is counted. If the previous slot no longer passes the
+    /// checks either, the slot on trial is kept as the only image left to start.
+    pub fn boot_slot(&mut self, flash: &mut UnlockedFlash) -> core::result::Result<Slot, Error> {
+        let state = self.state;
+        if let Some(pending) = state.pending {
+            let state = if self.check_image(flash, pending).is_err() {
+                SlotState {
+                    pending: None,
+                    ..state
+                }
+            } else {
+                SlotState {
+                    active: pending,
+                    pending: None,
+                    confirmed: false,
+                    trials: 1,
+                }
+            };
+            self.save(flash, state)?;
+        } else if !state.confirmed && state.trials >= self.max_trials {
+            let active = if self.check_image(flash, state.active.other()).is_ok() {
+                state.active.other()
+            } else {
+                state.active
+            };
+            self.save(
+                flash,
+                SlotState {
+                    active,
+                    pending: None,
+                    confirmed: true,
+                    trials: 0,
+                },
+            )?;
+        } else if !state.confirmed {
+            self.save(
+                flash,
+                SlotState {
+                    trials: state.trials + 1,
+                    ..state
+                },
+            )?;
+        }
+        Ok(self.state.active)
+    }
+
+    fn check_image(&self, flash: &UnlockedFlash, slot: Slot) -> Result {
+        let header = validate_image(flash, &self.region(slot), &self.verifier)?;
+        match &self.rollback {
+            Some(rollback) => rollback.check(&header),
+            None => Ok(()),
+        }
+    }
+
+    fn save(&mut self, flash: &mut UnlockedFlash, state: SlotState) -> Result {
+        self.log.append(flash, &state.to_bytes())?;
+        self.state = state;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_round_trip() {
+        let state = SlotState {
+            active: Slot::B,
+            pending: Some(Slot::A),
+            confirmed: false,
+            trials: 3,
+        };
+        assert_eq!(SlotState::from_record(state.to_bytes(), RECORD_LEN), Some(state));
+    }
+
+    #[test]
+    fn legacy_record_is_read() {
+        let bytes = [1, NONE, 0];
+        let record = [bytes[0], bytes[1], bytes[2], check(&bytes), 0];
+        assert_eq!(
+            SlotState::from_record(record, LEGACY_RECORD_LEN),
+            Some(SlotState {
+                active: Slot::B,
+                pending: None,
+                confirmed: false,
+                trials: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn damaged_record_is_skipped() {
+        let mut record = SlotState {
+            active: Slot::A,
+            pending: None,
+            confirmed: true,
+            trials: 0,
+        }
+        .to_bytes();
+        record[2] ^= 1;
+        assert_eq!(SlotState::from_record(record, RECORD_LEN), None);
+        assert_eq!(SlotState::from_record(record, 3), None);
+    }
+}
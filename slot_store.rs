@@ -0,0 +1,213 @@
+//! Fixed-size records in numbered slots, for bounded tables such as paired device credentials.
+//!
+//! ```text
+//! state (u16) per slot | slot 0 | slot 1 | ...
+//! ```
+//!
+//! The controller can't program single bits, so the allocation bitmap holds a halfword per
+//! slot that only ever moves towards zero: erased is free, `ALLOCATED` once the slot has been
+//! written, `0x0000` once it has been freed. A slot is written first and marked allocated
+//! last, so a write cut short by a reset leaves a free slot with data in it, which `alloc`
+//! frees for good. Freed slots are reused only after `clear` or `compact`.
+//!
+//! `compact` keeps the allocated slots through a scratch region: they are copied there, at the
+//! same offsets behind a relocation marker that is programmed last, then the store region is
+//! erased and the slots are copied back. After a reset during compaction, `finish_compaction`
+//! picks up the copy from the scratch region.
+//!
+//! Slots with their state or data on a page in the mounted `BadPages` table are never used.
+
+use crate::{Error, Read, Region, Result, UnlockedFlash, WriteErase, ERASED_BYTE};
+
+const FREE: u16 = 0xffff;
+const ALLOCATED: u16 = 0x5555;
+const FREED: u16 = 0x0000;
+/// Start of a scratch region holding a complete copy of the allocated slots
+const RELOCATION: u16 = 0x5353;
+const MARKER_LEN: usize = 2;
+
+/// Region of slots of `N` bytes each
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SlotStore<const N: usize> {
+    region: Region,
+    slots: usize,
+}
+
+impl<const N: usize> SlotStore<N> {
+    const SLOT_LEN: usize = (N + 1) / 2 * 2;
+
+    pub fn mount(region: Region) -> core::result::Result<Self, Error> {
+        let slots = region.len() / (2 + Self::SLOT_LEN);
+        if N == 0 || slots == 0 {
+            return Err(Error::InvalidLength);
+        }
+        Ok(SlotStore { region, slots })
+    }
+
+    /// Number of slots in the region, including the ones on bad pages
+    pub fn capacity(&self) -> usize {
+        self.slots
+    }
+
+    /// Index of a free slot for `write`, `Error::RegionFull` if every slot has been used
+    pub fn alloc(&self, flash: &mut UnlockedFlash) -> core::result::Result<usize, Error> {
+        for slot in 0..self.slots {
+            if !self.is_usable(flash, slot) || self.state(flash, slot) != FREE {
+                continue;
+            }
+            if self.is_blank(flash, slot) {
+                return Ok(slot);
+            }
+            // Left over from an interrupted write
+            flash.write_native(self.state_address(slot), &[FREED])?;
+        }
+        Err(Error::RegionFull)
+    }
+
+    /// Store `data` in the free `slot`, padding it with erased bytes up to `N`
+    pub fn write(&self, flash: &mut UnlockedFlash, slot: usize, data: &[u8]) -> Result {
+        if slot >= self.slots {
+            return Err(Error::OutOfBounds);
+        }
+        if data.len() > N {
+            return Err(Error::InvalidLength);
+        }
+        if !self.is_usable(flash, slot) {
+            return Err(Error::Failure);
+        }
+        if self.state(flash, slot) != FREE {
+            return Err(Error::ProgrammingError);
+        }
+        if !data.is_empty() {
+            flash.write(self.slot_address(slot), data)?;
+        }
+        flash.write_native(self.state_address(slot), &[ALLOCATED])
+    }
+
+    /// Free `slot`. The space is reclaimed by the next `clear`.
+    pub fn free(&self, flash: &mut UnlockedFlash, slot: usize) -> Result {
+        if slot >= self.slots {
+            return Err(Error::OutOfBounds);
+        }
+        if !self.is_usable(flash, slot) {
+            return Ok(());
+        }
+        flash.write_native(self.state_address(slot), &[FREED])
+    }
+
+    /// Whether `slot` holds data
+    pub fn is_allocated(&self, flash: &UnlockedFlash, slot: usize) -> bool {
+        slot < self.slots && self.is_usable(flash, slot) && self.state(flash, slot) == ALLOCATED
+    }
+
+    /// Copy the `N` bytes of the allocated `slot` into `buf`
+    pub fn read(&self, flash: &UnlockedFlash, slot: usize, buf: &mut [u8; N]) -> Result {
+        if !self.is_allocated(flash, slot) {
+            return Err(Error::OutOfBounds);
+        }
+        flash.read(self.slot_address(slot), buf);
+        Ok(())
+    }
+
+    /// Indices of the allocated slots
+    pub fn slots<'a>(&'a self, flash: &'a UnlockedFlash) -> impl Iterator<Item = usize> + 'a {
+        (0..self.slots).filter(move |&slot| self.is_allocated(flash, slot))
+    }
+
+    /// Erase the region, freeing every slot
+    pub fn clear(&self, flash: &mut UnlockedFlash) -> Result {
+        flash.erase_region(&self.region)
+    }
+
+    /// Make the freed slots free again, keeping the allocated ones at their index. `scratch`
+    /// has to be 2 bytes longer than the used part of the store region and not overlap it,
+    /// `Error::RegionFull` otherwise, and fails with `Error::Failure` if that part of it holds
+    /// a bad page.
+    pub fn compact(&self, flash: &mut UnlockedFlash, scratch: &Region) -> Result {
+        if MARKER_LEN + self.len() > scratch.len() {
+            return Err(Error::RegionFull);
+        }
+        if flash.touches_bad_page(scratch.start_address(), MARKER_LEN + self.len()) {
+            return Err(Error::Failure);
+        }
+        if flash.read_halfword(scratch.start_address()) != RELOCATION {
+            flash.erase_region(scratch)?;
+            let base = scratch.start_address() + MARKER_LEN;
+            for slot in 0..self.slots {
+                if !self.is_allocated(flash, slot) {
+                    continue;
+                }
+                let offset = self.slot_address(slot) - self.region.start_address();
+                copy(flash, self.slot_address(slot), base + offset, Self::SLOT_LEN)?;
+                flash.write_native(base + 2 * slot, &[ALLOCATED])?;
+            }
+            flash.write_native(scratch.start_address(), &[RELOCATION])?;
+        }
+        self.copy_back(flash, scratch)
+    }
+
+    /// Finish a compaction that a reset cut short, doing nothing if there is none. Call it
+    /// right after `mount` with the scratch region `compact` is called with.
+    pub fn finish_compaction(&self, flash: &mut UnlockedFlash, scratch: &Region) -> Result {
+        if flash.read_halfword(scratch.start_address()) != RELOCATION {
+            return Ok(());
+        }
+        self.copy_back(flash, scratch)
+    }
+
+    fn copy_back(&self, flash: &mut UnlockedFlash, scratch: &Region) -> Result {
+        flash.erase_region(&self.region)?;
+        let base = scratch.start_address() + MARKER_LEN;
+        for slot in 0..self.slots {
+            if !self.is_usable(flash, slot) || flash.read_halfword(base + 2 * slot) != ALLOCATED {
+                continue;
+            }
+            let offset = self.slot_address(slot) - self.region.start_address();
+            copy(flash, base + offset, self.slot_address(slot), Self::SLOT_LEN)?;
+            flash.write_native(self.state_address(slot), &[ALLOCATED])?;
+        }
+        flash.erase_region(scratch)
+    }
+
+    /// Bytes of the region taken up by the bitmap and the slots
+    fn len(&self) -> usize {
+        self.slots * (2 + Self::SLOT_LEN)
+    }
+
+    fn state(&self, flash: &UnlockedFlash, slot: usize) -> u16 {
+        flash.read_halfword(self.state_address(slot))
+    }
+
+    /// Whether neither the state nor the data of `slot` lies on a bad page
+    fn is_usable(&self, flash: &UnlockedFlash, slot: usize) -> bool {
+        !flash.touches_bad_page(self.state_address(slot), 2)
+            && !flash.touches_bad_page(self.slot_address(slot), Self::SLOT_LEN)
+    }
+
+    fn is_blank(&self, flash: &UnlockedFlash, slot: usize) -> bool {
+        let mut byte = [0u8; 1];
+        (0..Self::SLOT_LEN).all(|offset| {
+            flash.read(self.slot_address(slot) + offset, &mut byte);
+            byte[0] == ERASED_BYTE
+        })
+    }
+
+    fn state_address(&self, slot: usize) -> usize {
+        self.region.start_address() + 2 * slot
+    }
+
+    fn slot_address(&self, slot: usize) -> usize {
+        self.region.start_address() + 2 * self.slots + slot * Self::SLOT_LEN
+    }
+}
+
+fn copy(flash: &mut UnlockedFlash, source: usize, target: usize, len: usize) -> Result {
+    for offset in (0..len).step_by(2) {
+        let halfword = flash.read_halfword(source + offset);
+        if halfword != FREE {
+            flash.write_native(target + offset, &[halfword])?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,102 @@
+//! SPI slave flashing protocol, for reflashing the F0 from a Linux host with plain spidev
+//! transfers.
+//!
+//! The commands, frames and answers are those of `I2cBootloader`. As a slave can't tell where
+//! a frame ends, every frame is sent with a start byte and its length:
+//!
+//! ```text
+//! 0x5a | length (u16, big endian) | frame
+//! ```
+//!
+//! The target shifts out `SPI_FILLER` while a frame comes in and while it's handled. The host
+//! then polls by clocking out `0x00` until it gets the `ACK` or `NACK` that starts the answer,
+//! like the ACK polling of AN4286, and reads the rest of the answer, whose length it knows from
+//! the command. A new frame drops what's left of the previous answer.
+
+use crate::{I2cBootloader, Region, UnlockedFlash};
+
+const SOF: u8 = 0x5a;
+/// Byte shifted out when there is nothing to answer
+pub const SPI_FILLER: u8 = 0xa5;
+/// Longest frame, the page list of an erase of 256 pages
+const MAX_FRAME: usize = 2 * 256 + 1;
+
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+enum State {
+    Idle,
+    LengthHigh,
+    LengthLow(u8),
+    Frame(usize),
+}
+
+/// Frame handler for the SPI slave driver
+pub struct SpiBootloader {
+    frames: I2cBootloader,
+    state: State,
+    frame: [u8; MAX_FRAME],
+    len: usize,
+}
+
+impl SpiBootloader {
+    /// Handler that lets the host write, erase and start the application in `region`
+    pub fn new(region: Region) -> Self {
+        SpiBootloader {
+            frames: I2cBootloader::new(region),
+            state: State::Idle,
+            frame: [0u8; MAX_FRAME],
+            len: 0,
+        }
+    }
+
+    /// Take the byte the host shifted in and return the one to shift out next. A frame is
+    /// handled as soon as its last byte is in, so the host has to poll for the answer.
+    pub fn exchange(&mut self, flash: &mut UnlockedFlash, byte: u8) -> u8 {
+        match self.state {
+            State::Idle => {
+                if byte == SOF {
+                    self.state = State::LengthHigh;
+                    // Drop the rest of an answer the host didn't read
+                    while self.frames.on_read(&mut [0u8; 16]) != 0 {}
+                } else {
+                    let mut next = [SPI_FILLER];
+                    self.frames.on_read(&mut next);
+                    return next[0];
+                }
+            }
+            State::LengthHigh => self.state = State::LengthLow(byte),
+            State::LengthLow(high) => {
+                self.len = 0;
+                self.state = match u16::from_be_bytes([high, byte]) as usize {
+                    0 => {
+                        self.frames.on_write(flash, &[]);
+                        State::Idle
+                    }
+                    len => State::Frame(len),
+                };
+            }
+            State::Frame(len) => {
+                if self.len < self.frame.len() {
+                    self.frame[self.len] = byte;
+                }
+                self.len += 1;
+                if self.len == len {
+                    // A frame too long for any command gets a NACK
+                    let frame: &[u8] = if len <= self.frame.len() {
+                        &self.frame[..len]
+                    } else {
+                        &[]
+                    };
+                    self.frames.on_write(flash, frame);
+                    self.state = State::Idle;
+                }
+            }
+        }
+        SPI_FILLER
+    }
+
+    /// Address of a completed GO command, see `I2cBootloader::go_address`
+    pub fn go_address(&self) -> Option<usize> {
+        self.frames.go_address()
+    }
+}
@@ -0,0 +1,79 @@
+use crate::{FlashPage, Read, Result, UnlockedFlash, WriteErase};
+
+/// Read-only half of a split flash. It can be copied freely between tasks.
+#[derive(Copy, Clone, Debug)]
+pub struct FlashReader {
+    _private: (),
+}
+
+/// Exclusive erase/write half of a split flash.
+pub struct FlashWriter {
+    flash: UnlockedFlash,
+}
+
+impl UnlockedFlash {
+    /// Split into a shareable reader and an exclusive writer.
+    ///
+    /// Reads that hit the flash while the writer is programming stall until the controller is
+    /// done, they never observe a partial halfword.
+    pub fn split(self) -> (FlashReader, FlashWriter) {
+        (FlashReader { _private: () }, FlashWriter { flash: self })
+    }
+}
+
+impl FlashWriter {
+    /// Join the halves back together
+    pub fn unsplit(self, _reader: FlashReader) -> UnlockedFlash {
+        self.flash
+    }
+}
+
+impl Read for FlashReader {
+    type NativeType = u8;
+
+    fn read_native(&self, address: usize, array: &mut [Self::NativeType]) {
+        let mut address = address as *const Self::NativeType;
+        for data in array {
+            unsafe {
+                *data = core::ptr::read(address);
+                address = address.add(1);
+            }
+        }
+    }
+
+    fn read(&self, address: usize, buf: &mut [u8]) {
+        self.read_native(address, buf);
+    }
+}
+
+impl Read for FlashWriter {
+    type NativeType = u8;
+
+    fn read_native(&self, address: usize, array: &mut [Self::NativeType]) {
+        self.flash.read_native(address, array)
+    }
+
+    fn read(&self, address: usize, buf: &mut [u8]) {
+        self.flash.read(address, buf)
+    }
+}
+
+impl WriteErase for FlashWriter {
+    type NativeType = u16;
+
+    fn status(&self) -> Result {
+        self.flash.status()
+    }
+
+    fn erase_page(&mut self, page: FlashPage) -> Result {
+        self.flash.erase_page(page)
+    }
+
+    fn write_native(&mut self, address: usize, array: &[Self::NativeType]) -> Result {
+        self.flash.write_native(address, array)
+    }
+
+    fn write(&mut self, address: usize, data: &[u8]) -> Result {
+        self.flash.write(address, data)
+    }
+}
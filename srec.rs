@@ -0,0 +1,199 @@
+//! Push parser for Motorola S-record files (S19, S28 and S37), sharing `RecordWriter` with
+//! `IntelHex`.
+//!
+//! Every record is `S`, the type digit and hex digits for
+//!
+//! ```text
+//! count (u8) | address (2, 3 or 4 bytes) | data | checksum (u8)
+//! ```
+//!
+//! big endian, where the count covers the address, the data and the checksum, and the checksum
+//! is the ones' complement of the sum of the other bytes. S1, S2 and S3 carry data with 16, 24
+//! and 32 bit addresses, S5 and S6 the number of data records so far, which is checked, and S7,
+//! S8 and S9 end the file with the start address. The S0 header is skipped, as are line breaks
+//! and other characters between records.
+
+use crate::{Error, RecordWriter, Region, Result, UnlockedFlash};
+
+/// S-record file being written into a region
+pub struct Srec {
+    writer: RecordWriter,
+    record: [u8; 1 + 255],
+    /// The `S` of a record has been seen, its type comes next
+    start: bool,
+    /// Type of the current record, `None` between records
+    kind: Option<u8>,
+    /// Hex digits of the current record so far
+    digits: usize,
+    data_records: u32,
+    start_address: Option<u32>,
+    done: bool,
+}
+
+impl Srec {
+    /// Parser that programs data records inside `region` only, see `RecordWriter`
+    pub fn new(region: Region) -> core::result::Result<Self, Error> {
+        Ok(Srec {
+            writer: RecordWriter::new(region)?,
+            record: [0u8; 1 + 255],
+            start: false,
+            kind: None,
+            digits: 0,
+            data_records: 0,
+            start_address: None,
+            done: false,
+        })
+    }
+
+    /// Parse the next chunk of the file. Fails with `Error::InvalidRecord` for a malformed
+    /// record, a wrong checksum or record count and with `Error::OutOfBounds` for data outside
+    /// of the region. Everything after the termination record is ignored.
+    pub fn feed(&mut self, flash: &mut UnlockedFlash, bytes: &[u8]) -> Result {
+        for &byte in bytes {
+            if self.done {
+                break;
+            }
+            if self.start {
+                self.start = false;
+                self.kind = match (byte as char).to_digit(10) {
+                    Some(kind) if kind != 4 => Some(kind as u8),
+                    _ => return Err(Error::InvalidRecord),
+                };
+                self.digits = 0;
+                continue;
+            }
+            if self.kind.is_none() {
+                self.start = byte == b'S';
+                continue;
+            }
+            let nibble = match (byte as char).to_digit(16) {
+                Some(nibble) => nibble as u8,
+                None => return Err(Error::InvalidRecord),
+            };
+            let index = self.digits / 2;
+            if self.digits % 2 == 0 {
+                self.record[index] = nibble << 4;
+            } else {
+                self.record[index] |= nibble;
+            }
+            self.digits += 1;
+            if self.digits % 2 == 0 && self.digits / 2 == 1 + self.record[0] as usize {
+                let kind = self.kind.take().unwrap_or(0);
+                self.process(flash, kind)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the termination record has been parsed
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Entry point from the termination record
+    pub fn start_address(&self) -> Option<u32> {
+        self.start_address
+    }
+
+    /// Program a byte still held back, `Error::InvalidRecord` if the file didn't end with a
+    /// termination record
+    pub fn finish(mut self, flash: &mut UnlockedFlash) -> Result {
+        self.writer.finish(flash)?;
+        if !self.done {
+            return Err(Error::InvalidRecord);
+        }
+        Ok(())
+    }
+
+    fn process(&mut self, flash: &mut UnlockedFlash, kind: u8) -> Result {
+        let (address, data) = decode(kind, &self.record[..1 + self.record[0] as usize])?;
+        match kind {
+            0 => {}
+            1..=3 => {
+                self.writer.write(flash, address as usize, data)?;
+                self.data_records += 1;
+            }
+            5 | 6 => {
+                if address != self.data_records {
+                    return Err(Error::InvalidRecord);
+                }
+            }
+            _ => {
+                self.start_address = Some(address);
+                self.done = true;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check the checksum of the `record` bytes of type `kind`, from the count to the checksum, and
+/// return its address and data
+fn decode(kind: u8, record: &[u8]) -> core::result::Result<(u32, &[u8]), Error> {
+    let (checksum, bytes) = match record.split_last() {
+        Some((checksum, bytes)) => (*checksum, bytes),
+        None => return Err(Error::InvalidRecord),
+    };
+    let sum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    if !sum != checksum {
+        return Err(Error::InvalidRecord);
+    }
+    let address_len = match kind {
+        0 | 1 | 5 | 9 => 2,
+        2 | 6 | 8 => 3,
+        _ => 4,
+    };
+    if bytes.len() < 1 + address_len {
+        return Err(Error::InvalidRecord);
+    }
+    let address = bytes[1..1 + address_len]
+        .iter()
+        .fold(0u32, |value, byte| (value << 8) | *byte as u32);
+    Ok((address, &bytes[1 + address_len..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn data_records_are_decoded() {
+        // S1 13 7AF0 0A0A0D00000000000000000000000000 61
+        let record = [
+            0x13, 0x7a, 0xf0, 0x0a, 0x0a, 0x0d, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x61,
+        ];
+        let (address, data) = decode(1, &record).unwrap();
+        assert_eq!(address, 0x7af0);
+        assert_eq!(data.len(), 16);
+        assert_eq!(&data[..3], &[0x0a, 0x0a, 0x0d]);
+
+        // S3 07 08000000 1234 AA
+        let record = [0x07, 0x08, 0x00, 0x00, 0x00, 0x12, 0x34, 0xaa];
+        let (address, data) = decode(3, &record).unwrap();
+        assert_eq!(address, 0x0800_0000);
+        assert_eq!(data, &[0x12, 0x34]);
+    }
+
+    #[test]
+    fn termination_record_holds_the_start_address() {
+        // S9 03 0000 FC
+        assert_eq!(decode(9, &[0x03, 0x00, 0x00, 0xfc]).unwrap(), (0, &[][..]));
+        // S7 05 08000101 F0
+        let (address, data) = decode(7, &[0x05, 0x08, 0x00, 0x01, 0x01, 0xf0]).unwrap();
+        assert_eq!(address, 0x0800_0101);
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn wrong_checksum_is_refused() {
+        assert!(matches!(decode(9, &[0x03, 0x00, 0x00, 0xfd]), Err(Error::InvalidRecord)));
+    }
+
+    #[test]
+    fn record_shorter_than_its_address_is_refused() {
+        // S3 with a count of 3 only has room for 2 address bytes
+        assert!(matches!(decode(3, &[0x03, 0x00, 0x00, 0xfc]), Err(Error::InvalidRecord)));
+        assert!(matches!(decode(1, &[]), Err(Error::InvalidRecord)));
+    }
+}
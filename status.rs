@@ -0,0 +1,88 @@
+use crate::regs::{
+    FlashRegisters, CR_EOPIE, CR_ERRIE, CR_LOCK, CR_MER, CR_OPTER, CR_OPTPG, CR_OPTWRE, CR_PER,
+    CR_PG, CR_STRT, SR_BSY, SR_EOP, SR_PGERR, SR_WRPRT,
+};
+use crate::UnlockedFlash;
+
+/// Every FLASH_SR flag and the FLASH_CR state, decoded.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DetailedStatus {
+    /// Operation in progress
+    pub busy: bool,
+    /// Programming target was not erased
+    pub programming_error: bool,
+    /// Write-protected address was programmed or erased
+    pub write_protection_error: bool,
+    /// Operation completed
+    pub end_of_operation: bool,
+    /// Programming mode
+    pub pg: bool,
+    /// Page erase selected
+    pub per: bool,
+    /// Mass erase selected
+    pub mer: bool,
+    /// Option byte programming selected
+    pub optpg: bool,
+    /// Option byte erase selected
+    pub opter: bool,
+    /// Erase start requested
+    pub strt: bool,
+    /// Flash locked
+    pub lock: bool,
+    /// Option bytes unlocked for writing
+    pub optwre: bool,
+    /// Error interrupt enabled
+    pub errie: bool,
+    /// End of operation interrupt enabled
+    pub eopie: bool,
+    /// Raw FLASH_SR
+    pub sr: u32,
+    /// Raw FLASH_CR
+    pub cr: u32,
+}
+
+impl DetailedStatus {
+    pub const fn from_bits(sr: u32, cr: u32) -> Self {
+        DetailedStatus {
+            busy: sr & SR_BSY != 0,
+            programming_error: sr & SR_PGERR != 0,
+            write_protection_error: sr & SR_WRPRT != 0,
+            end_of_operation: sr & SR_EOP != 0,
+            pg: cr & CR_PG != 0,
+            per: cr & CR_PER != 0,
+            mer: cr & CR_MER != 0,
+            optpg: cr & CR_OPTPG != 0,
+            opter: cr & CR_OPTER != 0,
+            strt: cr & CR_STRT != 0,
+            lock: cr & CR_LOCK != 0,
+            optwre: cr & CR_OPTWRE != 0,
+            errie: cr & CR_ERRIE != 0,
+            eopie: cr & CR_EOPIE != 0,
+            sr,
+            cr,
+        }
+    }
+}
+
+impl UnlockedFlash {
+    /// All status flags and control bits, without collapsing them into one error like `status`
+    pub fn status_detailed(&self) -> DetailedStatus {
+        DetailedStatus::from_bits(self.f.read_sr(), self.f.read_cr())
+    }
+
+    /// Clear the programming error flag
+    pub fn clear_pgerr(&mut self) {
+        self.f.clear_sr(SR_PGERR);
+    }
+
+    /// Clear the write protection error flag
+    pub fn clear_wrprt(&mut self) {
+        self.f.clear_sr(SR_WRPRT);
+    }
+
+    /// Clear the end of operation flag
+    pub fn clear_eop(&mut self) {
+        self.f.clear_sr(SR_EOP);
+    }
+}
@@ -0,0 +1,126 @@
+//! Flash error counters that survive resets.
+//!
+//! The counters are appended as one 6 byte record per `persist` to a reserved region. Every
+//! record is programmed into erased halfwords after the previous one, so the region is only
+//! erased once it is full. The last record in the region holds the current counts.
+
+use crate::{AppendPolicy, Error, Read, Region, UnlockedFlash};
+
+const RECORD_LEN: usize = 6;
+// A counter of 0xffff would read as the erased tail of the region
+const MAX_COUNT: u16 = 0xfffe;
+
+/// Number of failed flash operations by cause
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ErrorCounters {
+    /// `Error::ProgrammingError`
+    pub pgerr: u16,
+    /// `Error::WriteProtectionError`
+    pub wrprt: u16,
+    /// The controller stayed busy or never signalled end of operation, `Error::Busy` and
+    /// `Error::Eop`
+    pub timeout: u16,
+}
+
+impl ErrorCounters {
+    fn to_bytes(self) -> [u8; RECORD_LEN] {
+        let mut record = [0u8; RECORD_LEN];
+        record[0..2].copy_from_slice(&self.pgerr.to_ne_bytes());
+        record[2..4].copy_from_slice(&self.wrprt.to_ne_bytes());
+        record[4..6].copy_from_slice(&self.timeout.to_ne_bytes());
+        record
+    }
+
+    fn from_bytes(record: &[u8; RECORD_LEN]) -> Self {
+        ErrorCounters {
+            pgerr: u16::from_ne_bytes([record[0], record[1]]),
+            wrprt: u16::from_ne_bytes([record[2], record[3]]),
+            timeout: u16::from_ne_bytes([record[4], record[5]]),
+        }
+    }
+}
+
+/// Error counters backed by a reserved flash region.
+///
+/// Feed every flash error to `record` and call `persist` from a convenient place, e.g. the idle
+/// loop. Counts saturate at `0xfffe`. A reset between erasing the full region and programming
+/// the next record loses the counts.
+#[derive(Debug)]
+pub struct Telemetry {
+    region: Region,
+    counters: ErrorCounters,
+    pending: u16,
+    interval: u16,
+}
+
+impl Telemetry {
+    /// Counters stored in `region`, starting from the last record found there.
+    ///
+    /// `persist` only programs a record once `interval` errors have been recorded since the
+    /// last one, use `persist_now` to force it.
+    pub fn load(flash: &UnlockedFlash, region: Region, interval: u16) -> Self {
+        let end = flash.first_blank_offset(&region);
+        let counters = if end >= RECORD_LEN {
+            let mut record = [0u8; RECORD_LEN];
+            flash.read(region.start_address() + end - RECORD_LEN, &mut record);
+            ErrorCounters::from_bytes(&record)
+        } else {
+            ErrorCounters::default()
+        };
+        Telemetry {
+            region,
+            counters,
+            pending: 0,
+            interval,
+        }
+    }
+
+    pub fn counters(&self) -> ErrorCounters {
+        self.counters
+    }
+
+    /// Count `error` if it is one of the tracked causes
+    pub fn record(&mut self, error: Error) {
+        let counter = match error {
+            Error::ProgrammingError => &mut self.counters.pgerr,
+            Error::WriteProtectionError => &mut self.counters.wrprt,
+            Error::Busy | Error::Eop => &mut self.counters.timeout,
+            _ => return,
+        };
+        if *counter < MAX_COUNT {
+            *counter += 1;
+        }
+        self.pending = self.pending.saturating_add(1);
+    }
+
+    /// Record the error of `result`, if any, and hand it back unchanged
+    pub fn observe<T>(
+        &mut self,
+        result: core::result::Result<T, Error>,
+    ) -> core::result::Result<T, Error> {
+        if let Err(error) = result {
+            self.record(error);
+        }
+        result
+    }
+
+    /// Program the counters once `interval` errors are pending
+    pub fn persist(&mut self, flash: &mut UnlockedFlash) -> crate::Result {
+        if self.pending == 0 || self.pending < self.interval {
+            return Ok(());
+        }
+        self.persist_now(flash)
+    }
+
+    /// Program the counters if anything changed since the last record
+    pub fn persist_now(&mut self, flash: &mut UnlockedFlash) -> crate::Result {
+        if self.pending == 0 {
+            return Ok(());
+        }
+        let record = self.counters.to_bytes();
+        flash.append(&self.region, &record, AppendPolicy::EraseAndWrap)?;
+        self.pending = 0;
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+//! Timestamped text lines in a flash ring, written with `writeln!`.
+//!
+//! ```ignore
+//! let mut log = TextLog::<80>::mount(&mut flash, LOG_REGION, uptime_ms)?;
+//! writeln!(log, "battery {} mV", millivolts)?;
+//! ```
+//!
+//! Every line is a `RingLog` record of the timestamp taken when the line was completed followed
+//! by its text. Lines longer than `N` bytes are split, and once the region is full the oldest
+//! page of lines is erased.
+
+use core::fmt;
+
+use crate::{Error, Region, Result, RingLog, UnlockedFlash};
+
+const TIMESTAMP_LEN: usize = 4;
+
+/// Line oriented `fmt::Write` sink with lines of up to `N` bytes
+pub struct TextLog<'a, const N: usize> {
+    flash: &'a mut UnlockedFlash,
+    log: RingLog,
+    line: [u8; N],
+    len: usize,
+    timestamp: fn() -> u32,
+}
+
+impl<'a, const N: usize> TextLog<'a, N> {
+    /// Open the log in `region`, stamping every line with the value of `timestamp`
+    pub fn mount(
+        flash: &'a mut UnlockedFlash,
+        region: Region,
+        timestamp: fn() -> u32,
+    ) -> core::result::Result<Self, Error> {
+        let log = RingLog::mount(flash, region)?;
+        if N + TIMESTAMP_LEN > log.max_record_len() {
+            return Err(Error::InvalidLength);
+        }
+        Ok(TextLog {
+            flash,
+            log,
+            line: [0u8; N],
+            len: 0,
+            timestamp,
+        })
+    }
+
+    /// Write out the current line even if it isn't terminated yet
+    pub fn flush(&mut self) -> Result {
+        let len = core::mem::replace(&mut self.len, 0);
+        if len == 0 {
+            return Ok(());
+        }
+        let timestamp = (self.timestamp)().to_ne_bytes();
+        self.log.append_parts(self.flash, &[&timestamp, &self.line[..len]])
+    }
+
+    /// Pass every stored line with its timestamp to `f`, oldest first
+    pub fn for_each_line(&self, mut f: impl FnMut(u32, &[u8])) {
+        let mut timestamp = [0u8; TIMESTAMP_LEN];
+        let mut line = [0u8; N];
+        for record in self.log.records(&*self.flash) {
+            if record.read(&*self.flash, &mut timestamp) < TIMESTAMP_LEN {
+                continue;
+            }
+            let len = record.read_at(&*self.flash, TIMESTAMP_LEN, &mut line);
+            f(u32::from_ne_bytes(timestamp), &line[..len]);
+        }
+    }
+
+    /// Erase every stored line
+    pub fn clear(&mut self) -> Result {
+        self.len = 0;
+        self.log.clear(self.flash)
+    }
+}
+
+impl<const N: usize> fmt::Write for TextLog<'_, N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            if byte == b'\n' {
+                self.flush().map_err(|_| fmt::Error)?;
+                continue;
+            }
+            if self.len == N {
+                self.flush().map_err(|_| fmt::Error)?;
+            }
+            self.line[self.len] = byte;
+            self.len += 1;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+//! TicKV backend.
+//!
+//! TicKV packs objects back to back and programs each one with a single `write`. The flash is
+//! programmed in halfwords, so a write starting on an odd offset would have to program the
+//! halfword holding the last byte of the previous object again. Such writes fail with
+//! `ErrorCode::WriteFail`: keep the object lengths even.
+
+use core::cell::RefCell;
+
+use tickv::error_codes::ErrorCode;
+use tickv::flash_controller::FlashController;
+
+use crate::{Read, Region, UnlockedFlash, WriteErase, PAGE_SIZE};
+
+/// `FlashController` over the pages of `region`. TicKV region `n` is page `n` of the region and
+/// `S` has to be `PAGE_SIZE`.
+pub struct TickvFlash<const S: usize> {
+    flash: RefCell<UnlockedFlash>,
+    region: Region,
+}
+
+impl<const S: usize> TickvFlash<S> {
+    const PAGE_SIZED: () = assert!(S == PAGE_SIZE as usize, "TicKV region size must be PAGE_SIZE");
+
+    pub fn new(flash: UnlockedFlash, region: Region) -> Self {
+        let () = Self::PAGE_SIZED;
+        TickvFlash {
+            flash: RefCell::new(flash),
+            region,
+        }
+    }
+
+    pub fn region(&self) -> &Region {
+        &self.region
+    }
+
+    /// Size of the region in bytes, the flash size to pass to `TicKV::new`
+    pub fn size(&self) -> usize {
+        self.region.len()
+    }
+
+    pub fn into_inner(self) -> UnlockedFlash {
+        self.flash.into_inner()
+    }
+}
+
+impl<const S: usize> FlashController<S> for TickvFlash<S> {
+    fn read_region(&self, region_number: usize, buf: &mut [u8; S]) -> Result<(), ErrorCode> {
+        if region_number >= self.region.pages {
+            return Err(ErrorCode::ReadFail);
+        }
+        let address = self.region.page(region_number).to_address();
+        self.flash.borrow().read(address, buf);
+        Ok(())
+    }
+
+    fn write(&self, address: usize, buf: &[u8]) -> Result<(), ErrorCode> {
+        let end = address.checked_add(buf.len());
+        if !matches!(end, Some(end) if end <= self.region.len()) || address % 2 != 0 {
+            return Err(ErrorCode::WriteFail);
+        }
+        self.flash
+            .borrow_mut()
+            .write(self.region.start_address() + address, buf)
+            .map_err(|_| ErrorCode::WriteFail)
+    }
+
+    fn erase_region(&self, region_number: usize) -> Result<(), ErrorCode> {
+        if region_number >= self.region.pages {
+            return Err(ErrorCode::EraseFail);
+        }
+        self.flash
+            .borrow_mut()
+            .erase_page(self.region.page(region_number))
+            .map_err(|_| ErrorCode::EraseFail)
+    }
+}
@@ -0,0 +1,383 @@
+//! Tag-length-value records appended to a region.
+//!
+//! ```text
+//! tag (u16) | length (u16) | value
+//! ```
+//!
+//! Values are padded to a halfword. The length is programmed first and the tag last, so a record
+//! cut short by a reset keeps its size but has no tag and is skipped.
+//!
+//! Superseded records are invalidated by programming their tag to `0x0000`, which needs no
+//! erase. If a reset hits between appending the new record and invalidating the old one, both
+//! stay live and the newer one still wins.
+//!
+//! Records appended through a `Transaction` follow a marker record and only become visible
+//! once the marker is committed, see `TlvStore::begin`.
+//!
+//! `compact` reclaims the space of invalidated records through a scratch region: the live
+//! records are copied there and a relocation marker is programmed after the last one, then the
+//! store region is erased and the records are copied back. After a reset during compaction,
+//! `finish_compaction` picks up the copy from the scratch region.
+
+use crate::{Error, Read, Region, Result, Transaction, UnlockedFlash, WriteErase};
+
+const HEADER_LEN: usize = 4;
+const END: u16 = 0xffff;
+const INVALID: u16 = 0x0000;
+/// Tag of a transaction marker, its value is the length of the transaction once committed
+const TRANSACTION: u16 = 0xfffe;
+const MARKER_LEN: usize = HEADER_LEN + 2;
+/// Start of a scratch region holding a complete copy of the live records
+const RELOCATION: u16 = 0x544c;
+
+/// A live record, as returned by the `TlvStore` lookups
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TlvRecord {
+    tag: u16,
+    address: usize,
+    len: usize,
+}
+
+impl TlvRecord {
+    pub fn tag(&self) -> u16 {
+        self.tag
+    }
+
+    /// Length of the value in bytes
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Copy the start of the value into `buf` and return how many bytes were copied
+    pub fn read(&self, flash: &impl Read<NativeType = u8>, buf: &mut [u8]) -> usize {
+        let len = self.len.min(buf.len());
+        flash.read(self.address + HEADER_LEN, &mut buf[..len]);
+        len
+    }
+}
+
+/// Append-only TLV store. Tags `0x0000`, `0xfffe` and `0xffff` are reserved.
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TlvStore {
+    region: Region,
+    end: usize,
+    /// Offset of the marker of a transaction that hasn't been committed
+    uncommitted: Option<usize>,
+    auto_compact: Option<(Region, u8)>,
+}
+
+impl TlvStore {
+    /// Find the end of the records in `region`
+    pub fn mount(flash: &UnlockedFlash, region: Region) -> Self {
+        let mut store = TlvStore {
+            region,
+            end: region.len(),
+            uncommitted: None,
+            auto_compact: None,
+        };
+        let mut offset = 0;
+        while offset + HEADER_LEN <= region.len() {
+            let address = region.start_address() + offset;
+            let tag = flash.read_halfword(address);
+            if tag == END && flash.read_halfword(address + 2) == END {
+                store.end = offset;
+                break;
+            }
+            if tag == TRANSACTION && flash.read_halfword(address + HEADER_LEN) == END {
+                store.uncommitted = Some(offset);
+            }
+            offset += record_size(flash.read_halfword(address + 2));
+        }
+        store
+    }
+
+    /// Bytes left for records, including their 4 byte headers
+    pub fn remaining(&self) -> usize {
+        self.region.len() - self.end
+    }
+
+    /// Append a record, keeping the older records of `tag` live
+    pub fn append(&mut self, flash: &mut UnlockedFlash, tag: u16, value: &[u8]) -> Result {
+        self.prepare(flash, record_size(value.len() as u16))?;
+        self.append_record(flash, tag, value)
+    }
+
+    /// Start a transaction. Its records stay invisible until `Transaction::commit`, and are
+    /// rolled back by the next append if it is dropped or cut short by a reset.
+    pub fn begin(
+        &mut self,
+        flash: &mut UnlockedFlash,
+    ) -> core::result::Result<Transaction<'_>, Error> {
+        self.prepare(flash, MARKER_LEN)?;
+        if MARKER_LEN > self.remaining() {
+            return Err(Error::RegionFull);
+        }
+        let marker = self.end;
+        self.end += MARKER_LEN;
+        self.uncommitted = Some(marker);
+        let address = self.region.start_address() + marker;
+        flash.write_native(address + 2, &[2])?;
+        flash.write_native(address, &[TRANSACTION])?;
+        Ok(Transaction::new(self, marker))
+    }
+
+    /// Append a record without superseding, as part of the open transaction if there is one
+    pub(crate) fn append_record(
+        &mut self,
+        flash: &mut UnlockedFlash,
+        tag: u16,
+        value: &[u8],
+    ) -> Result {
+        if tag == END || tag == INVALID || tag == TRANSACTION {
+            return Err(Error::OutOfBounds);
+        }
+        let size = HEADER_LEN + (value.len() + 1) / 2 * 2;
+        if value.len() >= END as usize || size > self.remaining() {
+            return Err(Error::RegionFull);
+        }
+        let address = self.region.start_address() + self.end;
+        self.end += size;
+        flash.write_native(address + 2, &[value.len() as u16])?;
+        if !value.is_empty() {
+            flash.write(address + HEADER_LEN, value)?;
+        }
+        flash.write_native(address, &[tag])
+    }
+
+    /// Append a record that supersedes every older record of `tag`
+    pub fn set(&mut self, flash: &mut UnlockedFlash, tag: u16, value: &[u8]) -> Result {
+        self.prepare(flash, record_size(value.len() as u16))?;
+        let end = self.end;
+        self.append_record(flash, tag, value)?;
+        self.invalidate(flash, tag, end)
+    }
+
+    /// Invalidate every record of `tag`
+    pub fn remove(&mut self, flash: &mut UnlockedFlash, tag: u16) -> Result {
+        self.roll_back(flash)?;
+        self.invalidate(flash, tag, self.end)
+    }
+
+    /// The newest live record of `tag`
+    pub fn get(&self, flash: &UnlockedFlash, tag: u16) -> Option<TlvRecord> {
+        self.records_with_tag(flash, tag).last()
+    }
+
+    /// Every live record, oldest first
+    pub fn records<'a>(&'a self, flash: &'a UnlockedFlash) -> impl Iterator<Item = TlvRecord> + 'a {
+        let mut offset = 0;
+        core::iter::from_fn(move || self.next_live(flash, &mut offset))
+    }
+
+    /// The live records of `tag`, oldest first
+    pub fn records_with_tag<'a>(
+        &'a self,
+        flash: &'a UnlockedFlash,
+        tag: u16,
+    ) -> impl Iterator<Item = TlvRecord> + 'a {
+        self.records(flash).filter(move |record| record.tag == tag)
+    }
+
+    /// Bytes taken up by invalidated, torn and uncommitted records and transaction markers
+    pub fn stale_bytes(&self, flash: &UnlockedFlash) -> usize {
+        self.end - self.live_bytes(flash)
+    }
+
+    /// Drop the stale records by copying the live ones through `scratch`, which must not
+    /// overlap the store. `progress` is called with the bytes copied so far and the total.
+    pub fn compact(
+        &mut self,
+        flash: &mut UnlockedFlash,
+        scratch: &Region,
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result {
+        if flash.read_halfword(scratch.start_address()) != RELOCATION {
+            let live = self.live_bytes(flash);
+            if HEADER_LEN + live > scratch.len() || live >= END as usize {
+                return Err(Error::RegionFull);
+            }
+            flash.erase_region(scratch)?;
+            let mut copied = 0;
+            let mut offset = 0;
+            while let Some(record) = self.next_live(flash, &mut offset) {
+                let size = record_size(record.len as u16);
+                let target = scratch.start_address() + HEADER_LEN + copied;
+                copy(flash, record.address, target, size)?;
+                copied += size;
+                progress(copied, 2 * live);
+            }
+            flash.write_native(scratch.start_address() + 2, &[copied as u16])?;
+            flash.write_native(scratch.start_address(), &[RELOCATION])?;
+        }
+        self.copy_back(flash, scratch, progress)
+    }
+
+    /// Finish a compaction that a reset cut short, doing nothing if there is none. Call it
+    /// right after `mount` with the scratch region `compact` is called with.
+    pub fn finish_compaction(&mut self, flash: &mut UnlockedFlash, scratch: &Region) -> Result {
+        if flash.read_halfword(scratch.start_address()) != RELOCATION {
+            return Ok(());
+        }
+        self.copy_back(flash, scratch, |_, _| {})
+    }
+
+    /// Compact through the scratch region before an append once stale records take up
+    /// `percent` of the store or the record doesn't fit anymore, `None` to never compact on
+    /// its own
+    pub fn set_auto_compact(&mut self, auto_compact: Option<(Region, u8)>) {
+        self.auto_compact = auto_compact;
+    }
+
+    /// Erase the region
+    pub fn clear(&mut self, flash: &mut UnlockedFlash) -> Result {
+        flash.erase_region(&self.region)?;
+        self.end = 0;
+        self.uncommitted = None;
+        Ok(())
+    }
+
+    /// Program the length of the transaction at `marker`, making its records visible, and
+    /// invalidate the records they supersede
+    pub(crate) fn commit(&mut self, flash: &mut UnlockedFlash, marker: usize) -> Result {
+        let span = self.end - marker;
+        if span >= END as usize {
+            return Err(Error::RegionFull);
+        }
+        let address = self.region.start_address() + marker;
+        flash.write_native(address + HEADER_LEN, &[span as u16])?;
+        self.uncommitted = None;
+        let mut offset = marker + MARKER_LEN;
+        while offset < self.end {
+            let address = self.region.start_address() + offset;
+            let len = flash.read_halfword(address + 2);
+            let tag = flash.read_halfword(address);
+            if tag != INVALID && tag != END {
+                self.invalidate(flash, tag, marker)?;
+            }
+            offset += record_size(len);
+        }
+        Ok(())
+    }
+
+    /// Invalidate the marker and the records of an uncommitted transaction
+    pub(crate) fn roll_back(&mut self, flash: &mut UnlockedFlash) -> Result {
+        let marker = match self.uncommitted.take() {
+            Some(marker) => marker,
+            None => return Ok(()),
+        };
+        let mut offset = marker;
+        while offset < self.end {
+            let address = self.region.start_address() + offset;
+            let len = flash.read_halfword(address + 2);
+            if flash.read_halfword(address) != INVALID {
+                flash.write_native(address, &[INVALID])?;
+            }
+            offset += record_size(len);
+        }
+        Ok(())
+    }
+
+    /// Roll back an uncommitted transaction and compact if due before appending `size` bytes
+    fn prepare(&mut self, flash: &mut UnlockedFlash, size: usize) -> Result {
+        self.roll_back(flash)?;
+        let (scratch, percent) = match self.auto_compact {
+            Some(auto_compact) => auto_compact,
+            None => return Ok(()),
+        };
+        if size > self.remaining()
+            || self.stale_bytes(flash) * 100 >= self.region.len() * percent as usize
+        {
+            self.compact(flash, &scratch, |_, _| {})?;
+        }
+        Ok(())
+    }
+
+    /// Erase the store and copy the records back from the complete copy in `scratch`
+    fn copy_back(
+        &mut self,
+        flash: &mut UnlockedFlash,
+        scratch: &Region,
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result {
+        let len = flash.read_halfword(scratch.start_address() + 2) as usize;
+        flash.erase_region(&self.region)?;
+        let mut offset = 0;
+        while offset < len {
+            let source = scratch.start_address() + HEADER_LEN + offset;
+            let size = record_size(flash.read_halfword(source + 2));
+            copy(flash, source, self.region.start_address() + offset, size)?;
+            offset += size;
+            progress(len + offset, 2 * len);
+        }
+        self.end = len;
+        self.uncommitted = None;
+        flash.erase_region(scratch)
+    }
+
+    /// The live record at or after `offset`, moving `offset` past it
+    fn next_live(&self, flash: &UnlockedFlash, offset: &mut usize) -> Option<TlvRecord> {
+        while *offset < self.end {
+            let address = self.region.start_address() + *offset;
+            let tag = flash.read_halfword(address);
+            let len = flash.read_halfword(address + 2);
+            *offset += record_size(len);
+            if tag == TRANSACTION && flash.read_halfword(address + HEADER_LEN) == END {
+                // Nothing after an uncommitted transaction is visible
+                *offset = self.end;
+                return None;
+            }
+            if tag != INVALID && tag != END && tag != TRANSACTION && len != END {
+                return Some(TlvRecord {
+                    tag,
+                    address,
+                    len: len as usize,
+                });
+            }
+        }
+        None
+    }
+
+    fn live_bytes(&self, flash: &UnlockedFlash) -> usize {
+        self.records(flash)
+            .map(|record| record_size(record.len as u16))
+            .sum()
+    }
+
+    /// Invalidate the records of `tag` before offset `end`
+    fn invalidate(&mut self, flash: &mut UnlockedFlash, tag: u16, end: usize) -> Result {
+        let mut offset = 0;
+        while offset < end {
+            let address = self.region.start_address() + offset;
+            let len = flash.read_halfword(address + 2);
+            if flash.read_halfword(address) == tag {
+                flash.write_native(address, &[INVALID])?;
+            }
+            offset += record_size(len);
+        }
+        Ok(())
+    }
+}
+
+/// Copy `len` bytes of programmed halfwords from `source` to the erased `target`
+fn copy(flash: &mut UnlockedFlash, source: usize, target: usize, len: usize) -> Result {
+    for offset in (0..len).step_by(2) {
+        let halfword = flash.read_halfword(source + offset);
+        if halfword != END {
+            flash.write_native(target + offset, &[halfword])?;
+        }
+    }
+    Ok(())
+}
+
+fn record_size(len: u16) -> usize {
+    match len {
+        END => HEADER_LEN,
+        len => HEADER_LEN + (len as usize + 1) / 2 * 2,
+    }
+}
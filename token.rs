@@ -0,0 +1,113 @@
+use crate::{cs, device, AppendPolicy, Error, Read, Region, Result, UnlockedFlash, WriteErase};
+
+static mut ALLOCATOR_TAKEN: bool = false;
+
+/// Hands out one `RegionToken` per configured region.
+///
+/// Only one allocator exists per program and regions have to be claimed in ascending page
+/// order, so no two tokens can ever cover the same page.
+pub struct RegionAllocator {
+    next_free_page: usize,
+}
+
+/// Proof of exclusive access to a region. Not `Clone`, so whoever owns it owns the pages.
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RegionToken {
+    region: Region,
+}
+
+impl RegionAllocator {
+    /// Returns the allocator the first time it is called, `None` afterwards
+    pub fn take() -> Option<Self> {
+        cs::free(|| unsafe {
+            if ALLOCATOR_TAKEN {
+                None
+            } else {
+                ALLOCATOR_TAKEN = true;
+                Some(RegionAllocator { next_free_page: 0 })
+            }
+        })
+    }
+
+    /// Claim `region`, which must start after every previously claimed region.
+    pub fn claim(&mut self, region: Region) -> core::result::Result<RegionToken, Error> {
+        if region.start.0 < self.next_free_page {
+            return Err(Error::RegionOverlap);
+        }
+        if region.start.0 + region.pages > device::num_pages() {
+            return Err(Error::PageOutOfRange);
+        }
+        self.next_free_page = region.start.0 + region.pages;
+        Ok(RegionToken { region })
+    }
+}
+
+impl RegionToken {
+    /// Address of the first byte of the region
+    pub fn start_address(&self) -> usize {
+        self.region.start_address()
+    }
+
+    /// Size of the region in bytes
+    pub fn len(&self) -> usize {
+        self.region.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.region.is_empty()
+    }
+
+    /// Region of the token, for a handle taking over its exclusive access
+    pub(crate) fn into_region(self) -> Region {
+        self.region
+    }
+
+    /// Erase every page of the region
+    pub fn erase(&mut self, flash: &mut UnlockedFlash) -> Result {
+        flash.erase_region(&self.region)
+    }
+
+    /// Erase the `index`th page of the region
+    pub fn erase_page(&mut self, flash: &mut UnlockedFlash, index: usize) -> Result {
+        if index >= self.region.pages {
+            return Err(Error::PageOutOfRange);
+        }
+        flash.erase_page(self.region.page(index))
+    }
+
+    /// Write `data` at `offset` bytes into the region
+    pub fn write(&mut self, flash: &mut UnlockedFlash, offset: usize, data: &[u8]) -> Result {
+        self.check(offset, data.len())?;
+        flash.write(self.region.start_address() + offset, data)
+    }
+
+    /// Read `buf.len()` bytes from `offset` bytes into the region
+    pub fn read(
+        &self,
+        flash: &impl Read<NativeType = u8>,
+        offset: usize,
+        buf: &mut [u8],
+    ) -> Result {
+        self.check(offset, buf.len())?;
+        flash.read(self.region.start_address() + offset, buf);
+        Ok(())
+    }
+
+    /// `UnlockedFlash::append` restricted to this region
+    pub fn append(
+        &mut self,
+        flash: &mut UnlockedFlash,
+        record: &[u8],
+        policy: AppendPolicy,
+    ) -> core::result::Result<usize, Error> {
+        flash.append(&self.region, record, policy)
+    }
+
+    fn check(&self, offset: usize, len: usize) -> Result {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.region.len() => Ok(()),
+            _ => Err(Error::OutOfBounds),
+        }
+    }
+}
@@ -1,6 +1,7 @@
-/// Flash page representation where each flash page represents a region of 1024 bytes. The flash
-/// controller can only erase on a page basis.
+/// Flash page representation where each flash page represents a region of `PAGE_SIZE` bytes (1024
+/// or 2048 depending on the device). The flash controller can only erase on a page basis.
 #[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct FlashPage(pub usize);
 
 pub trait Read {
@@ -18,6 +19,7 @@ pub trait Read {
 
 /// Flash operation error
 #[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Error {
     /// Flash controller is not done yet
     Busy,
@@ -33,6 +35,103 @@ pub enum Error {
     Eop,
     ///Set by hardware when programming a write-protected address of the flash memory.Reset by writing 1
     WriteProtectionError,
+    /// Not enough erased space left in the region for the requested record
+    RegionFull,
+    /// Buffer length does not match what the operation requires
+    InvalidLength,
+    /// Address is not aligned as the operation requires
+    Unaligned,
+    /// Address range lies outside of main flash
+    OutOfBounds,
+    /// Flash controller stayed locked after the unlock key sequence
+    Locked,
+    /// Region overlaps one that has already been claimed
+    RegionOverlap,
+    /// Page has been software write-protected with `protect`
+    SoftProtected,
+    /// Address lies below the boundary of the running firmware
+    FirmwareRegion,
+    /// Operation can't be undone and needs an explicit acknowledgement
+    Irreversible,
+    /// Stored data has a schema version that can't be migrated to the current one
+    SchemaVersion,
+    /// Value could not be serialized or deserialized
+    Serialization,
+    /// Computed CRC doesn't match the expected one
+    CrcMismatch,
+    /// Cached page has changes that have to be flushed first
+    UnflushedCache,
+    /// No valid image header, or one that doesn't fit its slot
+    InvalidImage,
+    /// Image version is below the anti-rollback minimum
+    Rollback,
+    /// Image signature doesn't verify
+    BadSignature,
+    /// Delta patch is malformed or doesn't fit the image it is applied to
+    InvalidPatch,
+    /// Firmware file record is malformed or fails its checksum
+    InvalidRecord,
+    /// Transfer was cancelled by the other side or timed out
+    Cancelled,
+    /// Region that has to be write protected isn't covered by the WRP option bytes
+    Unprotected,
+    /// No partition table with a valid CRC, or one with partitions that don't fit the flash
+    InvalidTable,
+    /// Image in the active slot is on trial and has to be confirmed first
+    InTrial,
+    /// Appended record ends in an erased halfword, which can't be told apart from free space
+    ErasedTail,
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let message = match self {
+            Error::Busy => "flash controller busy",
+            Error::ProgrammingError => "programming error, target was not erased",
+            Error::EccError => "ECC error",
+            Error::PageOutOfRange => "page out of range",
+            Error::Failure => "flash command failed",
+            Error::Eop => "operation did not signal end of operation",
+            Error::WriteProtectionError => "write-protected address",
+            Error::RegionFull => "region full",
+            Error::InvalidLength => "invalid buffer length",
+            Error::Unaligned => "unaligned address",
+            Error::OutOfBounds => "address out of bounds",
+            Error::Locked => "flash is locked",
+            Error::RegionOverlap => "region overlaps a claimed region",
+            Error::SoftProtected => "page is software write-protected",
+            Error::FirmwareRegion => "address inside the running firmware",
+            Error::Irreversible => "irreversible operation needs acknowledgement",
+            Error::SchemaVersion => "no migration for the stored schema version",
+            Error::Serialization => "value could not be serialized or deserialized",
+            Error::CrcMismatch => "CRC mismatch",
+            Error::UnflushedCache => "cached page has unflushed changes",
+            Error::InvalidImage => "no valid image header",
+            Error::Rollback => "image version below the rollback minimum",
+            Error::BadSignature => "image signature doesn't verify",
+            Error::InvalidPatch => "malformed delta patch",
+            Error::InvalidRecord => "malformed firmware file record",
+            Error::Cancelled => "transfer cancelled",
+            Error::Unprotected => "region is not write protected",
+            Error::InvalidTable => "no valid partition table",
+            Error::InTrial => "active image is on trial",
+            Error::ErasedTail => "record ends in an erased halfword",
+        };
+        f.write_str(message)
+    }
+}
+
+/// Flash operation error together with where it happened
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DetailedError {
+    pub error: Error,
+    /// Address of the halfword or page that failed, if known
+    pub address: Option<usize>,
+    /// Page containing `address`
+    pub page: Option<FlashPage>,
+    /// For buffer writes, the byte offset into the buffer that was reached
+    pub offset: Option<usize>,
 }
 
 pub type Result = core::result::Result<(), Error>;
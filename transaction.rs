@@ -0,0 +1,44 @@
+//! All-or-nothing updates of several `TlvStore` records.
+//!
+//! ```ignore
+//! let mut tx = store.begin(&mut flash)?;
+//! tx.set(&mut flash, TAG_SSID, ssid)?;
+//! tx.set(&mut flash, TAG_PASSWORD, password)?;
+//! tx.commit(&mut flash)?;
+//! ```
+//!
+//! A transaction starts with a marker record, its records follow and the length of the
+//! transaction is programmed into the marker last. Until then lookups stop at the marker, so a
+//! reset before the commit leaves the old values in place and the next append rolls the
+//! transaction back by invalidating its records.
+
+use crate::{Result, TlvStore, UnlockedFlash};
+
+/// Open transaction of a `TlvStore`, returned by `TlvStore::begin`
+#[derive(Debug)]
+pub struct Transaction<'a> {
+    store: &'a mut TlvStore,
+    marker: usize,
+}
+
+impl<'a> Transaction<'a> {
+    pub(crate) fn new(store: &'a mut TlvStore, marker: usize) -> Self {
+        Transaction { store, marker }
+    }
+
+    /// Stage a record that supersedes the older records of `tag` once committed
+    pub fn set(&mut self, flash: &mut UnlockedFlash, tag: u16, value: &[u8]) -> Result {
+        self.store.append_record(flash, tag, value)
+    }
+
+    /// Make the staged records visible and invalidate the records they supersede
+    pub fn commit(self, flash: &mut UnlockedFlash) -> Result {
+        self.store.commit(flash, self.marker)
+    }
+
+    /// Discard the staged records. Dropping the transaction has the same effect, but the
+    /// records are only invalidated by the next append to the store.
+    pub fn roll_back(self, flash: &mut UnlockedFlash) -> Result {
+        self.store.roll_back(flash)
+    }
+}
@@ -0,0 +1,36 @@
+use core::mem;
+
+use bytemuck::Pod;
+
+use crate::{Error, Read, Result, UnlockedFlash, WriteErase, FLASH_START};
+
+impl UnlockedFlash {
+    /// Program `value` at `address` as its raw bytes.
+    ///
+    /// `address` must be aligned to a native write and the whole value must lie in main flash.
+    pub fn write_value<T: Pod>(&mut self, address: usize, value: &T) -> Result {
+        check_bounds::<T>(address, self.end_address())?;
+        if address % mem::align_of::<<Self as WriteErase>::NativeType>() != 0 {
+            return Err(Error::Unaligned);
+        }
+        self.write(address, bytemuck::bytes_of(value))
+    }
+
+    /// Read a `T` from `address`, which must be aligned for `T` and lie in main flash.
+    pub fn read_value<T: Pod>(&self, address: usize) -> core::result::Result<T, Error> {
+        check_bounds::<T>(address, self.end_address())?;
+        if address % mem::align_of::<T>() != 0 {
+            return Err(Error::Unaligned);
+        }
+        let mut value = T::zeroed();
+        self.read(address, bytemuck::bytes_of_mut(&mut value));
+        Ok(value)
+    }
+}
+
+fn check_bounds<T>(address: usize, flash_end: usize) -> Result {
+    match address.checked_add(mem::size_of::<T>()) {
+        Some(end) if address >= FLASH_START && end <= flash_end => Ok(()),
+        _ => Err(Error::OutOfBounds),
+    }
+}
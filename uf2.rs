@@ -0,0 +1,204 @@
+//! UF2 blocks, for drag and drop updates through a USB mass storage device.
+//!
+//! A UF2 file is a sequence of 512 byte blocks, little endian:
+//!
+//! ```text
+//! magic (u32) | magic (u32) | flags (u32) | address (u32) | payload size (u32) |
+//! block number (u32) | block count (u32) | family ID (u32) | payload (476 bytes) | magic (u32)
+//! ```
+//!
+//! The mass storage driver passes every written sector to `handle_block`, which skips sectors
+//! that aren't UF2 blocks, such as the FAT and directory entries the host writes along, and
+//! blocks for other families. Payloads are programmed through a `RecordWriter`, blocks that are
+//! seen again are skipped, and the file is complete once every block number was seen.
+
+use crate::{Error, RecordWriter, Region, Result, UnlockedFlash};
+
+/// Size of a UF2 block
+pub const UF2_BLOCK_LEN: usize = 512;
+/// UF2 family ID of the STM32F0
+pub const UF2_FAMILY_STM32F0: u32 = 0x6478_24b6;
+/// UF2 family ID of the STM32F1
+pub const UF2_FAMILY_STM32F1: u32 = 0x5ee2_1072;
+/// UF2 family ID of the STM32F3
+pub const UF2_FAMILY_STM32F3: u32 = 0x6b84_6188;
+
+const MAGIC_START0: u32 = 0x0a32_4655;
+const MAGIC_START1: u32 = 0x9e5d_5157;
+const MAGIC_END: u32 = 0x0ab1_6f30;
+const FLAG_NOT_MAIN_FLASH: u32 = 0x0000_0001;
+const FLAG_FAMILY_ID: u32 = 0x0000_2000;
+const MAX_PAYLOAD: usize = 476;
+/// Blocks `Uf2Writer` can keep track of
+const MAX_BLOCKS: usize = 1024;
+
+/// UF2 file being written into a region
+pub struct Uf2Writer {
+    writer: RecordWriter,
+    family_id: u32,
+    /// Block numbers seen so far, one bit each
+    seen: [u32; MAX_BLOCKS / 32],
+    num_blocks: u32,
+    received: u32,
+}
+
+impl Uf2Writer {
+    /// Accept blocks tagged with `family_id` for addresses inside `region`
+    pub fn new(region: Region, family_id: u32) -> core::result::Result<Self, Error> {
+        Ok(Uf2Writer {
+            writer: RecordWriter::new(region)?,
+            family_id,
+            seen: [0u32; MAX_BLOCKS / 32],
+            num_blocks: 0,
+            received: 0,
+        })
+    }
+
+    /// Program the payload of `block` if it is a UF2 block for this family.
+    ///
+    /// `Error::InvalidRecord` for a malformed block or one that doesn't belong to the file of the
+    /// earlier blocks, `Error::OutOfBounds` for a payload outside of the region.
+    pub fn handle_block(
+        &mut self,
+        flash: &mut UnlockedFlash,
+        block: &[u8; UF2_BLOCK_LEN],
+    ) -> Result {
+        let block = match parse_block(block, self.family_id)? {
+            Some(block) => block,
+            None => return Ok(()),
+        };
+        if self.num_blocks != 0 && block.count != self.num_blocks {
+            return Err(Error::InvalidRecord);
+        }
+        self.num_blocks = block.count;
+        let (index, bit) = (block.number as usize / 32, 1 << (block.number % 32));
+        if self.seen[index] & bit != 0 {
+            return Ok(());
+        }
+        self.writer.write(flash, block.address as usize, block.payload)?;
+        self.seen[index] |= bit;
+        self.received += 1;
+        Ok(())
+    }
+
+    /// Different blocks programmed so far
+    pub fn received(&self) -> u32 {
+        self.received
+    }
+
+    /// Blocks in the file, 0 before the first block
+    pub fn num_blocks(&self) -> u32 {
+        self.num_blocks
+    }
+
+    /// Whether every block of the file has been programmed
+    pub fn is_complete(&self) -> bool {
+        self.num_blocks != 0 && self.received == self.num_blocks
+    }
+
+    /// Program a byte still held back by an odd payload size
+    pub fn finish(mut self, flash: &mut UnlockedFlash) -> Result {
+        self.writer.finish(flash)
+    }
+}
+
+/// Fields of a UF2 block for this family
+#[derive(Debug)]
+struct Block<'a> {
+    address: u32,
+    payload: &'a [u8],
+    number: u32,
+    count: u32,
+}
+
+/// `None` for a sector that isn't a UF2 block for `family_id`, `Error::InvalidRecord` for a
+/// malformed block
+fn parse_block(
+    block: &[u8; UF2_BLOCK_LEN],
+    family_id: u32,
+) -> core::result::Result<Option<Block<'_>>, Error> {
+    let word = |index: usize| {
+        let start = index * 4;
+        u32::from_le_bytes([
+            block[start],
+            block[start + 1],
+            block[start + 2],
+            block[start + 3],
+        ])
+    };
+    if word(0) != MAGIC_START0 || word(1) != MAGIC_START1 || word(127) != MAGIC_END {
+        return Ok(None);
+    }
+    let flags = word(2);
+    if flags & FLAG_NOT_MAIN_FLASH != 0 || flags & FLAG_FAMILY_ID == 0 || word(7) != family_id {
+        return Ok(None);
+    }
+    let (address, len, number, count) = (word(3), word(4) as usize, word(5), word(6));
+    if len > MAX_PAYLOAD || count == 0 || count as usize > MAX_BLOCKS || number >= count {
+        return Err(Error::InvalidRecord);
+    }
+    Ok(Some(Block {
+        address,
+        payload: &block[32..32 + len],
+        number,
+        count,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(flags: u32, len: u32, number: u32, count: u32, family_id: u32) -> [u8; 512] {
+        let mut block = [0u8; UF2_BLOCK_LEN];
+        let words = [
+            MAGIC_START0,
+            MAGIC_START1,
+            flags,
+            0x0800_4000,
+            len,
+            number,
+            count,
+            family_id,
+        ];
+        for (chunk, word) in block.chunks_exact_mut(4).zip(words.iter()) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        block[32..32 + 4].copy_from_slice(&[1, 2, 3, 4]);
+        block[508..].copy_from_slice(&MAGIC_END.to_le_bytes());
+        block
+    }
+
+    #[test]
+    fn block_for_the_family_is_parsed() {
+        let block = block(FLAG_FAMILY_ID, 4, 1, 3, UF2_FAMILY_STM32F0);
+        let parsed = parse_block(&block, UF2_FAMILY_STM32F0).unwrap().unwrap();
+        assert_eq!(parsed.address, 0x0800_4000);
+        assert_eq!(parsed.payload, &[1, 2, 3, 4]);
+        assert_eq!((parsed.number, parsed.count), (1, 3));
+    }
+
+    #[test]
+    fn other_sectors_are_skipped() {
+        let other_family = block(FLAG_FAMILY_ID, 4, 0, 1, UF2_FAMILY_STM32F1);
+        assert!(parse_block(&other_family, UF2_FAMILY_STM32F0).unwrap().is_none());
+        let no_family = block(0, 4, 0, 1, UF2_FAMILY_STM32F0);
+        assert!(parse_block(&no_family, UF2_FAMILY_STM32F0).unwrap().is_none());
+        let not_main_flash = block(FLAG_FAMILY_ID | FLAG_NOT_MAIN_FLASH, 4, 0, 1, 0);
+        assert!(parse_block(&not_main_flash, 0).unwrap().is_none());
+        let mut fat = block(FLAG_FAMILY_ID, 4, 0, 1, UF2_FAMILY_STM32F0);
+        fat[508] ^= 1;
+        assert!(parse_block(&fat, UF2_FAMILY_STM32F0).unwrap().is_none());
+    }
+
+    #[test]
+    fn malformed_blocks_are_refused() {
+        for (len, number, count) in [(477, 0, 1), (4, 1, 1), (4, 0, 0), (4, 0, 1025)] {
+            let block = block(FLAG_FAMILY_ID, len, number, count, UF2_FAMILY_STM32F0);
+            assert!(matches!(
+                parse_block(&block, UF2_FAMILY_STM32F0),
+                Err(Error::InvalidRecord)
+            ));
+        }
+    }
+}
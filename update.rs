@@ -0,0 +1,66 @@
+use crate::{Error, FlashPage, Read, Result, UnlockedFlash, WriteErase, PAGE_SIZE};
+
+const ERASED_HALFWORD: u16 = 0xffff;
+
+impl UnlockedFlash {
+    /// Bring `page` to `contents`, which must be exactly one page long.
+    ///
+    /// The current page is compared halfword by halfword with the desired contents. If every
+    /// changed halfword is either still erased or is to be cleared to `0x0000`, only those
+    /// halfwords are programmed and the erase is skipped. Otherwise the page is erased first.
+    pub fn update_page(&mut self, page: FlashPage, contents: &[u8]) -> Result {
+        if page.0 >= self.num_pages() {
+            return Err(Error::PageOutOfRange);
+        }
+        if contents.len() != PAGE_SIZE as usize {
+            return Err(Error::InvalidLength);
+        }
+
+        let base = page.to_address();
+        let needs_erase = contents.chunks_exact(2).enumerate().any(|(i, chunk)| {
+            let current = self.read_halfword(base + 2 * i);
+            let desired = u16::from_ne_bytes([chunk[0], chunk[1]]);
+            current != desired && current != ERASED_HALFWORD && desired != 0
+        });
+        if needs_erase {
+            self.erase_page(page)?;
+        }
+
+        for (i, chunk) in contents.chunks_exact(2).enumerate() {
+            let address = base + 2 * i;
+            let desired = u16::from_ne_bytes([chunk[0], chunk[1]]);
+            if self.read_halfword(address) != desired {
+                self.write_native(address, &[desired])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Read-modify-write `data` at `address`, preserving the rest of every page it touches.
+    pub fn modify(&mut self, address: usize, data: &[u8]) -> Result {
+        if address < crate::FLASH_START {
+            return Err(Error::OutOfBounds);
+        }
+        let mut buf = [0u8; PAGE_SIZE as usize];
+        let mut address = address;
+        let mut data = data;
+
+        while !data.is_empty() {
+            let page = FlashPage::from_address(address);
+            let offset = address - page.to_address();
+            let len = core::cmp::min(data.len(), PAGE_SIZE as usize - offset);
+
+            self.read(page.to_address(), &mut buf);
+            buf[offset..offset + len].copy_from_slice(&data[..len]);
+            self.update_page(page, &buf)?;
+
+            address += len;
+            data = &data[len..];
+        }
+        Ok(())
+    }
+
+    pub(crate) fn read_halfword(&self, address: usize) -> u16 {
+        unsafe { (address as *const u16).read_volatile() }
+    }
+}
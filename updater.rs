@@ -0,0 +1,222 @@
+//! Over the air updates through any transport, resuming after a reset.
+//!
+//! `Updater` downloads an image in chunks from an `UpdateTransport` into the inactive slot of a
+//! `SlotManager` with an `ImageWriter`. The image is streamed as is, so it has to start with its
+//! header, see `image_header`. Every completed page is recorded in a `RingLog`:
+//!
+//! ```text
+//! length (u32) | crc (u32) | offset (u32) | slot (u8) | check (u8)
+//! ```
+//!
+//! so a download cut short by a reset continues with the page it was writing, as long as the
+//! transport offers the same image, told apart by its length and CRC, and the inactive slot is
+//! still the same. At the end the image is checked with `ImageWriter::commit` and the slot
+//! against the CRC-32 of the whole download before the image is marked pending.
+
+use crate::checksum::flash_crc32;
+use crate::{
+    Error, ImageWriter, Region, Result, RingLog, SignatureVerifier, SlotManager, UnlockedFlash,
+};
+
+const RECORD_LEN: usize = 14;
+const CHECK: u8 = 0xa5;
+/// Bytes asked from the transport at a time
+const CHUNK_LEN: usize = 256;
+
+/// Image offered by an `UpdateTransport`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct UpdateInfo {
+    /// Length of the download, header included
+    pub length: u32,
+    /// CRC-32 of the whole download
+    pub crc: u32,
+}
+
+/// Source of an update, such as an HTTP client or a radio link
+pub trait UpdateTransport {
+    /// The image to download
+    fn info(&mut self) -> core::result::Result<UpdateInfo, Error>;
+
+    /// Fill `buf` with the bytes of the image starting at `offset` and return how many there
+    /// were. Fewer than `buf.len()` are fine, none means the transfer broke off.
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> core::result::Result<usize, Error>;
+}
+
+/// Download in progress
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+struct Progress {
+    info: UpdateInfo,
+    offset: u32,
+    slot: u8,
+}
+
+impl Progress {
+    fn to_bytes(self) -> [u8; RECORD_LEN] {
+        let mut bytes = [0u8; RECORD_LEN];
+        bytes[..4].copy_from_slice(&self.info.length.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.info.crc.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.offset.to_le_bytes());
+        bytes[12] = self.slot;
+        bytes[13] = check(&bytes[..13]);
+        bytes
+    }
+
+    fn from_bytes(bytes: [u8; RECORD_LEN]) -> Option<Self> {
+        if bytes[13] != check(&bytes[..13]) {
+            return None;
+        }
+        let word =
+            |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        Some(Progress {
+            info: UpdateInfo {
+                length: word(0),
+                crc: word(4),
+            },
+            offset: word(8),
+            slot: bytes[12],
+        })
+    }
+}
+
+fn check(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(CHECK, |check, byte| check ^ byte)
+}
+
+/// Resumable download into the inactive slot
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Updater {
+    log: RingLog,
+    progress: Option<Progress>,
+}
+
+impl Updater {
+    /// Pick up the progress recorded in `state`
+    pub fn mount(flash: &mut UnlockedFlash, state: Region) -> core::result::Result<Self, Error> {
+        let log = RingLog::mount(flash, state)?;
+        let progress = log
+            .records(flash)
+            .filter_map(|record| {
+                let mut bytes = [0u8; RECORD_LEN];
+                if record.read(flash, &mut bytes) != RECORD_LEN {
+                    return None;
+                }
+                Progress::from_bytes(bytes)
+            })
+            .last();
+        Ok(Updater { log, progress })
+    }
+
+    /// Bytes of the image last downloaded that are safely in flash
+    pub fn resume_offset(&self) -> usize {
+        self.progress.map_or(0, |progress| progress.offset as usize)
+    }
+
+    /// Download the image `transport` offers, continuing where an earlier download of the same
+    /// image stopped, then mark it pending. Returns the length of the image.
+    ///
+    /// `Error::RegionFull` if the image doesn't fit the slot, `Error::Cancelled` if the
+    /// transport runs dry, `Error::CrcMismatch` if the slot doesn't hold the download
+    /// afterwards, in which case the next run starts over, and the errors of `commit` if the
+    /// download isn't the image its header describes. Errors of the transport are passed on.
+    pub fn run<V: SignatureVerifier>(
+        &mut self,
+        flash: &mut UnlockedFlash,
+        slots: &mut SlotManager<V>,
+        transport: &mut impl UpdateTransport,
+    ) -> core::result::Result<usize, Error> {
+        let info = transport.info()?;
+        let region = slots.region(slots.inactive());
+        let length = info.length as usize;
+        if length > region.len() {
+            return Err(Error::RegionFull);
+        }
+        let slot = slots.inactive() as u8;
+        let mut writer = match self.progress {
+            Some(progress) if progress.info == info && progress.slot == slot => {
+                ImageWriter::resume(flash, region, progress.offset as usize)?
+            }
+            _ => {
+                let writer = slots.stage(flash)?;
+                self.save(flash, Progress { info, offset: 0, slot })?;
+                writer
+            }
+        };
+
+        let page_len = region.len() / region.pages;
+        let mut buf = [0u8; CHUNK_LEN];
+        let mut offset = writer.written();
+        while offset < length {
+            let chunk = buf.len().min(length - offset);
+            let len = transport.read(offset, &mut buf[..chunk])?;
+            if len == 0 {
+                return Err(Error::Cancelled);
+            }
+            let len = len.min(chunk);
+            writer.write(flash, &buf[..len])?;
+            offset += len;
+            let committed = (offset / page_len * page_len) as u32;
+            if committed > self.resume_offset() as u32 {
+                self.save(
+                    flash,
+                    Progress {
+                        info,
+                        offset: committed,
+                        slot,
+                    },
+                )?;
+            }
+        }
+        writer.commit(flash)?;
+
+        if flash_crc32(flash, region.start_address(), length) != info.crc {
+            self.save(flash, Progress { info, offset: 0, slot })?;
+            return Err(Error::CrcMismatch);
+        }
+        slots.mark_pending(flash)?;
+        Ok(length)
+    }
+
+    fn save(&mut self, flash: &mut UnlockedFlash, progress: Progress) -> Result {
+        self.log.append(flash, &progress.to_bytes())?;
+        self.progress = Some(progress);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress() -> Progress {
+        Progress {
+            info: UpdateInfo {
+                length: 0x0001_2345,
+                crc: 0xdead_beef,
+            },
+            offset: 0x0000_c000,
+            slot: 1,
+        }
+    }
+
+    #[test]
+    fn progress_round_trip() {
+        assert_eq!(Progress::from_bytes(progress().to_bytes()), Some(progress()));
+    }
+
+    #[test]
+    fn damaged_progress_is_skipped() {
+        for index in 0..RECORD_LEN {
+            let mut bytes = progress().to_bytes();
+            bytes[index] ^= 0x10;
+            assert_eq!(Progress::from_bytes(bytes), None);
+        }
+    }
+
+    #[test]
+    fn erased_record_is_no_progress() {
+        assert_eq!(Progress::from_bytes([crate::ERASED_BYTE; RECORD_LEN]), None);
+    }
+}
@@ -0,0 +1,326 @@
+//! Wear leveling over a pool of pages.
+//!
+//! Every logical block lives in one physical page of the pool, behind a 16 byte header:
+//!
+//! ```text
+//! 0: block (u16) | 2: commit (u16) | 4: erase count (u32) | 8: sequence (u32) | 12: bad (u16)
+//! ```
+//!
+//! A write programs the new contents into the least worn free page, commits it by clearing the
+//! commit halfword last and only then recycles the page of the previous copy. Recycling erases
+//! the page and programs its incremented erase count straight back, so the counts survive in
+//! the pages themselves. A fresh pool gets its counts on the first mount.
+//!
+//! A page that fails to erase, or to program despite the retries of the controller, is marked
+//! bad by clearing its bad halfword, and added to the `BadPages` table if the leveler was
+//! mounted with one, and is left alone from then on. Pages already in the table are skipped
+//! too. The blocks keep working as long as the pool has a good spare page left.
+
+use crate::bad_pages::is_page_failure;
+use crate::{
+    BadPages, Error, Read, Region, Result, UnlockedFlash, WriteErase, ERASED_BYTE, PAGE_SIZE,
+};
+
+const HEADER_LEN: usize = 16;
+const BLOCK: usize = 0;
+const COMMIT: usize = 2;
+const ERASES: usize = 4;
+const SEQUENCE: usize = 8;
+const BAD: usize = 12;
+
+const FREE: u16 = 0xffff;
+const COMMITTED: u16 = 0x0000;
+const BAD_PAGE: u16 = 0x0000;
+
+/// Storage of fixed size logical blocks, the interface the storage layers can be stacked on.
+pub trait BlockStorage {
+    /// Size of every block in bytes
+    fn block_size(&self) -> usize;
+
+    fn block_count(&self) -> usize;
+
+    /// Read the start of `block` into `buf`. Blocks that were never written read as erased.
+    fn read_block(&self, block: usize, buf: &mut [u8]) -> Result;
+
+    /// Replace the contents of `block` with `data`, the rest of the block reads as erased
+    fn write_block(&mut self, block: usize, data: &[u8]) -> Result;
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Header {
+    block: u16,
+    committed: bool,
+    erases: u32,
+    sequence: u32,
+    bad: bool,
+}
+
+/// `blocks` logical blocks spread over the pages of `pool`, which needs at least one spare
+/// page.
+pub struct WearLeveler {
+    flash: UnlockedFlash,
+    pool: Region,
+    blocks: usize,
+    sequence: u32,
+    bad_pages: Option<BadPages>,
+}
+
+impl WearLeveler {
+    /// Block size of every `WearLeveler`
+    pub const BLOCK_SIZE: usize = PAGE_SIZE as usize - HEADER_LEN;
+
+    /// Pick up the blocks stored in `pool`, cleaning up after a write that was interrupted.
+    pub fn mount(
+        flash: UnlockedFlash,
+        pool: Region,
+        blocks: usize,
+    ) -> core::result::Result<Self, Error> {
+        Self::mount_inner(flash, pool, blocks, None)
+    }
+
+    /// `mount` that also adds the pages that fail to `bad_pages`, which has to be mounted on
+    /// `flash`
+    pub fn mount_with_bad_pages(
+        flash: UnlockedFlash,
+        pool: Region,
+        blocks: usize,
+        bad_pages: BadPages,
+    ) -> core::result::Result<Self, Error> {
+        Self::mount_inner(flash, pool, blocks, Some(bad_pages))
+    }
+
+    fn mount_inner(
+        flash: UnlockedFlash,
+        pool: Region,
+        blocks: usize,
+        bad_pages: Option<BadPages>,
+    ) -> core::result::Result<Self, Error> {
+        if blocks >= flash.good_pages(&pool) || blocks >= FREE as usize {
+            return Err(Error::InvalidLength);
+        }
+        let mut leveler = WearLeveler {
+            flash,
+            pool,
+            blocks,
+            sequence: 0,
+            bad_pages,
+        };
+
+        for page in 0..pool.pages {
+            let header = leveler.header(page);
+            if header.committed && !header.bad {
+                leveler.sequence = leveler.sequence.max(header.sequence.wrapping_add(1));
+            }
+        }
+
+        for page in 0..pool.pages {
+            let header = leveler.header(page);
+            let stale = if header.bad {
+                false
+            } else if header.committed {
+                // An interrupted write leaves the old copy of the block behind
+                header.block as usize >= blocks
+                    || leveler
+                        .copies(header.block)
+                        .any(|(_, other)| other.sequence > header.sequence)
+            } else {
+                header.erases == u32::MAX || !leveler.is_clean(page)
+            };
+            if stale {
+                leveler.recycle(page)?;
+            }
+        }
+        Ok(leveler)
+    }
+
+    /// Erase count of the `page`th page of the pool
+    pub fn erase_count(&self, page: usize) -> u32 {
+        self.header(page).erases
+    }
+
+    /// Number of pages of the pool marked bad
+    pub fn bad_pages(&self) -> usize {
+        (0..self.pool.pages)
+            .filter(|page| self.header(*page).bad)
+            .count()
+    }
+
+    pub fn into_inner(self) -> UnlockedFlash {
+        self.flash
+    }
+
+    /// The flash and the bad page table passed to `mount_with_bad_pages`
+    pub fn into_parts(self) -> (UnlockedFlash, Option<BadPages>) {
+        (self.flash, self.bad_pages)
+    }
+
+    fn header(&self, page: usize) -> Header {
+        let mut raw = [0u8; HEADER_LEN];
+        self.flash.read(self.pool.page(page).to_address(), &mut raw);
+        let halfword = |at: usize| u16::from_ne_bytes([raw[at], raw[at + 1]]);
+        let word = |at: usize| {
+            u32::from_ne_bytes([raw[at], raw[at + 1], raw[at + 2], raw[at + 3]])
+        };
+        Header {
+            block: halfword(BLOCK),
+            committed: halfword(COMMIT) == COMMITTED,
+            erases: word(ERASES),
+            sequence: word(SEQUENCE),
+            bad: halfword(BAD) == BAD_PAGE || self.flash.is_bad_page(self.pool.page(page)),
+        }
+    }
+
+    /// Committed pages holding `block`, with their headers
+    fn copies(&self, block: u16) -> impl Iterator<Item = (usize, Header)> + '_ {
+        (0..self.pool.pages)
+            .map(move |page| (page, self.header(page)))
+            .filter(move |(_, header)| header.committed && !header.bad && header.block == block)
+    }
+
+    /// Committed page with the newest copy of `block`. Older copies are left behind by a write
+    /// interrupted before it recycled them.
+    fn newest(&self, block: u16) -> Option<usize> {
+        self.copies(block)
+            .max_by_key(|(_, header)| header.sequence)
+            .map(|(page, _)| page)
+    }
+
+    /// Whether everything but the erase count of `page` is erased
+    fn is_clean(&self, page: usize) -> bool {
+        let start = self.pool.page(page).to_address();
+        let mut buf = [0u8; 64];
+        let mut head = [0u8; ERASES];
+        self.flash.read(start, &mut head);
+        if head.iter().any(|b| *b != ERASED_BYTE) {
+            return false;
+        }
+        for address in (start + SEQUENCE..start + PAGE_SIZE as usize).step_by(buf.len()) {
+            let len = buf.len().min(start + PAGE_SIZE as usize - address);
+            self.flash.read(address, &mut buf[..len]);
+            if buf[..len].iter().any(|b| *b != ERASED_BYTE) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Erase `page` and program its erase count back, marking it bad if that fails
+    fn recycle(&mut self, page: usize) -> Result {
+        let erases = match self.header(page).erases {
+            // Count lost to a reset during an earlier recycle, assume the worst
+            u32::MAX => (0..self.pool.pages)
+                .map(|page| self.header(page).erases)
+                .filter(|erases| *erases != u32::MAX)
+                .max()
+                .unwrap_or(0),
+            erases => erases,
+        };
+        let address = self.pool.page(page).to_address();
+        let result = self.flash.erase_page(self.pool.page(page)).and_then(|()| {
+            self.write_word(address + ERASES, erases.saturating_add(1).min(u32::MAX - 1))
+        });
+        match result {
+            Err(error) if is_page_failure(error) => self.mark_bad(page),
+            result => result,
+        }
+    }
+
+    /// Mark `page` bad in its header and in the bad page table, if there is one
+    fn mark_bad(&mut self, page: usize) -> Result {
+        let page = self.pool.page(page);
+        if let Some(bad_pages) = &mut self.bad_pages {
+            bad_pages.mark_bad(&mut self.flash, page)?;
+        }
+        match self.flash.write_native(page.to_address() + BAD, &[BAD_PAGE]) {
+            // The table keeps the page out of use already
+            Err(error) if is_page_failure(error) && self.bad_pages.is_some() => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Program `data` and the header of `block` into the free `page`, committing it last
+    fn program(&mut self, page: usize, block: u16, data: &[u8]) -> Result {
+        let address = self.pool.page(page).to_address();
+        if !data.is_empty() {
+            self.flash.write(address + HEADER_LEN, data)?;
+        }
+        self.write_word(address + SEQUENCE, self.sequence)?;
+        self.flash.write_native(address + BLOCK, &[block])?;
+        self.flash.write_native(address + COMMIT, &[COMMITTED])
+    }
+
+    fn write_word(&mut self, address: usize, word: u32) -> Result {
+        let bytes = word.to_ne_bytes();
+        self.flash.write_native(
+            address,
+            &[
+                u16::from_ne_bytes([bytes[0], bytes[1]]),
+                u16::from_ne_bytes([bytes[2], bytes[3]]),
+            ],
+        )
+    }
+}
+
+impl BlockStorage for WearLeveler {
+    fn block_size(&self) -> usize {
+        Self::BLOCK_SIZE
+    }
+
+    fn block_count(&self) -> usize {
+        self.blocks
+    }
+
+    fn read_block(&self, block: usize, buf: &mut [u8]) -> Result {
+        if block >= self.blocks {
+            return Err(Error::OutOfBounds);
+        }
+        if buf.len() > Self::BLOCK_SIZE {
+            return Err(Error::InvalidLength);
+        }
+        match self.newest(block as u16) {
+            Some(page) => {
+                let address = self.pool.page(page).to_address() + HEADER_LEN;
+                self.flash.read(address, buf);
+            }
+            None => buf.fill(ERASED_BYTE),
+        }
+        Ok(())
+    }
+
+    fn write_block(&mut self, block: usize, data: &[u8]) -> Result {
+        if block >= self.blocks {
+            return Err(Error::OutOfBounds);
+        }
+        if data.len() > Self::BLOCK_SIZE {
+            return Err(Error::InvalidLength);
+        }
+        let target = loop {
+            let target = (0..self.pool.pages)
+                .map(|page| (page, self.header(page)))
+                .filter(|(_, header)| header.block == FREE && !header.committed && !header.bad)
+                .min_by_key(|(_, header)| header.erases)
+                .map(|(page, _)| page)
+                .ok_or(Error::RegionFull)?;
+
+            match self.program(target, block as u16, data) {
+                Ok(()) => break target,
+                // Try the next free page, the failed one is never used again
+                Err(error) if is_page_failure(error) => self.mark_bad(target)?,
+                Err(error) => return Err(error),
+            }
+        };
+        self.sequence = self.sequence.wrapping_add(1);
+
+        // Recycling either erases a copy or marks its page bad, both drop it from `copies`
+        loop {
+            let stale = self
+                .copies(block as u16)
+                .map(|(page, _)| page)
+                .find(|page| *page != target);
+            match stale {
+                Some(page) => self.recycle(page)?,
+                None => return Ok(()),
+            }
+        }
+    }
+}
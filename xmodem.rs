@@ -0,0 +1,292 @@
+//! XMODEM-1K and YMODEM receiver streaming into flash, for updates over a plain UART.
+//!
+//! Packets are `SOH` with 128 or `STX` with 1024 data bytes:
+//!
+//! ```text
+//! SOH/STX | block | !block | data | crc (u16, big endian)
+//! ```
+//!
+//! with the CRC-16/XMODEM of the data. The receiver asks for CRC mode with `C`, acknowledges
+//! every good packet after its data is programmed and asks for a bad one again with `NAK`.
+//! XMODEM data starts with block 1 and its last block is padded with `0x1a`, which is written
+//! as well. YMODEM starts with block 0 holding the file name and size, so the padding is cut
+//! off, and ends the batch with an empty block 0. Only one file per batch is taken.
+//!
+//! `feed` is the push interface, the driver sends the bytes it returns. `run` drives a
+//! `ByteTransport` until the transfer is over. At the end the data in flash is checked against
+//! a CRC-32 of what was received.
+
+use crate::checksum::flash_crc32;
+use crate::{Checksum, Crc32, Error, ImageWriter, UnlockedFlash};
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+const CRC_MODE: u8 = b'C';
+
+/// Bytes that cancel the transfer on the other side
+pub const CANCEL: &[u8] = &[CAN, CAN];
+
+/// Byte stream to the sender
+pub trait ByteTransport {
+    /// Next received byte, `None` once the transport's timeout of about a second expired
+    fn read_byte(&mut self) -> Option<u8>;
+
+    fn write(&mut self, bytes: &[u8]);
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ModemProtocol {
+    /// XMODEM with CRC and 1 KB packets
+    Xmodem,
+    Ymodem,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+enum State {
+    /// Between packets
+    Idle,
+    /// Inside a packet of this many bytes, counting everything but the start byte
+    Packet(usize),
+    /// The file has been received, waiting for the empty YMODEM header ending the batch
+    BatchEnd,
+    Done,
+}
+
+/// Receiver writing one file with an `ImageWriter`
+pub struct ModemReceiver {
+    writer: ImageWriter,
+    protocol: ModemProtocol,
+    state: State,
+    packet: [u8; 2 + 1024 + 2],
+    len: usize,
+    /// Block number of the next packet
+    block: u8,
+    /// File size from the YMODEM header
+    size: Option<usize>,
+    received: usize,
+    crc: Crc32,
+}
+
+impl ModemReceiver {
+    pub fn new(writer: ImageWriter, protocol: ModemProtocol) -> Self {
+        ModemReceiver {
+            writer,
+            protocol,
+            state: State::Idle,
+            packet: [0u8; 2 + 1024 + 2],
+            len: 0,
+            block: match protocol {
+                ModemProtocol::Xmodem => 1,
+                ModemProtocol::Ymodem => 0,
+            },
+            size: None,
+            received: 0,
+            crc: Crc32::new(),
+        }
+    }
+
+    /// What to send to start the transfer, and again after every second without a packet
+    pub fn start(&self) -> &'static [u8] {
+        &[CRC_MODE]
+    }
+
+    /// Whether the transfer is over and `finish` can be called
+    pub fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    /// Bytes of the file received so far
+    pub fn received(&self) -> usize {
+        self.received
+    }
+
+    /// Take the next byte from the sender and return the bytes to send back.
+    ///
+    /// `Error::Cancelled` if the sender cancels, `Error::InvalidRecord` for packets out of
+    /// order. On these and on flash errors send `CANCEL`.
+    pub fn feed(
+        &mut self,
+        flash: &mut UnlockedFlash,
+        byte: u8,
+    ) -> core::result::Result<&'static [u8], Error> {
+        match self.state {
+            State::Idle | State::BatchEnd => match byte {
+                SOH => self.state = State::Packet(2 + 128 + 2),
+                STX => self.state = State::Packet(2 + 1024 + 2),
+                EOT if self.received > 0 => {
+                    return Ok(match self.protocol {
+                        ModemProtocol::Xmodem => {
+                            self.state = State::Done;
+                            &[ACK]
+                        }
+                        ModemProtocol::Ymodem => {
+                            self.state = State::BatchEnd;
+                            self.block = 0;
+                            &[ACK, CRC_MODE]
+                        }
+                    });
+                }
+                CAN => return Err(Error::Cancelled),
+                _ => {}
+            },
+            State::Packet(len) => {
+                self.packet[self.len] = byte;
+                self.len += 1;
+                if self.len == len {
+                    self.len = 0;
+                    return self.handle_packet(flash, len);
+                }
+            }
+            State::Done => {}
+        }
+        Ok(&[])
+    }
+
+    /// Drive `transport` until the transfer is over, then `finish`. Gives up with
+    /// `Error::Cancelled` after `retries` timeouts in a row.
+    pub fn run(
+        mut self,
+        flash: &mut UnlockedFlash,
+        transport: &mut impl ByteTransport,
+        retries: u8,
+    ) -> core::result::Result<usize, Error> {
+        transport.write(self.start());
+        let mut timeouts = 0;
+        while !self.is_done() {
+            let byte = match transport.read_byte() {
+                Some(byte) => byte,
+                None => {
+                    timeouts += 1;
+                    if timeouts > retries {
+                        transport.write(CANCEL);
+                        return Err(Error::Cancelled);
+                    }
+                    // Drop a partial packet and have it sent again
+                    if let State::Packet(_) = self.state {
+                        self.state = State::Idle;
+                        self.len = 0;
+                        transport.write(&[NAK]);
+                    } else if self.received == 0 || self.state == State::BatchEnd {
+                        transport.write(self.start());
+                    }
+                    continue;
+                }
+            };
+            timeouts = 0;
+            match self.feed(flash, byte) {
+                Ok(reply) => transport.write(reply),
+                Err(error) => {
+                    transport.write(CANCEL);
+                    return Err(error);
+                }
+            }
+        }
+        self.finish(flash)
+    }
+
+    /// Finish the image and check it against the CRC-32 of the received data, returning its
+    /// length. `Error::CrcMismatch` if flash doesn't hold what was received.
+    pub fn finish(self, flash: &mut UnlockedFlash) -> core::result::Result<usize, Error> {
+        let start = self.writer.region().start_address();
+        let len = self.writer.finish(flash)?;
+        if flash_crc32(flash, start, len) != self.crc.finish() {
+            return Err(Error::CrcMismatch);
+        }
+        Ok(len)
+    }
+
+    /// Handle a complete packet of `len` bytes after the start byte
+    fn handle_packet(
+        &mut self,
+        flash: &mut UnlockedFlash,
+        len: usize,
+    ) -> core::result::Result<&'static [u8], Error> {
+        let batch_end = self.state == State::BatchEnd;
+        self.state = State::Idle;
+        let (block, data, crc) = (
+            self.packet[0],
+            &self.packet[2..len - 2],
+            u16::from_be_bytes([self.packet[len - 2], self.packet[len - 1]]),
+        );
+        if block != !self.packet[1] || crc16(data) != crc {
+            if batch_end {
+                self.state = State::BatchEnd;
+            }
+            return Ok(&[NAK]);
+        }
+        if batch_end {
+            // Only an empty header may follow, one file per batch
+            if block != 0 || data[0] != 0 {
+                return Err(Error::InvalidRecord);
+            }
+            self.state = State::Done;
+            return Ok(&[ACK]);
+        }
+        // The sender didn't see the last ACK
+        if block == self.block.wrapping_sub(1) {
+            return Ok(&[ACK]);
+        }
+        if block != self.block {
+            return Err(Error::InvalidRecord);
+        }
+
+        if self.protocol == ModemProtocol::Ymodem && self.size.is_none() {
+            // An empty header ends an empty batch
+            if data[0] == 0 {
+                self.state = State::Done;
+                return Ok(&[ACK]);
+            }
+            self.size = Some(parse_size(data)?);
+            self.block = 1;
+            return Ok(&[ACK, CRC_MODE]);
+        }
+
+        let take = match self.size {
+            Some(size) => data.len().min(size - self.received),
+            None => data.len(),
+        };
+        self.writer.write(flash, &data[..take])?;
+        self.crc.update(&data[..take]);
+        self.received += take;
+        self.block = self.block.wrapping_add(1);
+        Ok(&[ACK])
+    }
+}
+
+/// Size field of a YMODEM header: the name, a NUL and the decimal size
+fn parse_size(data: &[u8]) -> core::result::Result<usize, Error> {
+    let name_end = data
+        .iter()
+        .position(|byte| *byte == 0)
+        .ok_or(Error::InvalidRecord)?;
+    let mut size = 0usize;
+    for &byte in data[name_end + 1..].iter().take_while(|byte| byte.is_ascii_digit()) {
+        size = size
+            .checked_mul(10)
+            .and_then(|size| size.checked_add((byte - b'0') as usize))
+            .ok_or(Error::InvalidRecord)?;
+    }
+    Ok(size)
+}
+
+/// CRC-16/XMODEM
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}